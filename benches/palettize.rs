@@ -0,0 +1,43 @@
+//! Measures how long it takes to map true-colour pixels to the palette, with the images mapped
+//! one at a time and in parallel, where the threads share the cache of mapped colours.
+//! Run with `cargo bench`.
+
+use irongrp::palpng::{palettize_rgba_pixels, ColourMapping, PalettizedImageWithMetadata, TrimEdges};
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+
+const IMAGE_COUNT: usize = 64;
+const IMAGE_SIZE:  u32   = 128;
+const ITERATIONS:  u32   = 5;
+
+fn main() -> std::io::Result<()> {
+    let palette: Vec<[u8; 3]> = (0..=255u8).map(|i| [i, i.wrapping_mul(7), 255 - i]).collect();
+    let images: Vec<Vec<u8>> = (0..IMAGE_COUNT as u32)
+        .map(|i| {
+            (0..IMAGE_SIZE * IMAGE_SIZE)
+                .flat_map(|p| [(p * 31 + i) as u8, (p * 17) as u8, (p / 97 + i * 3) as u8, 255])
+                .collect()
+        })
+        .collect();
+    let palettize = |pixels: &Vec<u8>| -> std::io::Result<PalettizedImageWithMetadata<u32, u32>> {
+        palettize_rgba_pixels(pixels, IMAGE_SIZE, IMAGE_SIZE, true, &palette, TrimEdges::ALL, ColourMapping::default())
+    };
+
+    for parallel in [false, true] {
+        let mut total = Duration::ZERO;
+        for _ in 0..ITERATIONS {
+            let start = Instant::now();
+            if parallel {
+                images.par_iter().map(palettize).collect::<std::io::Result<Vec<_>>>()?;
+            } else {
+                images.iter().map(palettize).collect::<std::io::Result<Vec<_>>>()?;
+            }
+            total += start.elapsed();
+        }
+        println!(
+            "Palettizing {} images of {}x{} pixels, parallel: {:5} — {:?} per iteration",
+            IMAGE_COUNT, IMAGE_SIZE, IMAGE_SIZE, parallel, total / ITERATIONS,
+        );
+    }
+    Ok(())
+}
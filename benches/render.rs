@@ -0,0 +1,55 @@
+//! Measures how long it takes to render and save the frames of a GRP as separate PNGs,
+//! with and without reporting frames that have duplicated content.
+//! Run with `cargo bench`.
+
+use clap::Parser;
+use irongrp::grp::frames_from_buffers;
+use irongrp::palpng::greyscale_palette;
+use irongrp::png::render_and_save_frames_to_png;
+use irongrp::{Args, CompressionType};
+use std::time::{Duration, Instant};
+
+const FRAME_COUNT: usize = 400;
+const FRAME_SIZE:  u32   = 96;
+const ITERATIONS:  u32   = 5;
+
+fn main() -> std::io::Result<()> {
+    let palette = greyscale_palette()?;
+    let buffers = (0..FRAME_COUNT)
+        .map(|i| {
+            let pixels = (0..FRAME_SIZE * FRAME_SIZE)
+                .flat_map(|p| {
+                    let value = ((p + i as u32 % 50) % 251) as u8;
+                    [value, value, value, 255]
+                })
+                .collect();
+            (pixels, FRAME_SIZE, FRAME_SIZE)
+        })
+        .collect();
+    let frames = frames_from_buffers(buffers, &palette, &CompressionType::Normal)?;
+
+    let output_dir = std::env::temp_dir().join("irongrp_render_bench");
+    std::fs::create_dir_all(&output_dir)?;
+    let output_path = output_dir.display().to_string();
+
+    for report_content_duplicates in [false, true] {
+        let mut command_line = vec!["irongrp", "--log-level", "error", "--output-path", &output_path];
+        if report_content_duplicates {
+            command_line.push("--report-content-duplicates");
+        }
+        let args = Args::parse_from(command_line);
+
+        let mut total = Duration::ZERO;
+        for _ in 0..ITERATIONS {
+            let start = Instant::now();
+            render_and_save_frames_to_png(&frames, &palette, FRAME_SIZE, FRAME_SIZE, None, &mut Vec::new(), &args)?;
+            total += start.elapsed();
+        }
+        println!(
+            "Rendering {} frames of {}x{} pixels, report-content-duplicates: {:5} — {:?} per iteration",
+            FRAME_COUNT, FRAME_SIZE, FRAME_SIZE, report_content_duplicates, total / ITERATIONS,
+        );
+    }
+
+    std::fs::remove_dir_all(&output_dir)
+}
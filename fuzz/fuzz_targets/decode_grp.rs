@@ -0,0 +1,18 @@
+#![no_main]
+
+use irongrp::grp::{decode_grp_checked, read_grp_frames, read_grp_header, GrpReadOptions, GrpType};
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_grp_checked(data);
+
+    // Decode the frames as every type too, since the type detection would otherwise
+    // only let through data that looks like the detected type
+    for grp_type in [GrpType::Normal, GrpType::Uncompressed, GrpType::War1] {
+        let mut cursor = Cursor::new(data);
+        if let Ok((header, _)) = read_grp_header(&mut cursor) {
+            let _ = read_grp_frames(&mut cursor, header.frame_count, grp_type, &GrpReadOptions::default());
+        }
+    }
+});
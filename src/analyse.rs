@@ -1,9 +1,12 @@
-use crate::grp::{detect_uncompressed, read_grp_frames, read_grp_header, GrpType, EXTENDED_IMAGE_WIDTH};
-use crate::{Args, LogLevel, LOG_LEVEL};
-use log::{debug, error, info, warn};
+use crate::grp::{check_grp_limits, get_header_size, read_frame_headers, read_grp_file, read_grp_frames, read_grp_header_and_type, smallest_rle_encoding, FrameHeader, GrpFrame, GrpHeader, GrpLimits, GrpReadOptions, GrpType, EXTENDED_IMAGE_WIDTH, EXTENDED_OFFSET_BIT};
+use crate::palette::EMBEDDED_PALETTE_SIZE;
+use crate::png::draw_on_canvas;
+use crate::{list_grp_files, AnalyseFormat, Args};
+use log::{debug, error, info, log_enabled, warn, Level};
+use serde::Serialize;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
 use std::io::{Read, Seek, SeekFrom};
 
@@ -14,87 +17,34 @@ pub fn analyse_grp(args: &Args) -> std::io::Result<()> {
     let mut file = File::open(input_path)?;
     let file_len = file.metadata()?.len();
 
-    let (header, war1_style) = read_grp_header(&mut file)?;
-    let is_uncompressed = detect_uncompressed(input_path, &header, war1_style)?;
+    let (header, war1_style, grp_type) = read_grp_header_and_type(&mut file, input_path, args.force_grp_type)?;
+    let is_uncompressed = grp_type != GrpType::Normal;
+    check_grp_limits(&mut file, &header, grp_type, &GrpLimits::from(args))?;
+    let frames = read_grp_frames(&mut file, header.frame_count, grp_type, &GrpReadOptions::from(args))?;
 
-    let grp_type = if is_uncompressed && war1_style {
-        GrpType::War1
-    } else if is_uncompressed {
-        GrpType::Uncompressed
-    } else {
-        GrpType::Normal
-    };
-    let frames = read_grp_frames(&mut file, header.frame_count, grp_type)?;
+    if args.analyse_format == AnalyseFormat::Json {
+        let report = analyse_report(&mut file, &header, war1_style, grp_type, &frames, args.embedded_palette)?;
+        println!("{}", serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?);
+        return Ok(());
+    }
 
     println!();
     info!("GRP type: {:?}", grp_type);
 
-    if args.frame_number.is_some() {
-        let frame_number = args.frame_number.unwrap() as usize;
-        if  frame_number > frames.len() {
-            error!("Frame number {} is out of range (0-{})", frame_number, frames.len() - 1);
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
-        }
-        let row_number = if args.analyse_row_number.is_none() || is_uncompressed {
-            frames[frame_number].height + 1
-        } else {
-            args.analyse_row_number.unwrap()
-        };
-        if row_number > frames[frame_number].height && args.analyse_row_number.is_some() {
-            error!("Row number {} is out of range (0-{})", row_number, frames[frame_number].height);
+    if let Some(frame_number) = args.frame_number {
+        let frame_number = frame_number as usize;
+        if frame_number >= frames.len() {
+            error!("Frame number {} is out of range (0-{})", frame_number, frames.len() as isize - 1);
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
         }
-
-        let width = if frames[frame_number].image_data.grp_type != GrpType::UncompressedExtended {
-            frames[frame_number].width as u16
-        } else {
-            frames[frame_number].width as u16 + EXTENDED_IMAGE_WIDTH
-        };
-        let next_offset = if frame_number + 1 < frames.len() {
-            frames[frame_number + 1].image_data_offset
-        } else {
-            file_len as u32
-        };
-        info!("Analyzing frame {}:", frame_number);
-        info!("- GrpType:  {:?}", frames[frame_number].image_data.grp_type);
-        info!("- X offset: {}", frames[frame_number].x_offset);
-        info!("- Y offset: {}", frames[frame_number].y_offset);
-        info!("- Width:    {}", width);
-        info!("- Height:   {}", frames[frame_number].height);
-        info!("- This frames image data offset: 0x{:0>2X}", frames[frame_number].image_data_offset);
-        info!("- Next frames image data offset: 0x{:0>2X}", next_offset);
-        if frames[frame_number].image_data.grp_type == GrpType::Normal {
-            for (i, _) in frames[frame_number].image_data.raw_row_data.iter().enumerate() {
-                info!(
-                    "- Row {: >2} (0x{:0>2X}), Relative offset: 0x{:0>4X}, Absolute offset: 0x{:0>6X}",
-                    i, i, frames[frame_number].image_data.row_offsets[i],
-                    frames[frame_number].image_data.row_offsets[i] + frames[frame_number].image_data_offset as u16,
-                );
-            }
-        }
-        if args.analyse_row_number.is_some() && frames[frame_number].image_data.grp_type == GrpType::Normal {
-            for (i, row) in frames[frame_number].image_data.raw_row_data.iter().enumerate() {
-                if row_number == i as u8 {
-                    let start = frames[frame_number].image_data_offset as u64 + frames[frame_number].image_data.row_offsets[i] as u64;
-                    println!();
-                    info!(
-                        "- Row {: >2} (0x{:0>2X}), Relative offset: 0x{:X}, Absolute offset: 0x{:X}",
-                        i, i, frames[frame_number].image_data.row_offsets[i], start,
-                    );
-
-                    let mut bytes = "".to_string();
-                    let mut buf = vec![0u8; row.len()];
-                    file.seek(SeekFrom::Start(start))?;
-                    file.read_exact(&mut buf)?;
-                    for b in &buf {
-                        bytes.push_str(&format!("{:02X} ", b));
-                    }
-                    info!("  Data ({} bytes): {}", row.len(), &bytes);
-                    break;
-                }
-            }
+        return analyse_frame(&mut file, &frames, frame_number, file_len, is_uncompressed, args);
+    }
+    if let Some(frame_range) = args.frame_range {
+        frame_range.check_in_bounds(frames.len())?;
+        for frame_number in frame_range.start as usize..=frame_range.end as usize {
+            analyse_frame(&mut file, &frames, frame_number, file_len, is_uncompressed, args)?;
+            println!();
         }
-
         return Ok(());
     }
     println!();
@@ -102,22 +52,14 @@ pub fn analyse_grp(args: &Args) -> std::io::Result<()> {
     info!("- Frame count: {}", header.frame_count);
     info!("- Max width:   {}", header.max_width);
     info!("- Max height:  {}", header.max_height);
-
-    let mut actual_max_width  = 0;
-    let mut actual_max_height = 0;
-
-    for frame in &frames {
-        let width = if frame.image_data.grp_type != GrpType::UncompressedExtended {
-            frame.width as u16
-        } else {
-            frame.width as u16 + EXTENDED_IMAGE_WIDTH
-        };
-        let right  = frame.x_offset as u16 + width;
-        let bottom = frame.y_offset as u16 + frame.height as u16;
-        actual_max_width  = actual_max_width .max(right);
-        actual_max_height = actual_max_height.max(bottom);
+    info!("- File size:   {} bytes", file_len);
+    info!("- CRC32:       {:08x}", crc32fast::hash(&fs::read(input_path)?));
+    if args.transparent_index != 0 {
+        info!("Treating palette index {} as transparent", args.transparent_index);
     }
 
+    let (actual_max_width, actual_max_height) = actual_max_dimensions(&frames);
+
     if actual_max_width > header.max_width || actual_max_height > header.max_height {
         warn!("⚠ Header max dimensions are less than the actual frame extents!");
         warn!("- Actual max width:  {}", actual_max_width);
@@ -128,116 +70,102 @@ pub fn analyse_grp(args: &Args) -> std::io::Result<()> {
     println!();
 
     // Analyze for gaps
-    let mut used_ranges: Vec<(u64, u64, String)> = Vec::new();
-    used_ranges.push((0, 6, format!("GRP Header ({} frames)", frames.len())));
-    used_ranges.push((6, 6 + (frames.len() * 8) as u64, "Frame headers".to_string()));
-
-    for (frame_index, frame) in frames.iter().enumerate() {
-        let data_offset = frame.image_data_offset as u64;
-        let row_table_end = data_offset + (frame.image_data.row_offsets.len() * 2) as u64;
-        let label = format!("Frame {: >2} row offset table ({} rows)", frame_index, frame.height);
-        used_ranges.push((data_offset, row_table_end, label));
+    let used_ranges = used_ranges(&frames, file_len, war1_style, args.embedded_palette);
 
-        for (i, row) in frame.image_data.raw_row_data.iter().enumerate() {
-            let row_offset = if frame.image_data.grp_type == GrpType::Normal {
-                frame.image_data.row_offsets[i] as u64
-            } else if frame.image_data.grp_type == GrpType::UncompressedExtended {
-                (frame.width as u64 + EXTENDED_IMAGE_WIDTH as u64) * i as u64
-            } else {
-                frame.width as u64 * i as u64
-            };
-
-            let start = data_offset + row_offset;
-            let end = start + row.len() as u64;
-            used_ranges.push((start, end, format!(
-                "Frame {: >2}: Image data for row {: >2} ({} bytes)",
-                frame_index, i, end - start,
-            )));
-        }
+    let duplicates = duplicate_frame_groups(&frames);
+    for indices in &duplicates {
+        warn!("⚠ Identical image data found in frames: {:?}", indices);
     }
-
-
-    let mut hash_map: HashMap<u64, Vec<usize>> = HashMap::new();
-    for (i, frame) in frames.iter().enumerate() {
-        let mut hasher = DefaultHasher::new();
-        frame.image_data.converted_pixels.hash(&mut hasher);
-        let hash = hasher.finish();
-        hash_map.entry(hash).or_default().push(i);
+    if duplicates.is_empty() {
+        info!("✔ All frames have unique pixel data");
     }
+    println!();
 
-    let mut duplicates_found = false;
-    for (_, indices) in hash_map {
-        if indices.len() > 1 {
-            duplicates_found = true;
-            warn!("⚠ Identical image data found in frames: {:?}", indices);
-        }
+    let inconsistent_offsets = find_inconsistent_shared_offsets(&frames);
+    for (offset, indices) in &inconsistent_offsets {
+        warn!(
+            "⚠ Frames {:?} share the image data offset 0x{:0>2X}, but their image content differs! \
+            At least one of them will be rendered wrong.",
+            indices, offset,
+        );
     }
-    if !duplicates_found {
-        info!("✔ All frames have unique pixel data");
+    if inconsistent_offsets.is_empty() {
+        info!("✔ All frames sharing image data offsets have identical content");
+    }
+    if !is_uncompressed {
+        println!();
+        report_smallest_encoding(&frames, args.transparent_index);
+        println!();
+        report_literal_runs(&frames);
+    }
+    if args.delta_report {
+        println!();
+        report_deltas(&frames, actual_max_width.max(header.max_width), actual_max_height.max(header.max_height), args.transparent_index);
     }
-    used_ranges.sort_by_key(|r| r.0);
     println!();
 
 
     // Check for overlapping ranges
-    let mut has_printed_header = false;
-    let mut overlap_found = false;
-    for i in 1..used_ranges.len() {
-        let (prev_start, prev_end, prev_label) = &used_ranges[i - 1];
-        let (curr_start, curr_end, curr_label) = &used_ranges[i];
-        if curr_start < prev_end {
-            if !has_printed_header {
-                debug!("⚠ Overlapping ranges detected:");
-                has_printed_header = true;
-            }
-            debug!(
-                "[0x{:0>2X}]-[0x{:0>2X}] ({}) overlaps with [0x{:0>2X}]-[0x{:0>2X}] ({})",
-                prev_start, prev_end, prev_label, curr_start, curr_end, curr_label,
-            );
-            overlap_found = true;
-        }
+    let overlaps = overlapping_ranges(&used_ranges);
+    if !overlaps.is_empty() {
+        debug!("⚠ Overlapping ranges detected:");
+    }
+    for (prev, curr) in overlaps.iter().map(|&(prev, curr)| (&used_ranges[prev], &used_ranges[curr])) {
+        debug!(
+            "[0x{:0>2X}]-[0x{:0>2X}] ({}) overlaps with [0x{:0>2X}]-[0x{:0>2X}] ({})",
+            prev.0, prev.1, prev.2, curr.0, curr.1, curr.2,
+        );
     }
-    if !overlap_found {
+    if overlaps.is_empty() {
         info!("✔ No overlapping ranges detected");
     }
     println!();
 
 
-    has_printed_header = false;
-    let mut pos = 0;
-    let mut any_gaps = false;
-    for (start, end, _) in &used_ranges {
-        if pos < *start {
+    let mut has_printed_header = false;
+    let gaps = unused_ranges(&used_ranges);
+    let mut any_gaps = !gaps.is_empty();
+    for (start, end) in gaps {
+        if !has_printed_header {
+            warn!("⚠ Unused data found between GRP sections:");
+            has_printed_header = true;
+        }
+        warn!(
+            "- Gap from [0x{:0>6X}] to [0x{:0>6X}] ({} bytes)",
+            start, end, end - start,
+        );
+
+        let mut bytes = "".to_string();
+        let mut buf = vec![0u8; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf)?;
+        for b in &buf {
+            bytes.push_str(&format!("{:02X} ", b));
+        }
+        warn!("  Data: {}", &bytes);
+    }
+    let pos = used_ranges.last().map_or(0, |range| range.1);
+    if pos < file_len {
+        let mut trailing = vec![0u8; (file_len - pos) as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut trailing)?;
+
+        if is_zero_padding(&trailing) {
+            // Typically written to align the file size, and harmless
+            info!(
+                "ℹ Zero padding from 0x{:0>6X} to end ({} bytes)",
+                pos, file_len - pos,
+            );
+        } else {
             any_gaps = true;
             if !has_printed_header {
                 warn!("⚠ Unused data found between GRP sections:");
-                has_printed_header = true;
             }
             warn!(
-                "- Gap from [0x{:0>6X}] to [0x{:0>6X}] ({} bytes)",
-                pos, start, start - pos,
+                "- Trailing data from 0x{:0>6X} to end ({} bytes)",
+                pos, file_len - pos,
             );
-
-            let mut bytes = "".to_string();
-            let mut buf = vec![0u8; (start - pos) as usize];
-            file.seek(SeekFrom::Start(pos))?;
-            file.read_exact(&mut buf)?;
-            for b in &buf {
-                bytes.push_str(&format!("{:02X} ", b));
-            }
-            warn!("  Data: {}", &bytes);
         }
-        pos = *end;
-    }
-    if pos < file_len {
-        any_gaps = true;
-        if !has_printed_header {
-            warn!("⚠ Unused data found between GRP sections:");
-        }
-        warn!(
-            "- Trailing data from 0x{:0>6X} to end ({} bytes)",
-            pos, file_len - pos,
-        );
     }
     if !any_gaps {
         info!("✔ No unused data found between GRP sections");
@@ -245,10 +173,16 @@ pub fn analyse_grp(args: &Args) -> std::io::Result<()> {
     println!();
 
 
-    if matches!(LOG_LEVEL.get(), Some(LogLevel::Debug)) {
+    if args.analyse_format == AnalyseFormat::Svg {
+        let svg_path = args.output_path.as_deref().unwrap();
+        fs::write(svg_path, layout_svg(&used_ranges, file_len))?;
+        info!("Saved file layout diagram to {}", svg_path);
+    }
+
+    if log_enabled!(Level::Debug) {
         debug!("File layout diagram:");
         let mut pos = 0;
-        for (start, end, label) in used_ranges {
+        for (start, end, label, _) in used_ranges {
             if pos < start {
                 let mut bytes = "".to_string();
                 if start - pos < 32 { // Don't print excessive amounts of data
@@ -278,3 +212,1037 @@ pub fn analyse_grp(args: &Args) -> std::io::Result<()> {
 
     Ok(())
 }
+
+/// The structure of a GRP file, as output by '--analyse-format json'
+#[derive(Serialize)]
+struct AnalyseReport {
+    grp_type: String,
+    header:   HeaderReport,
+    frames:   Vec<FrameGeometry>,
+    /// Byte ranges of the file that no part of the GRP uses, including trailing data that is not
+    /// zero padding
+    gaps:     Vec<ByteRange>,
+    overlaps: Vec<Overlap>,
+    /// Groups of frames with identical pixels
+    duplicate_frames: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize)]
+struct HeaderReport {
+    frame_count: u16,
+    max_width:   u16,
+    max_height:  u16,
+    /// The dimensions that the frames actually extend to
+    actual_max_width:  u16,
+    actual_max_height: u16,
+    file_size: u64,
+}
+
+#[derive(Serialize)]
+struct FrameGeometry {
+    x_offset: u8,
+    y_offset: u8,
+    width:    u16,
+    height:   u8,
+    image_data_offset: u32,
+}
+
+#[derive(Serialize)]
+struct ByteRange {
+    start: u64,
+    end:   u64,
+}
+
+/// Two parts of the GRP whose byte ranges overlap
+#[derive(Serialize)]
+struct Overlap {
+    first:        String,
+    first_range:  ByteRange,
+    second:       String,
+    second_range: ByteRange,
+}
+
+/// Gathers the findings of the analysis of a GRP into a report, which is meant to be serialised
+fn analyse_report<R: Read + Seek>(
+    file: &mut R,
+    header: &GrpHeader,
+    war1_style: bool,
+    grp_type: GrpType,
+    frames: &[GrpFrame],
+    embedded_palette: bool,
+) -> std::io::Result<AnalyseReport> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let (actual_max_width, actual_max_height) = actual_max_dimensions(frames);
+    let used_ranges = used_ranges(frames, file_len, war1_style, embedded_palette);
+
+    let mut gaps: Vec<ByteRange> = unused_ranges(&used_ranges).into_iter()
+        .map(|(start, end)| ByteRange { start, end })
+        .collect();
+    let pos = used_ranges.last().map_or(0, |range| range.1);
+    if pos < file_len {
+        let mut trailing = vec![0u8; (file_len - pos) as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut trailing)?;
+        if !is_zero_padding(&trailing) {
+            gaps.push(ByteRange { start: pos, end: file_len });
+        }
+    }
+
+    Ok(AnalyseReport {
+        grp_type: format!("{:?}", grp_type),
+        header: HeaderReport {
+            frame_count: header.frame_count,
+            max_width:   header.max_width,
+            max_height:  header.max_height,
+            actual_max_width,
+            actual_max_height,
+            file_size: file_len,
+        },
+        frames: frames.iter()
+            .map(|frame| FrameGeometry {
+                x_offset: frame.x_offset,
+                y_offset: frame.y_offset,
+                width:    frame.actual_width(),
+                height:   frame.height,
+                image_data_offset: image_data_start(frame) as u32,
+            })
+            .collect(),
+        gaps,
+        overlaps: overlapping_ranges(&used_ranges).into_iter()
+            .map(|(first, second)| Overlap {
+                first:        used_ranges[first].2.clone(),
+                first_range:  ByteRange { start: used_ranges[first].0, end: used_ranges[first].1 },
+                second:       used_ranges[second].2.clone(),
+                second_range: ByteRange { start: used_ranges[second].0, end: used_ranges[second].1 },
+            })
+            .collect(),
+        duplicate_frames: duplicate_frame_groups(frames),
+    })
+}
+
+/// The width and height that the frames extend to, from the top left corner of the canvas
+fn actual_max_dimensions(frames: &[GrpFrame]) -> (u16, u16) {
+    frames.iter().fold((0, 0), |(max_width, max_height), frame| (
+        max_width .max(frame.x_offset as u16 + frame.actual_width()),
+        max_height.max(frame.y_offset as u16 + frame.height as u16),
+    ))
+}
+
+/// The byte ranges of the file that the headers, row offset tables, image data and embedded
+/// palette take up, sorted by where they start. WarCraft I style GRPs have a shorter header.
+fn used_ranges(frames: &[GrpFrame], file_len: u64, war1_style: bool, embedded_palette: bool) -> Vec<(u64, u64, String, RangeKind)> {
+    let header_size = get_header_size(war1_style) as u64;
+    let mut used_ranges: Vec<(u64, u64, String, RangeKind)> = Vec::new();
+    used_ranges.push((0, header_size, format!("GRP Header ({} frames)", frames.len()), RangeKind::Header));
+    used_ranges.push((header_size, header_size + (frames.len() * 8) as u64, "Frame headers".to_string(), RangeKind::FrameHeaders));
+
+    for (frame_index, frame) in frames.iter().enumerate() {
+        let data_offset = image_data_start(frame);
+        let row_table_end = data_offset + (frame.image_data.row_offsets.len() * 2) as u64;
+        let label = format!("Frame {: >2} row offset table ({} rows)", frame_index, frame.height);
+        used_ranges.push((data_offset, row_table_end, label, RangeKind::RowOffsetTable));
+
+        for (i, row) in frame.image_data.raw_row_data.iter().enumerate() {
+            let start = absolute_row_offset(frame, i);
+            let end = start + row.len() as u64;
+            used_ranges.push((start, end, format!(
+                "Frame {: >2}: Image data for row {: >2} ({} bytes)",
+                frame_index, i, end - start,
+            ), RangeKind::ImageData));
+        }
+    }
+    if embedded_palette && file_len >= EMBEDDED_PALETTE_SIZE as u64 {
+        let start = file_len - EMBEDDED_PALETTE_SIZE as u64;
+        used_ranges.push((start, file_len, "Embedded palette".to_string(), RangeKind::Palette));
+    }
+    used_ranges.sort_by_key(|r| r.0);
+    used_ranges
+}
+
+/// Groups of the indices of frames with identical pixels, ordered by their first frame
+fn duplicate_frame_groups(frames: &[GrpFrame]) -> Vec<Vec<usize>> {
+    let mut hash_map: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let mut hasher = DefaultHasher::new();
+        frame.image_data.converted_pixels.hash(&mut hasher);
+        let hash = hasher.finish();
+        hash_map.entry(hash).or_default().push(i);
+    }
+    let mut duplicates: Vec<Vec<usize>> = hash_map.into_values()
+        .filter(|indices| indices.len() > 1)
+        .collect();
+    duplicates.sort();
+    duplicates
+}
+
+/// Pairs of the indices of adjacent ranges among the sorted used ranges, where the second range
+/// starts before the first one ends
+fn overlapping_ranges(used_ranges: &[(u64, u64, String, RangeKind)]) -> Vec<(usize, usize)> {
+    (1..used_ranges.len())
+        .filter(|&i| used_ranges[i].0 < used_ranges[i - 1].1)
+        .map(|i| (i - 1, i))
+        .collect()
+}
+
+/// The byte ranges between the sorted used ranges that are not part of any of them. The end of
+/// the file after the last range is not included.
+fn unused_ranges(used_ranges: &[(u64, u64, String, RangeKind)]) -> Vec<(u64, u64)> {
+    let mut gaps = vec![];
+    let mut pos = 0;
+    for (start, end, _, _) in used_ranges {
+        if pos < *start {
+            gaps.push((pos, *start));
+        }
+        pos = *end;
+    }
+    gaps
+}
+
+/// Logs the offsets and dimensions of the given frame, where its rows are, and the bytes of the
+/// row given by `--analyse-row-number`
+fn analyse_frame(
+    file: &mut File,
+    frames: &[GrpFrame],
+    frame_number: usize,
+    file_len: u64,
+    is_uncompressed: bool,
+    args: &Args,
+) -> std::io::Result<()> {
+    let row_number = match args.analyse_row_number {
+        Some(row_number) if !is_uncompressed => row_number,
+        _ => frames[frame_number].height + 1,
+    };
+    if row_number >= frames[frame_number].height && args.analyse_row_number.is_some() {
+        error!("Row number {} is out of range (0-{})", row_number, frames[frame_number].height as i32 - 1);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+
+    let width = frames[frame_number].actual_width();
+    let next_offset = if frame_number + 1 < frames.len() {
+        frames[frame_number + 1].image_data_offset
+    } else {
+        file_len as u32
+    };
+    info!("Analyzing frame {}:", frame_number);
+    info!("- GrpType:  {:?}", frames[frame_number].image_data.grp_type);
+    info!("- X offset: {}", frames[frame_number].x_offset);
+    info!("- Y offset: {}", frames[frame_number].y_offset);
+    info!("- Width:    {}", width);
+    info!("- Height:   {}", frames[frame_number].height);
+    info!("- This frames image data offset: 0x{:0>2X}", frames[frame_number].image_data_offset);
+    info!("- Next frames image data offset: 0x{:0>2X}", next_offset);
+    if frames[frame_number].image_data.grp_type == GrpType::Normal {
+        for (i, _) in frames[frame_number].image_data.raw_row_data.iter().enumerate() {
+            info!(
+                "- Row {: >2} (0x{:0>2X}), Relative offset: 0x{:0>4X}, Absolute offset: 0x{:0>6X}",
+                i, i, frames[frame_number].image_data.row_offsets[i],
+                absolute_row_offset(&frames[frame_number], i),
+            );
+        }
+    }
+    if args.analyse_row_number.is_some() && frames[frame_number].image_data.grp_type == GrpType::Normal {
+        for (i, row) in frames[frame_number].image_data.raw_row_data.iter().enumerate() {
+            if row_number == i as u8 {
+                let start = absolute_row_offset(&frames[frame_number], i);
+                println!();
+                info!(
+                    "- Row {: >2} (0x{:0>2X}), Relative offset: 0x{:X}, Absolute offset: 0x{:X}",
+                    i, i, frames[frame_number].image_data.row_offsets[i], start,
+                );
+
+                let mut bytes = "".to_string();
+                let mut buf = vec![0u8; row.len()];
+                file.seek(SeekFrom::Start(start))?;
+                file.read_exact(&mut buf)?;
+                for b in &buf {
+                    bytes.push_str(&format!("{:02X} ", b));
+                }
+                info!("  Data ({} bytes): {}", row.len(), &bytes);
+
+                if let Some(dump_path) = &args.dump_row_to {
+                    fs::write(dump_path, &buf)?;
+                    info!("  Wrote the {} bytes of row {} to {}", buf.len(), i, dump_path);
+                }
+                break;
+            }
+        }
+    }
+
+    if args.print_pixels {
+        print_pixels(&frames[frame_number]);
+    }
+
+    Ok(())
+}
+
+/// Compares the number of bytes that the image data of the frames takes up with the smallest
+/// number of bytes it could be RLE encoded to, where frames with identical pixels share data.
+fn report_smallest_encoding(frames: &[GrpFrame], transparent_index: u8) {
+    let mut actual_ranges = Vec::new();
+    let mut smallest_size = 0;
+    let mut seen_content = HashSet::new();
+    for (frame_index, frame) in frames.iter().enumerate() {
+        let ranges = frame_data_ranges(frame);
+        let pixels = &frame.image_data.converted_pixels;
+        if pixels.len() != frame.width as usize * frame.height as usize {
+            warn!("⚠ Frame {} could not be fully decoded, so it is left out of the smallest size", frame_index);
+            continue;
+        }
+        let (size, encoding) = smallest_rle_encoding(frame.width as u16, frame.height as u16, pixels, transparent_index);
+        debug!(
+            "Frame {: >2}: {} bytes, could be {} bytes using {}",
+            frame_index, covered_bytes(ranges.clone()), size, encoding,
+        );
+        actual_ranges.extend(ranges);
+        if seen_content.insert(decoded_pixels_hash(frame)) {
+            smallest_size += size as u64;
+        }
+    }
+
+    let actual_size = covered_bytes(actual_ranges);
+    info!("Size of the image data:");
+    info!("- Actual:            {} bytes", actual_size);
+    info!("- Smallest possible: {} bytes", smallest_size);
+    if actual_size > smallest_size {
+        let saving = actual_size - smallest_size;
+        info!(
+            "ℹ Re-encoding the frames could save {} bytes ({:.1}% of the image data)",
+            saving, 100.0 * saving as f64 / actual_size as f64,
+        );
+    } else {
+        info!("✔ The image data is as small as it can be encoded");
+    }
+}
+
+/// Reports the frames that encode runs of one colour as literal copies rather than as repeats
+fn report_literal_runs(frames: &[GrpFrame]) {
+    let mut total_wasted = 0;
+    let mut seen_offsets = HashSet::new();
+    for (frame_index, frame) in frames.iter().enumerate() {
+        if !seen_offsets.insert(frame.image_data_offset) {
+            continue; // Shares image data with an earlier frame, which has already been reported
+        }
+        let wasteful_rows: Vec<(usize, usize)> = frame.image_data.raw_row_data.iter()
+            .map(|row| bytes_wasted_on_literal_runs(row))
+            .enumerate()
+            .filter(|&(_, wasted)| wasted > 0)
+            .collect();
+        if wasteful_rows.is_empty() {
+            continue;
+        }
+        let wasted: usize = wasteful_rows.iter().map(|&(_, wasted)| wasted).sum();
+        warn!(
+            "⚠ Frame {: >2} wastes at least {} bytes on runs of one colour encoded as literal copies, in rows {:?}",
+            frame_index, wasted, wasteful_rows.iter().map(|&(row, _)| row).collect::<Vec<_>>(),
+        );
+        total_wasted += wasted;
+    }
+    if total_wasted > 0 {
+        warn!("⚠ In total, at least {} bytes could be saved by encoding the runs as repeats", total_wasted);
+    } else {
+        info!("✔ No runs of one colour are encoded as literal copies");
+    }
+}
+
+/// Estimates how many bytes of an RLE encoded row are wasted on literal copies containing runs of
+/// one colour, which would take fewer bytes as repeats, even after splitting the literal copy
+/// around them. Parsing stops at the first literal copy that goes past the end of the row.
+fn bytes_wasted_on_literal_runs(row: &[u8]) -> usize {
+    let mut wasted = 0;
+    let mut pos = 0;
+    while let Some(&control_byte) = row.get(pos) {
+        if control_byte & 0x80 != 0 {
+            pos += 1;
+        } else if control_byte & 0x40 != 0 {
+            pos += 2;
+        } else {
+            let Some(literal) = row.get(pos + 1 .. pos + 1 + control_byte as usize) else {
+                break;
+            };
+            let mut start = 0;
+            for run in literal.chunk_by(|a, b| a == b) {
+                let end = start + run.len();
+                // A repeat takes 2 bytes, and the parts of the literal copy before and after
+                // the run each need a control byte of their own
+                let splits = (start > 0) as usize + (end < literal.len()) as usize;
+                wasted += (run.len() + 1).saturating_sub(2 + splits);
+                start = end;
+            }
+            pos += 1 + literal.len();
+        }
+    }
+    wasted
+}
+
+/// Reports how many pixels of each frame differ from the first frame and from the previous frame,
+/// when drawn on a canvas of the given size
+fn report_deltas(frames: &[GrpFrame], canvas_width: u16, canvas_height: u16, transparent_index: u8) {
+    let canvases: Vec<Vec<u8>> = frames.iter()
+        .map(|frame| draw_on_canvas(frame, canvas_width, canvas_height, transparent_index))
+        .collect();
+    let canvas_size = (canvas_width as usize * canvas_height as usize).max(1);
+    let percentage = |count: usize| 100.0 * count as f64 / canvas_size as f64;
+
+    info!("Pixels differing from frame 0 and from the previous frame:");
+    for (frame_index, canvas) in canvases.iter().enumerate() {
+        let from_first    = count_differing_pixels(&canvases[0], canvas);
+        let from_previous = count_differing_pixels(&canvases[frame_index.saturating_sub(1)], canvas);
+        info!(
+            "- Frame {: >2}: {: >5} ({: >5.1}%) from frame 0, {: >5} ({: >5.1}%) from the previous frame",
+            frame_index, from_first, percentage(from_first), from_previous, percentage(from_previous),
+        );
+    }
+}
+
+fn count_differing_pixels(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).filter(|(a, b)| a != b).count()
+}
+
+/// The ranges of bytes in the file that the row offset table and the rows of the frame take up
+fn frame_data_ranges(frame: &GrpFrame) -> Vec<(u64, u64)> {
+    let data_offset = image_data_start(frame);
+    let mut ranges = vec![(data_offset, data_offset + (frame.image_data.row_offsets.len() * 2) as u64)];
+    for (i, row) in frame.image_data.raw_row_data.iter().enumerate() {
+        let start = absolute_row_offset(frame, i);
+        ranges.push((start, start + row.len() as u64));
+    }
+    ranges
+}
+
+/// Counts the bytes covered by any of the given ranges, counting bytes in overlapping ranges once
+fn covered_bytes(mut ranges: Vec<(u64, u64)>) -> u64 {
+    ranges.sort();
+    let mut covered = 0;
+    let mut pos = 0;
+    for (start, end) in ranges {
+        let start = start.max(pos);
+        if end > start {
+            covered += end - start;
+            pos = end;
+        }
+    }
+    covered
+}
+
+/// What a range of bytes in a GRP file is used for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RangeKind {
+    Header,
+    FrameHeaders,
+    RowOffsetTable,
+    ImageData,
+    Palette,
+    Unused,
+}
+
+impl RangeKind {
+    const ALL: [RangeKind; 6] = [
+        RangeKind::Header, RangeKind::FrameHeaders, RangeKind::RowOffsetTable, RangeKind::ImageData,
+        RangeKind::Palette, RangeKind::Unused,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            RangeKind::Header         => "GRP header",
+            RangeKind::FrameHeaders   => "Frame headers",
+            RangeKind::RowOffsetTable => "Row offset tables",
+            RangeKind::ImageData      => "Image data",
+            RangeKind::Palette        => "Embedded palette",
+            RangeKind::Unused         => "Unused",
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self {
+            RangeKind::Header         => "#4e79a7",
+            RangeKind::FrameHeaders   => "#f28e2b",
+            RangeKind::RowOffsetTable => "#59a14f",
+            RangeKind::ImageData      => "#76b7b2",
+            RangeKind::Palette        => "#b07aa1",
+            RangeKind::Unused         => "#e15759",
+        }
+    }
+}
+
+const SVG_BAR_WIDTH:  f64 = 1200.0;
+const SVG_BAR_HEIGHT: f64 = 80.0;
+/// Ranges narrower than this in the diagram are not labelled, but still have a tooltip
+const SVG_MIN_LABELLED_WIDTH: f64 = 60.0;
+
+/// Draws the given sorted ranges of the file as a bar, where the width of each range is
+/// proportional to its size. Unused bytes between the ranges are drawn too.
+fn layout_svg(used_ranges: &[(u64, u64, String, RangeKind)], file_len: u64) -> String {
+    let mut regions: Vec<(u64, u64, String, RangeKind)> = vec![];
+    let mut pos = 0;
+    for (start, end, label, kind) in used_ranges {
+        if pos < *start {
+            regions.push((pos, *start, format!("Unused ({} bytes)", start - pos), RangeKind::Unused));
+        }
+        regions.push((*start, *end, label.trim().to_string(), *kind));
+        pos = pos.max(*end);
+    }
+    if pos < file_len {
+        regions.push((pos, file_len, format!("Unused ({} bytes)", file_len - pos), RangeKind::Unused));
+    }
+
+    let scale = SVG_BAR_WIDTH / file_len.max(1) as f64;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+        SVG_BAR_WIDTH + 20.0, SVG_BAR_HEIGHT + 40.0 + 20.0 * RangeKind::ALL.len() as f64,
+    );
+    svg.push_str(&format!("<text x=\"10\" y=\"15\">File layout ({} bytes)</text>\n", file_len));
+    for (start, end, label, kind) in &regions {
+        let x = 10.0 + *start as f64 * scale;
+        let width = (*end - *start) as f64 * scale;
+        svg.push_str(&format!(
+            "<rect x=\"{:.2}\" y=\"25\" width=\"{:.2}\" height=\"{}\" fill=\"{}\" stroke=\"#ffffff\" stroke-width=\"0.5\">\
+            <title>[0x{:0>6X}]-[0x{:0>6X}] {}</title></rect>\n",
+            x, width, SVG_BAR_HEIGHT, kind.colour(), start, end, escape_xml(label),
+        ));
+        if width >= SVG_MIN_LABELLED_WIDTH {
+            svg.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{}\" text-anchor=\"middle\" textLength=\"{:.2}\" lengthAdjust=\"spacingAndGlyphs\">{}</text>\n",
+                x + width / 2.0, 25.0 + SVG_BAR_HEIGHT / 2.0, (width - 4.0).min(label.len() as f64 * 6.0), escape_xml(label),
+            ));
+        }
+    }
+    for (i, kind) in RangeKind::ALL.iter().enumerate() {
+        let y = 40.0 + SVG_BAR_HEIGHT + 20.0 * i as f64;
+        svg.push_str(&format!(
+            "<rect x=\"10\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{}\"/><text x=\"28\" y=\"{}\">{}</text>\n",
+            y, kind.colour(), y + 10.0, kind.name(),
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Groups the frames by image data offset, and returns the groups where the frames sharing an
+/// offset don't have identical content. Sharing offsets is only intended for identical frames.
+fn find_inconsistent_shared_offsets(frames: &[GrpFrame]) -> Vec<(u32, Vec<usize>)> {
+    let mut offset_map: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, frame) in frames.iter().enumerate() {
+        offset_map.entry(frame.image_data_offset).or_default().push(i);
+    }
+
+    let mut inconsistent: Vec<(u32, Vec<usize>)> = offset_map
+        .into_iter()
+        .filter(|(_, indices)| {
+            let first = &frames[indices[0]];
+            indices.iter().skip(1).any(|&i| {
+                frames[i].width  != first.width ||
+                frames[i].height != first.height ||
+                frames[i].image_data.converted_pixels != first.image_data.converted_pixels
+            })
+        })
+        .collect();
+    inconsistent.sort_by_key(|(offset, _)| *offset);
+    inconsistent
+}
+
+/// Analyses all GRP files in a directory, and reports image data that is identical across
+/// them, as well as how much space would be saved if identical image data was shared.
+pub fn analyse_shared_data(args: &Args) -> std::io::Result<()> {
+    let grp_files = list_grp_files(&args.input_path.clone().unwrap())?;
+    let options = GrpReadOptions::from(args);
+
+    // Map: hash of image data -> list of (file index, frame index)
+    let mut occurrences: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    let mut data_sizes:  HashMap<u64, usize> = HashMap::new();
+    let mut total_size = 0;
+
+    for (file_index, grp_file) in grp_files.iter().enumerate() {
+        let frames = read_grp_file(grp_file, &options)?.frames;
+        info!("Read {} frames from {}", frames.len(), grp_file);
+
+        let mut seen_offsets = HashSet::new();
+        for (frame_index, frame) in frames.iter().enumerate() {
+            if !seen_offsets.insert(frame.image_data_offset) {
+                continue; // The image data is already shared within the GRP
+            }
+            let hash = image_data_hash(frame);
+            total_size += frame.grp_frame_len();
+            data_sizes.insert(hash, frame.grp_frame_len());
+            occurrences.entry(hash).or_default().push((file_index, frame_index));
+        }
+    }
+    println!();
+
+    let mut shared: Vec<(&u64, &Vec<(usize, usize)>)> = occurrences
+        .iter()
+        .filter(|(_, frames)| frames.len() > 1)
+        .collect();
+    // Sort by the first file and frame that the image data occurs in
+    shared.sort_by_key(|(_, frames)| frames[0]);
+
+    let mut saved_size = 0;
+    for (hash, frames) in &shared {
+        let size = data_sizes[*hash];
+        saved_size += size * (frames.len() - 1);
+        let locations: Vec<String> = frames
+            .iter()
+            .map(|(file_index, frame_index)| format!("{}[{}]", grp_files[*file_index], frame_index))
+            .collect();
+        info!("Identical image data ({} bytes) in: {}", size, locations.join(", "));
+    }
+
+    if shared.is_empty() {
+        info!("✔ No image data is shared between the {} GRPs", grp_files.len());
+    } else {
+        println!();
+        info!(
+            "Image data of the {} GRPs is {} bytes in total. Sharing identical image data would save {} bytes ({:.1}%)",
+            grp_files.len(), total_size, saved_size, 100.0 * saved_size as f64 / total_size as f64,
+        );
+    }
+    Ok(())
+}
+
+/// Prints the header of a GRP file and a table of its frame headers. Only the headers are read,
+/// so this is fast even for huge GRPs, but nothing is known about the image data itself.
+pub fn list_frames(args: &Args) -> std::io::Result<()> {
+    let input_path = &args.input_path.clone().unwrap();
+    let mut file = File::open(input_path)?;
+
+    let (header, _, grp_type) = read_grp_header_and_type(&mut file, input_path, args.force_grp_type)?;
+    check_grp_limits(&mut file, &header, grp_type, &GrpLimits::from(args))?;
+    let frame_headers = read_frame_headers(&mut file, header.frame_count, grp_type)?;
+
+    println!();
+    info!(
+        "GRP type: {:?}. Frame count: {}. Max width: {}. Max height: {}",
+        grp_type, header.frame_count, header.max_width, header.max_height,
+    );
+    println!();
+    for line in frame_table(&frame_headers) {
+        info!("{}", line);
+    }
+    Ok(())
+}
+
+/// Formats the frame headers as a table, with one line per frame after a line of column names
+fn frame_table(frame_headers: &[FrameHeader]) -> Vec<String> {
+    let mut lines = vec!["Frame  X    Y    Width  Height  Data offset  Compressed".to_string()];
+    lines.extend(frame_headers.iter().enumerate().map(|(i, frame)| format!(
+        "{:>5}  {:<3}  {:<3}  {:<5}  {:<6}  0x{:08X}   {}",
+        i, frame.x_offset, frame.y_offset, frame.width, frame.height, frame.image_data_offset,
+        if frame.grp_type == GrpType::Normal { "yes" } else { "no" },
+    )));
+    lines
+}
+
+/// Compares the frames of the input GRP with the frames of the other GRP, and reports
+/// which frames have identical decoded pixels.
+pub fn cross_diff(args: &Args) -> std::io::Result<()> {
+    let path_a = args.input_path.clone().unwrap();
+    let path_b = args.other_path.clone().unwrap();
+    let options = GrpReadOptions::from(args);
+    let frames_a = read_grp_file(&path_a, &options)?.frames;
+    let frames_b = read_grp_file(&path_b, &options)?.frames;
+    info!("A: {} ({} frames)", path_a, frames_a.len());
+    info!("B: {} ({} frames)", path_b, frames_b.len());
+    println!();
+
+    let identical = find_identical_frames(&frames_a, &frames_b);
+    for (a, b) in &identical {
+        info!("A[{}] == B[{}]", a, b);
+    }
+    if identical.is_empty() {
+        info!("✔ No frames are identical between the two GRPs");
+    } else {
+        let frames_in_a: HashSet<usize> = identical.iter().map(|(a, _)| *a).collect();
+        println!();
+        info!("{} of the {} frames in A have an identical frame in B", frames_in_a.len(), frames_a.len());
+    }
+    Ok(())
+}
+
+/// Returns all pairs of frame indices (a, b) where frame a of frames_a and frame b of
+/// frames_b have identical decoded pixels, sorted by a and then b.
+pub fn find_identical_frames(frames_a: &[GrpFrame], frames_b: &[GrpFrame]) -> Vec<(usize, usize)> {
+    let mut hash_to_frames_b: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (b, frame) in frames_b.iter().enumerate() {
+        hash_to_frames_b.entry(decoded_pixels_hash(frame)).or_default().push(b);
+    }
+
+    frames_a.iter()
+        .enumerate()
+        .flat_map(|(a, frame)| {
+            hash_to_frames_b.get(&decoded_pixels_hash(frame))
+                .into_iter()
+                .flatten()
+                .map(move |&b| (a, b))
+        })
+        .collect()
+}
+
+/// Hashes the decoded pixels and dimensions of a frame, regardless of how it is encoded
+fn decoded_pixels_hash(frame: &GrpFrame) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    frame.actual_width().hash(&mut hasher);
+    frame.height.hash(&mut hasher);
+    frame.image_data.converted_pixels.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns true if the given trailing bytes of a file are all zero
+fn is_zero_padding(trailing: &[u8]) -> bool {
+    trailing.iter().all(|&b| b == 0)
+}
+
+/// Hashes everything that needs to be identical for two frames to be able to share image data
+fn image_data_hash(frame: &GrpFrame) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    frame.width.hash(&mut hasher);
+    frame.height.hash(&mut hasher);
+    frame.image_data.grp_type.hash(&mut hasher);
+    frame.image_data.row_offsets.hash(&mut hasher);
+    frame.image_data.raw_row_data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The offset in the file where the image data of the given frame starts. For Extended
+/// Uncompressed frames, the high bit that signals the extended width is not included.
+fn image_data_start(frame: &GrpFrame) -> u64 {
+    (frame.image_data_offset & !EXTENDED_OFFSET_BIT) as u64
+}
+
+/// The offset in the file where the given row of the given frame starts. Computed using
+/// u64 throughout, so that large offsets don't wrap around.
+fn absolute_row_offset(frame: &GrpFrame, row: usize) -> u64 {
+    let row_offset = match frame.image_data.grp_type {
+        GrpType::Normal => frame.image_data.row_offsets[row] as u64,
+        GrpType::UncompressedExtended => (frame.width as u64 + EXTENDED_IMAGE_WIDTH as u64) * row as u64,
+        GrpType::Uncompressed | GrpType::War1 => frame.width as u64 * row as u64,
+    };
+    image_data_start(frame) + row_offset
+}
+
+
+/// Frames larger than this in any dimension are not printed by '--print-pixels'
+const MAX_PRINTED_PIXELS_SIDE: u16 = 64;
+
+/// Prints the palette indices of the decoded pixels of the frame, unless the frame is too big.
+fn print_pixels(frame: &GrpFrame) {
+    let width = frame.actual_width();
+    if width > MAX_PRINTED_PIXELS_SIDE || frame.height as u16 > MAX_PRINTED_PIXELS_SIDE {
+        warn!(
+            "The frame is {}x{} pixels, which is too big to print. Only frames of at most {}x{} pixels are printed",
+            width, frame.height, MAX_PRINTED_PIXELS_SIDE, MAX_PRINTED_PIXELS_SIDE,
+        );
+        return;
+    }
+    println!();
+    info!("Palette indices of the pixels (hexadecimal):");
+    for line in pixel_grid(&frame.image_data.converted_pixels, width) {
+        info!("  {}", line);
+    }
+}
+
+/// Formats the given pixels as lines of hexadecimal palette indices, one line per row.
+fn pixel_grid(pixels: &[u8], width: u16) -> Vec<String> {
+    if width == 0 {
+        return vec![];
+    }
+    pixels
+        .chunks(width as usize)
+        .map(|row| row.iter().map(|pixel| format!("{:02X}", pixel)).collect::<Vec<_>>().join(" "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grp::ImageData;
+
+    #[test]
+    fn rejects_frame_number_equal_to_frame_count() -> std::io::Result<()> {
+        use crate::grp::{encode_grp, FrameInput};
+        use crate::palpng::PalettizedImageWithMetadata;
+        use crate::CompressionType;
+        use clap::Parser;
+        let frame = FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset: 0,
+                y_offset: 0,
+                width:    2,
+                height:   2,
+                original_width:  2,
+                original_height: 2,
+                palettized_image: vec![1, 2, 3, 4],
+            },
+        };
+        let path = "test_analyse_frame_number.grp";
+        fs::write(path, encode_grp(&[frame.clone(), frame], &CompressionType::Normal)?)?;
+        let analyse = |frame_number: &str| analyse_grp(&Args::parse_from([
+            "irongrp", "--mode", "analyse-grp", "--input-path", path, "--frame-number", frame_number,
+        ]));
+        let analyse_row = |row_number: &str| analyse_grp(&Args::parse_from([
+            "irongrp", "--mode", "analyse-grp", "--input-path", path, "--frame-number", "1", "--analyse-row-number", row_number,
+        ]));
+
+        let last_frame = analyse("1");
+        let past_last_frame = analyse("2");
+        let last_row = analyse_row("1");
+        let past_last_row = analyse_row("2");
+        fs::remove_file(path)?;
+
+        assert!(last_frame.is_ok());
+        assert_eq!(past_last_frame.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(last_row.is_ok());
+        assert_eq!(past_last_row.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn finds_no_unused_data_in_war1_grps() -> std::io::Result<()> {
+        use crate::grp::{encode_grp, read_grp_frames, FrameInput};
+        use crate::palpng::PalettizedImageWithMetadata;
+        use crate::CompressionType;
+        let frame = |x_offset: u8, pixels: Vec<u8>| FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 1,
+                width:    2,
+                height:   3,
+                original_width:  8,
+                original_height: 4,
+                palettized_image: pixels,
+            },
+        };
+        let bytes = encode_grp(&[frame(0, vec![1; 6]), frame(4, vec![2; 6])], &CompressionType::War1)?;
+        let file_len = bytes.len() as u64;
+        let mut file = std::io::Cursor::new(bytes);
+        let (header, war1_style, grp_type) = read_grp_header_and_type(&mut file, "test", None)?;
+        assert!(war1_style);
+        assert_eq!(grp_type, GrpType::War1);
+        let frames = read_grp_frames(&mut file, header.frame_count, grp_type, &GrpReadOptions::default())?;
+
+        let used_ranges = used_ranges(&frames, file_len, war1_style, false);
+        assert_eq!((used_ranges[0].0, used_ranges[0].1), (0, 4));
+        assert_eq!((used_ranges[1].0, used_ranges[1].1), (4, 20));
+        assert!(unused_ranges(&used_ranges).is_empty());
+        assert!(overlapping_ranges(&used_ranges).is_empty());
+        assert_eq!(used_ranges.last().map(|range| range.1), Some(file_len));
+        Ok(())
+    }
+
+    #[test]
+    fn reports_findings_as_json() -> std::io::Result<()> {
+        use crate::grp::{encode_grp, read_grp_frames, FrameInput};
+        use crate::palpng::PalettizedImageWithMetadata;
+        use crate::CompressionType;
+        let frame = |x_offset: u8, pixels: Vec<u8>| FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 1,
+                width:    2,
+                height:   2,
+                original_width:  8,
+                original_height: 4,
+                palettized_image: pixels,
+            },
+        };
+        let frames = [frame(0, vec![1, 2, 3, 4]), frame(3, vec![5; 4]), frame(6, vec![1, 2, 3, 4])];
+        let mut bytes = encode_grp(&frames, &CompressionType::Uncompressed)?;
+        bytes.extend([0xAB, 0xCD]);
+        let file_len = bytes.len() as u64;
+        let mut file = std::io::Cursor::new(bytes);
+        let (header, _, grp_type) = read_grp_header_and_type(&mut file, "test", Some(GrpType::Uncompressed))?;
+        let frames = read_grp_frames(&mut file, header.frame_count, grp_type, &GrpReadOptions::default())?;
+
+        let report = analyse_report(&mut file, &header, false, grp_type, &frames, false)?;
+        assert_eq!(report.grp_type, "Uncompressed");
+        assert_eq!((report.header.actual_max_width, report.header.actual_max_height), (8, 3));
+        assert_eq!(report.header.file_size, file_len);
+        assert_eq!(report.frames[1].x_offset, 3);
+        assert_eq!(report.duplicate_frames, vec![vec![0, 2]]);
+        assert!(report.overlaps.is_empty());
+        assert_eq!(report.gaps.len(), 1);
+        assert_eq!((report.gaps[0].start, report.gaps[0].end), (file_len - 2, file_len));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["header"]["frame_count"], 3);
+        assert_eq!(json["frames"][2]["image_data_offset"], frames[2].image_data_offset);
+        assert_eq!(json["duplicate_frames"][0][1], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lists_frame_headers_without_image_data() -> std::io::Result<()> {
+        use crate::grp::{encode_grp, FrameInput};
+        use crate::palpng::PalettizedImageWithMetadata;
+        use crate::CompressionType;
+        let frame = |x_offset: u8, width: u16| FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 2,
+                width,
+                height: 3,
+                original_width:  width + 4,
+                original_height: 8,
+                palettized_image: vec![5; width as usize * 3],
+            },
+        };
+
+        let bytes = encode_grp(&[frame(1, 4), frame(3, 300)], &CompressionType::Uncompressed)?;
+        let frame_headers = read_frame_headers(&mut std::io::Cursor::new(bytes), 2, GrpType::Uncompressed)?;
+        assert_eq!(frame_headers[0], FrameHeader {
+            x_offset: 1,
+            y_offset: 2,
+            width:    4,
+            height:   3,
+            image_data_offset: 22,
+            grp_type: GrpType::Uncompressed,
+        });
+        assert_eq!((frame_headers[1].width, frame_headers[1].image_data_offset), (300, 34));
+        assert_eq!(frame_headers[1].grp_type, GrpType::UncompressedExtended);
+
+        let table = frame_table(&frame_headers);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table[1], "    0  1    2    4      3       0x00000016   no");
+        assert_eq!(table[2], "    1  3    2    300    3       0x00000022   no");
+        Ok(())
+    }
+
+    #[test]
+    fn draws_layout_with_unused_ranges() {
+        let ranges = vec![
+            (0, 6, "GRP Header (1 frames)".to_string(), RangeKind::Header),
+            (6, 14, "Frame headers".to_string(), RangeKind::FrameHeaders),
+            (20, 30, "Frame  0: Image data for row  0 (10 bytes)".to_string(), RangeKind::ImageData),
+        ];
+        let svg = layout_svg(&ranges, 40);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<title>").count(), 5); // Including the gap and the trailing bytes
+        assert!(svg.contains("[0x00000E]-[0x000014] Unused (6 bytes)"));
+        assert!(svg.contains("[0x00001E]-[0x000028] Unused (10 bytes)"));
+    }
+
+    #[test]
+    fn formats_pixels_as_grid_of_indices() {
+        let pixels = vec![0, 1, 255, 16, 0, 7];
+        assert_eq!(pixel_grid(&pixels, 3), vec!["00 01 FF", "10 00 07"]);
+        assert!(pixel_grid(&[], 0).is_empty());
+    }
+
+    fn frame(grp_type: GrpType, width: u8, image_data_offset: u32, row_offsets: Vec<u16>) -> GrpFrame {
+        GrpFrame {
+            x_offset: 0,
+            y_offset: 0,
+            width,
+            height: 2,
+            image_data_offset,
+            image_data: ImageData {
+                row_offsets,
+                raw_row_data: vec![vec![], vec![]],
+                converted_pixels: vec![],
+                grp_type,
+            },
+        }
+    }
+
+    #[test]
+    fn finds_frames_sharing_offset_with_different_content() {
+        let mut frames = vec![
+            frame(GrpType::Uncompressed, 2, 0x20, vec![]),
+            frame(GrpType::Uncompressed, 2, 0x20, vec![]),
+            frame(GrpType::Uncompressed, 2, 0x30, vec![]),
+            frame(GrpType::Uncompressed, 3, 0x30, vec![]),
+        ];
+        frames[0].image_data.converted_pixels = vec![1, 2, 3, 4];
+        frames[1].image_data.converted_pixels = vec![1, 2, 3, 4];
+
+        assert_eq!(find_inconsistent_shared_offsets(&frames), vec![(0x30, vec![2, 3])]);
+
+        frames[1].image_data.converted_pixels = vec![1, 2, 3, 5];
+        assert_eq!(find_inconsistent_shared_offsets(&frames), vec![(0x20, vec![0, 1]), (0x30, vec![2, 3])]);
+    }
+
+    #[test]
+    fn finds_identical_frames_across_grps_regardless_of_encoding() {
+        let mut frames_a = vec![
+            frame(GrpType::Normal, 2, 0x20, vec![0, 2]),
+            frame(GrpType::Normal, 2, 0x30, vec![0, 2]),
+        ];
+        let mut frames_b = vec![
+            frame(GrpType::Uncompressed, 2, 0x40, vec![]),
+            frame(GrpType::Uncompressed, 2, 0x44, vec![]),
+            frame(GrpType::Uncompressed, 1, 0x48, vec![]),
+        ];
+        frames_a[0].image_data.converted_pixels = vec![1, 2, 3, 4];
+        frames_a[1].image_data.converted_pixels = vec![5, 6, 7, 8];
+        frames_b[0].image_data.converted_pixels = vec![5, 6, 7, 8];
+        frames_b[1].image_data.converted_pixels = vec![5, 6, 7, 8];
+        frames_b[2].image_data.converted_pixels = vec![1, 2, 3, 4]; // Same pixels, different width
+
+        assert_eq!(find_identical_frames(&frames_a, &frames_b), vec![(1, 0), (1, 1)]);
+        assert_eq!(find_identical_frames(&frames_b, &frames_a), vec![(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn finds_bytes_wasted_on_literal_runs() {
+        // A literal copy of only one colour, which would be 2 bytes as a repeat
+        assert_eq!(bytes_wasted_on_literal_runs(&[0x05, 7, 7, 7, 7, 7]), 4);
+        // A run in the middle of a literal copy, which needs to be split in two around it
+        assert_eq!(bytes_wasted_on_literal_runs(&[0x07, 1, 7, 7, 7, 7, 7, 2]), 2);
+        assert_eq!(bytes_wasted_on_literal_runs(&[0x05, 1, 7, 7, 7, 2]), 0);
+        // Transparent runs and repeats are skipped over
+        assert_eq!(bytes_wasted_on_literal_runs(&[0x83, 0x45, 7, 0x03, 1, 2, 3]), 0);
+        // A literal copy going past the end of the row
+        assert_eq!(bytes_wasted_on_literal_runs(&[0x05, 7, 7]), 0);
+    }
+
+    #[test]
+    fn compares_frames_on_the_canvas() {
+        let mut first = frame(GrpType::Uncompressed, 2, 0, vec![]);
+        first.height = 1;
+        first.image_data.converted_pixels = vec![7, 8];
+        let mut moved = first.clone();
+        moved.x_offset = 1;
+
+        let first_canvas = draw_on_canvas(&first, 3, 2, 0);
+        let moved_canvas = draw_on_canvas(&moved, 3, 2, 0);
+        assert_eq!(first_canvas, vec![7, 8, 0, 0, 0, 0]);
+        assert_eq!(moved_canvas, vec![0, 7, 8, 0, 0, 0]);
+        assert_eq!(count_differing_pixels(&first_canvas, &moved_canvas), 3);
+    }
+
+    #[test]
+    fn counts_overlapping_ranges_once() {
+        assert_eq!(covered_bytes(vec![(10, 20), (0, 4), (15, 25), (12, 14)]), 19);
+        assert_eq!(covered_bytes(vec![]), 0);
+    }
+
+    #[test]
+    fn recognises_zero_padding() {
+        assert!(is_zero_padding(&[0, 0, 0]));
+        assert!(!is_zero_padding(&[0, 0, 1]));
+    }
+
+    #[test]
+    fn absolute_row_offset_does_not_wrap_above_u16() {
+        let normal = frame(GrpType::Normal, 8, 0xFFF0, vec![0x04, 0x20]);
+
+        assert_eq!(absolute_row_offset(&normal, 0), 0xFFF4);
+        assert_eq!(absolute_row_offset(&normal, 1), 0x1_0010);
+    }
+
+    #[test]
+    fn absolute_row_offset_of_extended_frame_ignores_extended_bit() {
+        let extended = frame(GrpType::UncompressedExtended, 4, 0x8001_0000, vec![]);
+
+        assert_eq!(image_data_start(&extended), 0x1_0000);
+        assert_eq!(absolute_row_offset(&extended, 1), 0x1_0000 + 260);
+    }
+}
@@ -1,13 +1,28 @@
-use crate::png::{png_to_pixels, render_and_save_frames_to_png};
-use crate::{list_png_files, Args, CompressionType, UNCOMPRESSED_FILENAME, WAR1_FILENAME};
+use crate::palette::{get_palette, index_heatmap_palette, palette_swatch, read_palette_entries, FULL_PALETTE_LEN};
+use crate::png::{hash_input_files, offsets_from_file_name, png_to_frame_pixels, png_to_pixels, read_frame_metadata, read_source_manifest, render_and_save_frames_to_png, resume_manifest_path, rgba_to_pixels, save_frames_as_apng, save_frames_as_gif, write_frame_metadata, write_source_manifest, FrameRecord, ResumeManifest};
+use crate::zip::write_zip;
+use crate::{is_zip_path, list_grp_files, list_png_files, Args, CompressionType, LogLevel, UNCOMPRESSED_FILENAME, WAR1_FILENAME};
 use clap::ValueEnum;
 use log::{debug, error, info, trace, warn};
-use palpngrs::{greyscale_palette, read_rgb_palette, PalettizedImageWithMetadata};
+use crate::palpng::{save_rgb_pixels_to_image_file, write_rgb_palette, ColourMapping, PalettizedImageWithMetadata, TrimEdges};
+use rayon::prelude::*;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub(crate) const EXTENDED_OFFSET_BIT: u32 = 0x8000_0000;
+pub const EXTENDED_IMAGE_WIDTH: u16 = 256;
+/// The highest image data offset that a frame header can hold, since the highest bit of the
+/// offset marks frames with extended widths in Uncompressed GRPs. This limits the size of GRPs.
+const MAX_IMAGE_DATA_OFFSET: u32 = EXTENDED_OFFSET_BIT - 1;
+/// Frames are at most 511 pixels wide and 255 pixels high, and are placed at offsets of at most
+/// 255, so the maximum dimensions in a GRP header should never be larger than this.
+const MAX_PLAUSIBLE_DIMENSION: u16 = 1024;
+/// The number of frames that are read when checking whether a palette fits a GRP
+const PALETTE_CHECK_SAMPLE_FRAMES: u16 = 8;
 
 #[derive(Debug)]
 pub struct GrpHeader {
@@ -16,6 +31,14 @@ pub struct GrpHeader {
     pub max_height:  u16,
 }
 
+/// A whole GRP: its header, its type and all its frames
+#[derive(Debug)]
+pub struct GrpFile {
+    pub header:   GrpHeader,
+    pub frames:   Vec<GrpFrame>,
+    pub grp_type: GrpType,
+}
+
 #[derive(Clone, Debug)]
 pub struct GrpFrame {
     pub x_offset: u8,
@@ -38,7 +61,7 @@ pub struct ImageData {
     pub grp_type: GrpType,
 }
 
-#[derive(Clone, ValueEnum, PartialEq, Debug, Copy)]
+#[derive(Clone, ValueEnum, PartialEq, Eq, Hash, Debug, Copy)]
 pub enum GrpType {
     Normal,
     Uncompressed,
@@ -46,6 +69,136 @@ pub enum GrpType {
     War1,
 }
 
+/// Upper bounds on what a GRP may claim in its headers. GRPs exceeding them are rejected
+/// before any image data is read, to guard against implausible or malicious files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrpLimits {
+    /// Maximum number of frames
+    pub max_frames: u16,
+    /// Maximum number of pixels (bytes) in the decoded image of any single frame
+    pub max_frame_bytes: usize,
+}
+
+impl Default for GrpLimits {
+    fn default() -> Self {
+        GrpLimits {
+            max_frames:      u16::MAX,
+            max_frame_bytes: usize::MAX,
+        }
+    }
+}
+
+impl From<&Args> for GrpLimits {
+    fn from(args: &Args) -> Self {
+        let default = GrpLimits::default();
+        GrpLimits {
+            max_frames:      args.max_frames.unwrap_or(default.max_frames),
+            max_frame_bytes: args.max_frame_bytes.unwrap_or(default.max_frame_bytes),
+        }
+    }
+}
+
+/// The frame header of a frame, read without its image data
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameHeader {
+    pub x_offset: u8,
+    pub y_offset: u8,
+    /// Width of the frame, including the extra width of Extended Uncompressed frames
+    pub width:    u16,
+    pub height:   u8,
+    /// Offset of the image data, without the bit that marks extended widths
+    pub image_data_offset: u32,
+    /// How the image data of the frame is stored
+    pub grp_type: GrpType,
+}
+
+/// Options for reading GRP files
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GrpReadOptions {
+    pub limits: GrpLimits,
+    /// Number of entries that the row offset tables of Normal GRPs are assumed to have,
+    /// for GRPs created by tools that leave out the last rows. The remaining rows of a
+    /// frame are assumed to follow directly after each other.
+    pub assume_table_rows: Option<u16>,
+    /// Type to read the GRP as, instead of detecting it
+    pub force_grp_type: Option<GrpType>,
+    /// Palette index that the pixels skipped by transparent runs of Normal GRPs get
+    pub transparent_index: u8,
+}
+
+impl From<&Args> for GrpReadOptions {
+    fn from(args: &Args) -> Self {
+        GrpReadOptions {
+            limits: GrpLimits::from(args),
+            assume_table_rows: args.assume_table_rows,
+            force_grp_type: args.force_grp_type,
+            transparent_index: args.transparent_index,
+        }
+    }
+}
+
+/// Options for writing GRP files
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GrpWriteOptions {
+    /// Treat all frames as having the canvas size of the first frame
+    pub canvas_from_first: bool,
+    /// Don't encode the transparent runs at the end of rows of RLE compressed GRPs
+    pub omit_trailing_transparent: bool,
+    /// Let frames of Uncompressed GRPs share image data with identical frames, even if their
+    /// offsets differ
+    pub share_data_across_offsets: bool,
+    /// Palette index of the transparent pixels
+    pub transparent_index: u8,
+    /// Pad RLE compressed GRPs with zero bytes until their size is a multiple of this
+    pub pad_to: Option<u32>,
+}
+
+impl From<&Args> for GrpWriteOptions {
+    fn from(args: &Args) -> Self {
+        GrpWriteOptions {
+            canvas_from_first: args.canvas_from_first,
+            omit_trailing_transparent: args.omit_trailing_transparent,
+            share_data_across_offsets: args.share_data_across_offsets,
+            transparent_index: args.transparent_index,
+            pad_to: args.pad_to,
+        }
+    }
+}
+
+/// Options for reading PNGs as frames to encode into a GRP
+#[derive(Clone, Copy, Debug)]
+struct FrameReadOptions<'a> {
+    /// Edges of the PNGs that transparent rows and columns are trimmed away from
+    trim: TrimEdges,
+    /// How colours are mapped to the palette
+    mapping: ColourMapping,
+    /// Offsets and dimensions to cut the frames out of the PNGs at, instead of trimming them
+    frame_records: Option<&'a [FrameRecord]>,
+    /// Give the frames the offsets in the names of the PNGs, instead of trimming them at the
+    /// top and left
+    offsets_in_filenames: bool,
+}
+
+impl Default for FrameReadOptions<'_> {
+    fn default() -> Self {
+        FrameReadOptions {
+            trim: TrimEdges::ALL,
+            mapping: ColourMapping::default(),
+            frame_records: None,
+            offsets_in_filenames: false,
+        }
+    }
+}
+
+/// A frame to encode into a GRP, as palette indices
+#[derive(Clone, Debug)]
+pub struct FrameInput {
+    /// Name of the frame, used in messages
+    pub name: String,
+    /// The pixels of the frame, with its offsets and canvas size
+    pub image: PalettizedImageWithMetadata<u8, u16>,
+}
+
 #[derive(Hash, Eq, PartialEq)]
 struct FrameDedupKey {
     image_data: Vec<u8>,
@@ -56,17 +209,77 @@ struct FrameDedupKey {
 }
 
 impl GrpFrame {
+    /// The actual width of the frame in pixels, taking extended widths into account
+    pub fn actual_width(&self) -> u16 {
+        if self.image_data.grp_type == GrpType::UncompressedExtended {
+            self.width as u16 + EXTENDED_IMAGE_WIDTH
+        } else {
+            self.width as u16
+        }
+    }
+
     /// The length of the frame in bytes, as it would be written to a GRP file
-    fn grp_frame_len(&self) -> usize {
+    pub(crate) fn grp_frame_len(&self) -> usize {
         let row_offsets_size     = self.image_data.row_offsets.len() * 2; // u16 = 2 bytes
         let raw_data_size: usize = self.image_data.raw_row_data.iter().map(|row| row.len()).sum();
         row_offsets_size + raw_data_size
     }
 }
 
+impl GrpFile {
+    /// Reads a whole GRP, detecting what type of GRP it is. Warns if the frames use palette
+    /// indices that the given palette has no entries for.
+    pub fn read<R: Read + Seek>(reader: &mut R, palette: &[[u8; 3]]) -> Result<GrpFile> {
+        GrpFile::read_with_options(reader, palette, &GrpReadOptions::default())
+    }
+
+    /// Reads a whole GRP like [`GrpFile::read`], with the given options
+    pub fn read_with_options<R: Read + Seek>(reader: &mut R, palette: &[[u8; 3]], options: &GrpReadOptions) -> Result<GrpFile> {
+        GrpFile::read_named(reader, "the GRP data", palette, options)
+    }
+
+    /// Reads a whole GRP like [`GrpFile::read_with_options`]. The name is used in messages.
+    fn read_named<R: Read + Seek>(reader: &mut R, name: &str, palette: &[[u8; 3]], options: &GrpReadOptions) -> Result<GrpFile> {
+        let grp = read_grp(reader, name, options)?;
+
+        let used_indices: HashSet<u8> = grp.frames()
+            .flat_map(|frame| frame.image_data.converted_pixels.iter().copied())
+            .collect();
+        let mut missing_indices = indices_missing_from_palette(&used_indices, palette.len());
+        if !missing_indices.is_empty() {
+            missing_indices.sort();
+            warn!(
+                "The frames of {} use palette indices that the palette has no entries for: {:?}",
+                name, missing_indices,
+            );
+        }
+        Ok(grp)
+    }
+
+    /// Writes the GRP with the given compression type, which decides whether the header is in
+    /// WarCraft I style. The frames are written as they are, so they must have been encoded
+    /// with that compression type.
+    pub fn write<W: Write>(&self, writer: &mut W, compression_type: &CompressionType) -> Result<()> {
+        writer.write_all(&grp_to_bytes(&self.header, &self.frames, compression_type)?)
+    }
+
+    /// The frames of the GRP, in order
+    pub fn frames(&self) -> std::slice::Iter<'_, GrpFrame> {
+        self.frames.iter()
+    }
+}
+
 /// Parses the header of a GRP file. Returns the header and whether
 /// it was in WarCraft I style or not.
 pub fn read_grp_header<R: Read + Seek>(file: &mut R) -> Result<(GrpHeader, bool)> {
+    read_grp_header_as(file, None)
+}
+
+/// Parses the header of a GRP file. Whether it is in WarCraft I style is detected, unless given
+/// by forced_war1_style, in which case it is only checked that the frame headers can be read in
+/// that style. Returns the header and whether it was in WarCraft I style or not.
+fn read_grp_header_as<R: Read + Seek>(file: &mut R, forced_war1_style: Option<bool>) -> Result<(GrpHeader, bool)> {
+    file.seek(SeekFrom::Start(0))?;
     let mut buf = [0u8; 8];
     file.read_exact(&mut buf)?;
 
@@ -76,12 +289,27 @@ pub fn read_grp_header<R: Read + Seek>(file: &mut R) -> Result<(GrpHeader, bool)
     let max_width       = u16::from_le_bytes([buf[2], buf[3]]);
     let max_height      = u16::from_le_bytes([buf[4], buf[5]]);
 
-    let war1_style = determine_grp_style(
-        file,
-        frame_count,
-        war1_max_width,
-        war1_max_height,
-    )?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    check_plausible_frame_count(frame_count, file_len)?;
+
+    let war1_style = match forced_war1_style {
+        Some(war1_style) => {
+            try_reading_frame_headers(file, frame_count, get_header_size(war1_style)).map_err(|e| Error::new(
+                ErrorKind::InvalidData,
+                format!("The frame headers cannot be read as {} style: {}", if war1_style { "WarCraft I" } else { "StarCraft" }, e),
+            ))?;
+            war1_style
+        },
+        None => determine_grp_style(
+            file,
+            frame_count,
+            war1_max_width,
+            war1_max_height,
+        )?,
+    };
+    if !war1_style {
+        check_plausible_dimensions(max_width, max_height)?;
+    }
 
     let header = if !war1_style {
         GrpHeader {
@@ -104,6 +332,47 @@ pub fn read_grp_header<R: Read + Seek>(file: &mut R) -> Result<(GrpHeader, bool)
     Ok((header, war1_style))
 }
 
+/// Returns an error if the file is too small to hold the frame headers of the given number of
+/// frames. That is a sign of a file with the wrong byte order, or of a file that is not a GRP.
+fn check_plausible_frame_count(frame_count: u16, file_len: u64) -> Result<()> {
+    let max_frame_count = file_len.saturating_sub(get_header_size(true) as u64) / 8;
+    if frame_count as u64 <= max_frame_count {
+        return Ok(());
+    }
+    let swapped = frame_count.swap_bytes();
+    let hint = if swapped != 0 && swapped as u64 <= max_frame_count {
+        format!("Read with the opposite byte order, the frame count would be {}, so the file may be big-endian", swapped)
+    } else {
+        "The file may not be a GRP".to_string()
+    };
+    Err(Error::new(ErrorKind::InvalidData, format!(
+        "The header claims that there are {} frames, but the file is only {} bytes, which is too \
+        small for more than {} frame headers. {}",
+        frame_count, file_len, max_frame_count, hint,
+    )))
+}
+
+/// Returns an error if the maximum dimensions in the header are implausibly large. That is a
+/// sign of a file with the wrong byte order, or of a file that is not a GRP.
+fn check_plausible_dimensions(max_width: u16, max_height: u16) -> Result<()> {
+    if max_width <= MAX_PLAUSIBLE_DIMENSION && max_height <= MAX_PLAUSIBLE_DIMENSION {
+        return Ok(());
+    }
+    let (swapped_width, swapped_height) = (max_width.swap_bytes(), max_height.swap_bytes());
+    let hint = if swapped_width <= MAX_PLAUSIBLE_DIMENSION && swapped_height <= MAX_PLAUSIBLE_DIMENSION {
+        format!(
+            "Read with the opposite byte order, the dimensions would be {}x{}, so the file may be big-endian",
+            swapped_width, swapped_height,
+        )
+    } else {
+        "The file may not be a GRP".to_string()
+    };
+    Err(Error::new(ErrorKind::InvalidData, format!(
+        "The header claims that the maximum frame dimensions are {}x{}, which is implausibly large. {}",
+        max_width, max_height, hint,
+    )))
+}
+
 /// Returns true if the GRP is in War1 style, false otherwise.
 /// If it appears to not be a GRP, it throws an error.
 fn determine_grp_style<R: Read + Seek>(
@@ -141,7 +410,7 @@ fn try_reading_frame_headers<R: Read + Seek>(
 
     let file_len = file.seek(SeekFrom::End(0))?;
     for i in 0..frame_count {
-        file.seek(SeekFrom::Start(start_pos as u64 + (i * 8) as u64))?;
+        file.seek(SeekFrom::Start(start_pos as u64 + i as u64 * 8))?;
         let mut buf = [0u8; 8];
         file.read_exact(&mut buf)?;
 
@@ -153,10 +422,10 @@ fn try_reading_frame_headers<R: Read + Seek>(
         let (width, offset) = adjust_width_and_offset_if_extended_when_decoding(w, image_data_offset);
 
         if width == 0 || height == 0 {
-            return Err(Error::new(ErrorKind::Other, "Frame width or height is zero"));
+            return Err(Error::other("Frame width or height is zero"));
         }
         if offset > file_len as u32 {
-            return Err(Error::new(ErrorKind::Other, "Image data offset is too large"));
+            return Err(Error::other("Image data offset is too large"));
         }
     }
     Ok(())
@@ -175,7 +444,7 @@ fn adjust_width_and_offset_if_extended_when_decoding(width: u8, image_data_offse
         // If the high bit is set, that means that the frame of the
         // Uncompressed GRP has a width greater than 256 pixels.
 
-        let offset = image_data_offset & EXTENDED_OFFSET_BIT - 1; // clear the highest bit
+        let offset = image_data_offset & (EXTENDED_OFFSET_BIT - 1); // clear the highest bit
         return (width as u16 + EXTENDED_IMAGE_WIDTH, offset)
     };
     (width as u16, image_data_offset)
@@ -189,81 +458,192 @@ fn adjust_width_and_offset_if_extended_when_encoding(width: u16, offset: u32) ->
 }
 
 
-/// Parses all GRP frames
+/// Verifies that the frame count and the frame headers of the GRP are within the given limits.
+/// Only the frame headers are read, so this is cheap to do before reading the frames themselves.
+pub fn check_grp_limits<R: Read + Seek>(
+    file: &mut R,
+    header: &GrpHeader,
+    grp_type: GrpType,
+    limits: &GrpLimits,
+) -> Result<()> {
+
+    if header.frame_count > limits.max_frames {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The GRP claims to have {} frames, but at most {} are allowed",
+            header.frame_count, limits.max_frames,
+        )));
+    }
+
+    let pos = get_header_size(grp_type == GrpType::War1) as u64;
+    for i in 0..header.frame_count {
+        file.seek(SeekFrom::Start(pos + (i as u64 * 8)))?;
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf)?;
+
+        let image_data_offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let width = if grp_type != GrpType::Normal {
+            adjust_width_and_offset_if_extended_when_decoding(buf[2], image_data_offset).0
+        } else {
+            buf[2] as u16
+        };
+        let frame_bytes = width as usize * buf[3] as usize;
+        if frame_bytes > limits.max_frame_bytes {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Frame {} is {}x{} pixels ({} bytes), but at most {} bytes per frame are allowed",
+                i, width, buf[3], frame_bytes, limits.max_frame_bytes,
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the frame headers of all frames of a GRP of the given type, without reading or decoding
+/// any image data.
+pub fn read_frame_headers<R: Read + Seek>(file: &mut R, frame_count: u16, grp_type: GrpType) -> Result<Vec<FrameHeader>> {
+    file.seek(SeekFrom::Start(get_header_size(grp_type == GrpType::War1) as u64))?;
+    let mut frame_headers = Vec::with_capacity(frame_count as usize);
+    for _ in 0..frame_count {
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf)?;
+
+        let image_data_offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let (width, offset, frame_type) = if grp_type == GrpType::Normal {
+            (buf[2] as u16, image_data_offset, grp_type)
+        } else {
+            let (width, offset) = adjust_width_and_offset_if_extended_when_decoding(buf[2], image_data_offset);
+            let frame_type = if offset_is_extended(image_data_offset) { GrpType::UncompressedExtended } else { grp_type };
+            (width, offset, frame_type)
+        };
+        frame_headers.push(FrameHeader {
+            x_offset: buf[0],
+            y_offset: buf[1],
+            width,
+            height: buf[3],
+            image_data_offset: offset,
+            grp_type: frame_type,
+        });
+    }
+    Ok(frame_headers)
+}
+
+/// Parses all GRP frames. See [`GrpReadOptions`] for `assume_table_rows` and `transparent_index`.
 pub fn read_grp_frames<R: Read + Seek>(
     file: &mut R,
     frame_count: u16,
     grp_type: GrpType,
+    options: &GrpReadOptions,
 ) -> Result<Vec<GrpFrame>> {
 
-    let pos = get_header_size(grp_type ==  GrpType::War1) as u64;
+    let frame_headers = read_frame_headers(file, frame_count, grp_type)?;
     let mut frames = Vec::new();
     for i in 0..frame_count {
         debug!("Reading GRP Frame {} / {}", i, frame_count);
-        file.seek(SeekFrom::Start(pos + (i * 8) as u64))?;
-        let mut buf = [0u8; 8];
-        file.read_exact(&mut buf)?;
+        frames.push(read_frame(file, i, grp_type, options, &frame_headers)?);
+    }
+    Ok(frames)
+}
 
-        let image_data_offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let width  = buf[2];
-        let height = buf[3];
+/// Reads the frame with the given index from a GRP of the given type, along with its image data
+pub fn read_single_frame<R: Read + Seek>(
+    file: &mut R,
+    i: u16,
+    grp_type: GrpType,
+    options: &GrpReadOptions,
+) -> Result<GrpFrame> {
 
-        let image_data = if grp_type != GrpType::Normal {
+    file.seek(SeekFrom::Start(0))?;
+    let mut frame_count = [0u8; 2];
+    file.read_exact(&mut frame_count)?;
+    let frame_headers = read_frame_headers(file, u16::from_le_bytes(frame_count), grp_type)?;
+    read_frame(file, i, grp_type, options, &frame_headers)
+}
 
-            let (w, offset) = adjust_width_and_offset_if_extended_when_decoding(width, image_data_offset);
-            let has_extended_size = offset_is_extended(image_data_offset);
-            if  has_extended_size {
-                debug!(
-                    "Reading Uncompressed frame {} with extended size. Width in file: {}, \
-                    actual width: {}. Offset in file: 0x{:0>2X}, actual offset: 0x{:0>2X}",
-                    i, width, w, image_data_offset, offset,
-                );
-            }
+/// Offset where the image data at `image_data_offset` ends, which is where the image data of the
+/// frame following it begins, or the end of the file for the last frame
+fn frame_data_end(frame_headers: &[FrameHeader], image_data_offset: u32, file_len: u64) -> u64 {
+    frame_headers.iter()
+        .map(|frame| frame.image_data_offset as u64)
+        .filter(|&offset| offset > image_data_offset as u64)
+        .min()
+        .map_or(file_len, |offset| offset.min(file_len))
+}
 
-            let compression_type = if has_extended_size {
-                // There does not seem to be any War1 GRPs with extended size.
-                // The code here needs to be changed if there are.
-                GrpType::UncompressedExtended
-            } else {
-                grp_type // Uncompressed or War1
-            };
-            read_uncompressed_image_data(
-                file,
-                w,
-                height,
-                offset,
-                compression_type,
-            )?
+/// Reads the frame with the given index, given the headers of all frames of the GRP
+fn read_frame<R: Read + Seek>(
+    file: &mut R,
+    i: u16,
+    grp_type: GrpType,
+    options: &GrpReadOptions,
+    frame_headers: &[FrameHeader],
+) -> Result<GrpFrame> {
+
+    let pos = get_header_size(grp_type == GrpType::War1) as u64;
+    file.seek(SeekFrom::Start(pos + i as u64 * 8))?;
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf)?;
+
+    let image_data_offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    let width  = buf[2];
+    let height = buf[3];
+
+    let image_data = if grp_type != GrpType::Normal {
+
+        let (w, offset) = adjust_width_and_offset_if_extended_when_decoding(width, image_data_offset);
+        let has_extended_size = offset_is_extended(image_data_offset);
+        if  has_extended_size {
+            debug!(
+                "Reading Uncompressed frame {} with extended size. Width in file: {}, \
+                actual width: {}. Offset in file: 0x{:0>2X}, actual offset: 0x{:0>2X}",
+                i, width, w, image_data_offset, offset,
+            );
+        }
+
+        let compression_type = if has_extended_size {
+            // There does not seem to be any War1 GRPs with extended size.
+            // The code here needs to be changed if there are.
+            GrpType::UncompressedExtended
         } else {
-            read_image_data(
-                file,
-                width  as u16,
-                height as u16,
-                image_data_offset,
-            )?
+            grp_type // Uncompressed or War1
         };
-
-        let grp_frame = GrpFrame {
-            x_offset: buf[0],
-            y_offset: buf[1],
-            width,
+        read_uncompressed_image_data(
+            file,
+            w,
             height,
+            offset,
+            compression_type,
+        )?
+    } else {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        read_image_data(
+            file,
+            width  as u16,
+            height as u16,
             image_data_offset,
-            image_data,
-        };
-        frames.push(grp_frame.clone());
-        debug!(
-            "Read GRP Frame {}. x-offset: 0x{:0>2X} ({}), y-offset: 0x{:0>2X} ({}), \
-            width: 0x{:0>2X} ({}), height: 0x{:0>2X} ({}), image-data-offset: 0x{:0>4X} ({}), \
-            number of pixels: {}",
-            i, grp_frame.x_offset, grp_frame.x_offset, grp_frame.y_offset, grp_frame.y_offset,
-            grp_frame.width, grp_frame.width, grp_frame.height, grp_frame.height,
-            grp_frame.image_data_offset, grp_frame.image_data_offset,
-            grp_frame.image_data.converted_pixels.len(),
-        );
-        debug!(""); // Give some space in the logs
-    }
-    Ok(frames)
+            frame_data_end(frame_headers, image_data_offset, file_len),
+            options.assume_table_rows,
+            options.transparent_index,
+        )?
+    };
+
+    let grp_frame = GrpFrame {
+        x_offset: buf[0],
+        y_offset: buf[1],
+        width,
+        height,
+        image_data_offset,
+        image_data,
+    };
+    debug!(
+        "Read GRP Frame {}. x-offset: 0x{:0>2X} ({}), y-offset: 0x{:0>2X} ({}), \
+        width: 0x{:0>2X} ({}), height: 0x{:0>2X} ({}), image-data-offset: 0x{:0>4X} ({}), \
+        number of pixels: {}",
+        i, grp_frame.x_offset, grp_frame.x_offset, grp_frame.y_offset, grp_frame.y_offset,
+        grp_frame.width, grp_frame.width, grp_frame.height, grp_frame.height,
+        grp_frame.image_data_offset, grp_frame.image_data_offset,
+        grp_frame.image_data.converted_pixels.len(),
+    );
+    debug!(""); // Give some space in the logs
+    Ok(grp_frame)
 }
 
 /// Reads row offsets and decodes image data
@@ -283,7 +663,7 @@ fn read_uncompressed_image_data<R: Read + Seek>(
         return Err(Error::new(
             ErrorKind::UnexpectedEof,
             format!("Wanted to read {} bytes, but only {} are available in file",
-                    width * height as u16, data_len,
+                    width as u64 * height as u64, data_len,
             ),
         ));
     }
@@ -312,16 +692,25 @@ fn read_uncompressed_pixels(width: u16, height: u16, pixels: Vec<u8>) -> Vec<Vec
     raw_row_data
 }
 
-/// Reads row offsets and decodes image data
+/// Reads row offsets and decodes image data, which ends at `data_end`. If `assume_table_rows` is
+/// given and smaller than the height, only that many row offsets are read, and the remaining rows
+/// are assumed to follow directly after each other. Pixels skipped by transparent runs get
+/// `transparent_index`.
+///
+/// Each row is decoded from the data up to where the next row in the table starts, or up to
+/// `data_end` for the last one, so that rows whose trailing transparent run has been omitted are
+/// padded with transparent pixels rather than continuing into the data of other rows.
 fn read_image_data<R: Read + Seek>(
     file:   &mut R,
     width:  u16,
     height: u16,
     image_data_offset: u32,
+    data_end: u64,
+    assume_table_rows: Option<u16>,
+    transparent_index: u8,
 ) -> Result<ImageData> {
 
-    let file_len = file.seek(SeekFrom::End(0))?;
-    let data_len = file_len
+    let data_len = data_end
         .checked_sub(image_data_offset as u64)
         .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "image_data_offset beyond file length"))?;
 
@@ -331,8 +720,9 @@ fn read_image_data<R: Read + Seek>(
     file.read_exact(&mut data_block)?;
 
     // Parse row offsets from the beginning of data_block
+    let table_rows = assume_table_rows.map_or(height, |rows| rows.min(height));
     let mut row_offsets = Vec::with_capacity(height as usize);
-    for i in 0..height {
+    for i in 0..table_rows {
         let offset_start = (i * 2) as usize;
         if  offset_start + 2 > data_block.len() {
             return Err(Error::new(
@@ -343,34 +733,42 @@ fn read_image_data<R: Read + Seek>(
         let row_offset = u16::from_le_bytes([data_block[offset_start], data_block[offset_start + 1]]);
         row_offsets.push(row_offset);
     }
+    check_row_offset_table(&row_offsets, image_data_offset);
+    let table_offsets = row_offsets.clone();
 
-    let mut raw_row_data = Vec::with_capacity(height as usize);
+    let mut raw_row_data: Vec<Vec<u8>> = Vec::with_capacity(height as usize);
     let mut pixels = vec![0; (width * height) as usize];
 
-    for (row, &row_offset) in row_offsets.iter().enumerate() {
+    for row in 0..height as usize {
+        if row >= row_offsets.len() {
+            // Not in the table, so assumed to follow directly after the previous row
+            let next_offset = match row {
+                0 => 0,
+                _ => row_offsets[row - 1] as usize + raw_row_data[row - 1].len(),
+            };
+            row_offsets.push(u16::try_from(next_offset).map_err(|_| Error::new(
+                ErrorKind::InvalidData,
+                format!("Row {} would start at offset {}, which is too far from the row offset table", row, next_offset),
+            ))?);
+        }
+        let row_offset = row_offsets[row];
         if row_offset as usize >= data_block.len() {
             return Err(Error::new(
                 ErrorKind::UnexpectedEof,
                 format!("Row data offset {} is beyond end of data_block ({})", row_offset, data_block.len()),
             ));
         }
-        let row_data = &data_block[row_offset as usize ..];
+        let row_end = table_offsets.iter()
+            .filter(|&&offset| offset > row_offset)
+            .min()
+            .map_or(data_block.len(), |&offset| (offset as usize).min(data_block.len()));
+        let row_data = &data_block[row_offset as usize .. row_end];
         debug!(
             "Decoding row {} of width {} from offset {} (length {})",
             row, width, row_offset, row_data.len(),
         );
 
-        let (decoded_row, encoded_length) = decode_grp_rle_row(row_data, width);
-
-        if row_offset as usize + encoded_length > data_block.len() {
-            return Err(Error::new(
-                ErrorKind::UnexpectedEof, format!(
-                    "Row {} encoded length goes beyond buffer: {} + {} > {}",
-                    row, row_offset, encoded_length, data_block.len(),
-                ),
-            ));
-        }
-
+        let (decoded_row, encoded_length) = decode_grp_rle_row(row_data, width, transparent_index);
         raw_row_data.push(row_data[..encoded_length].to_vec());
 
         let start = row * width as usize;
@@ -385,9 +783,54 @@ fn read_image_data<R: Read + Seek>(
     })
 }
 
+/// Rows cannot start inside of the row offset table. If any does, the table is likely shorter
+/// than the frame height, with the following row data being mistaken for row offsets, or the
+/// table is broken. Either way, a warning is logged, since the frame may still be readable.
+fn check_row_offset_table(row_offsets: &[u16], image_data_offset: u32) {
+    let table_size = row_offsets.len() * 2;
+    if let Some(&first_row) = row_offsets.iter().min().filter(|&&first_row| (first_row as usize) < table_size) {
+        let hint = if first_row > 0 && first_row % 2 == 0 {
+            format!(
+                " The table probably has only {} entries; try '--assume-table-rows {}'.",
+                first_row / 2, first_row / 2,
+            )
+        } else {
+            "".to_string()
+        };
+        warn!(
+            "The row offset table at 0x{:0>4X} has {} entries, but claims that a row starts at \
+            offset {}, which is inside of the table.{} If the table is broken, the 'fix-row-offsets' \
+            mode can rewrite it.",
+            image_data_offset, row_offsets.len(), first_row, hint,
+        );
+    }
+}
+
+/// Decodes one RLE-compressed row of a Normal GRP frame, of the given width in pixels.
+///
+/// Returns the palette indices of the row, which always has `width` entries, with pixels not
+/// covered by the data getting `transparent_index`. Also returns the number of bytes of `bytes` that
+/// were consumed, so that the caller can tell where the row ends. Decoding stops as soon as the
+/// row is filled, so any bytes after that are not consumed.
+///
+/// Malformed data does not cause an error; decoding stops when the data ends, and pixels that
+/// would end up outside of the row are dropped. A control byte of 0 (copy 0 pixels) is stepped
+/// over together with the byte following it. The consumed length is never larger than the length
+/// of `bytes`, even if the final run of the row is truncated.
+pub fn decode_row(bytes: &[u8], width: u16, transparent_index: u8) -> (Vec<u8>, usize) {
+    decode_grp_rle_row(bytes, width, transparent_index)
+}
+
+/// Encodes one row of palette indices into the RLE format of Normal GRP frames, the inverse
+/// of [`decode_row`]. Pixels with `transparent_index` are encoded as transparent runs.
+/// With `CompressionType::Optimised`, the encoding may be shorter.
+pub fn encode_row(pixels: &[u8], compression_type: &CompressionType, transparent_index: u8) -> Vec<u8> {
+    encode_grp_rle_row(pixels, compression_type, transparent_index)
+}
+
 /// Decodes an RLE-compressed row of pixels
-fn decode_grp_rle_row(line_data: &[u8], image_width: u16) -> (Vec<u8>, usize) {
-    let mut line_pixels = vec![0; image_width as usize]; // Initialize with transparent pixels (palette index 0)
+fn decode_grp_rle_row(line_data: &[u8], image_width: u16, transparent_index: u8) -> (Vec<u8>, usize) {
+    let mut line_pixels = vec![transparent_index; image_width as usize]; // Initialize with transparent pixels
     let mut x = 0; // Position in output row
     let mut data_offset = 0; // Position in input data
 
@@ -467,20 +910,21 @@ fn decode_grp_rle_row(line_data: &[u8], image_width: u16) -> (Vec<u8>, usize) {
         }
     }
 
-    (line_pixels, data_offset)
+    // A truncated final run may have stepped past the end of the data
+    (line_pixels, data_offset.min(line_data.len()))
 }
 
 
-/// Encodes an RLE-compressed row of pixels
-fn encode_grp_rle_row(row_pixels: &[u8], compression_type: &CompressionType) -> Vec<u8> {
+/// Encodes an RLE-compressed row of pixels, where pixels with transparent_index are transparent
+fn encode_grp_rle_row(row_pixels: &[u8], compression_type: &CompressionType, transparent_index: u8) -> Vec<u8> {
     let mut encoded = Vec::new();
     let mut i = 0;
 
     debug!("Beginning to encode using compression type '{}'", compression_type);
-    for x in 0..row_pixels.len() {
+    for (x, pixel) in row_pixels.iter().enumerate() {
         trace!(
             "x: {:2}, row_pixels[i]: {:2X} ({:3})",
-            x, row_pixels[x], row_pixels[x],
+            x, pixel, pixel,
         );
     }
 
@@ -503,10 +947,10 @@ fn encode_grp_rle_row(row_pixels: &[u8], compression_type: &CompressionType) ->
             "Encoding pixel at position {} / {} with palette index {}",
             i, row_pixels.len(), current_colour,
         );
-        // Case 1: Transparent run (index 0)
-        if current_colour == 0 {
+        // Case 1: Transparent run
+        if current_colour == transparent_index {
             let mut run_len = 1;
-            while i + run_len < row_pixels.len() && row_pixels[i + run_len] == 0 && run_len < 127 {
+            while i + run_len < row_pixels.len() && row_pixels[i + run_len] == transparent_index && run_len < 127 {
                 run_len += 1;
             }
             trace!(
@@ -542,17 +986,17 @@ fn encode_grp_rle_row(row_pixels: &[u8], compression_type: &CompressionType) ->
                 let mut last_colour_len = 0;
 
                 // Go through the row until we find a run of same coloured pixels above the threshold
-                for x in i..row_pixels.len() {
+                for (x, &pixel) in row_pixels.iter().enumerate().skip(i) {
                     trace!(
                         "Encoding literal copy. x: {:2}, row_pixels[i]: {:2X} ({:3})",
-                        x, row_pixels[x], row_pixels[x],
+                        x, pixel, pixel,
                     );
-                    if row_pixels[x] == 0 {
+                    if pixel == transparent_index {
                         break;
                     }
-                    if row_pixels[x] != last_colour || last_colour_len == 0 {
+                    if pixel != last_colour || last_colour_len == 0 {
                         // New pixel or first pixel
-                        last_colour = row_pixels[x];
+                        last_colour = pixel;
                         last_colour_len = 1;
                     } else {
                         // Repetition of last seen pixel
@@ -583,8 +1027,38 @@ fn encode_grp_rle_row(row_pixels: &[u8], compression_type: &CompressionType) ->
     encoded
 }
 
-/// Encodes pixels to an RLE-compressed ImageData
-fn encode_grp_rle_data(width: u16, height: u16, pixels: Vec<u8>, compression_type: &CompressionType) -> ImageData {
+/// Removes the transparent run at the end of an encoded row, if there is one. The decoder
+/// leaves pixels that the row doesn't reach transparent, so the row decodes the same. Rows
+/// that are entirely transparent are left as they are, so that no row becomes empty.
+fn omit_trailing_transparent_run(encoded_row: &mut Vec<u8>) {
+    let mut pos = 0;
+    let mut trailing_transparent_start = None;
+    while pos < encoded_row.len() {
+        let control_byte = encoded_row[pos];
+        if control_byte & 0x80 != 0 {
+            trailing_transparent_start.get_or_insert(pos);
+            pos += 1;
+        } else {
+            trailing_transparent_start = None;
+            pos += if control_byte & 0x40 != 0 { 2 } else { 1 + control_byte as usize };
+        }
+    }
+    if let Some(start) = trailing_transparent_start.filter(|&start| start > 0) {
+        encoded_row.truncate(start);
+    }
+}
+
+/// Encodes pixels to an RLE-compressed ImageData, where pixels with transparent_index are
+/// transparent. If omit_trailing_transparent is true, transparent runs at the end of the rows
+/// are not encoded.
+fn encode_grp_rle_data(
+    width:  u16,
+    height: u16,
+    pixels: Vec<u8>,
+    compression_type: &CompressionType,
+    omit_trailing_transparent: bool,
+    transparent_index: u8,
+) -> ImageData {
     let mut raw_row_data = Vec::new();
     let mut rle_data     = Vec::new();
     let mut row_offsets  = Vec::with_capacity(height as usize);
@@ -601,7 +1075,10 @@ fn encode_grp_rle_data(width: u16, height: u16, pixels: Vec<u8>, compression_typ
             "Encoding row {} / {} of width {}. Start: {}, End: {}",
             row, height, width, start, end,
         );
-        let encoded_row = encode_grp_rle_row(row_pixels, compression_type);
+        let mut encoded_row = encode_grp_rle_row(row_pixels, compression_type, transparent_index);
+        if omit_trailing_transparent {
+            omit_trailing_transparent_run(&mut encoded_row);
+        }
 
         rle_data.extend_from_slice(&encoded_row);
         raw_row_data.push(encoded_row.clone());
@@ -616,6 +1093,40 @@ fn encode_grp_rle_data(width: u16, height: u16, pixels: Vec<u8>, compression_typ
     }
 }
 
+/// Finds the smallest number of bytes, including the row offset table, that the given pixels
+/// can be RLE encoded to. Both compression types are tried, with and without encoding transparent
+/// runs at the end of rows. Identical rows are only counted once, since the row offsets of a frame
+/// can point to the same data. Returns the size and a description of the encoding giving it.
+pub(crate) fn smallest_rle_encoding(width: u16, height: u16, pixels: &[u8], transparent_index: u8) -> (usize, String) {
+    let mut smallest: Option<(usize, String)> = None;
+    for compression_type in [CompressionType::Normal, CompressionType::Optimised] {
+        for omit_trailing_transparent in [false, true] {
+            let unique_rows: HashSet<Vec<u8>> = pixels
+                .chunks(width.max(1) as usize)
+                .take(height as usize)
+                .map(|row_pixels| {
+                    let mut encoded_row = encode_grp_rle_row(row_pixels, &compression_type, transparent_index);
+                    if omit_trailing_transparent {
+                        omit_trailing_transparent_run(&mut encoded_row);
+                    }
+                    encoded_row
+                })
+                .collect();
+            let size = height as usize * 2 + unique_rows.iter().map(|row| row.len()).sum::<usize>();
+
+            if smallest.as_ref().is_none_or(|(smallest_size, _)| size < *smallest_size) {
+                let description = if omit_trailing_transparent {
+                    format!("{}, omitting trailing transparent runs", compression_type)
+                } else {
+                    compression_type.to_string()
+                };
+                smallest = Some((size, description));
+            }
+        }
+    }
+    smallest.unwrap()
+}
+
 /// Encodes pixels to an uncompressed ImageData
 fn encode_uncompressed_grp(width: u16, height: u16, pixels: Vec<u8>, extended_width: bool) -> ImageData {
 
@@ -647,28 +1158,28 @@ fn create_grp_header(frames: &[GrpFrame], max_width: u16, max_height: u16) -> Gr
 }
 
 
-/// Given a path, GrpHeader and a set of GrpFrames, this function writes a GRP file
-/// to the given path.
-fn write_grp_file(path: &str, header: &GrpHeader, frames: &[GrpFrame], compression_type: &CompressionType) -> Result<()> {
-    let mut file = File::create(path)?;
+/// Given a GrpHeader and a set of GrpFrames, this function returns the bytes of the GRP file
+fn grp_to_bytes(header: &GrpHeader, frames: &[GrpFrame], compression_type: &CompressionType) -> Result<Vec<u8>> {
+    verify_extended_offset_bits(frames)?;
+    let mut bytes = Vec::new();
 
     // Write header
-    file.write_all(&header.frame_count.to_le_bytes())?;
+    bytes.extend_from_slice(&header.frame_count.to_le_bytes());
     if compression_type == &CompressionType::War1 {
-        file.write_all(&[header.max_width  as u8])?;
-        file.write_all(&[header.max_height as u8])?;
+        bytes.push(header.max_width  as u8);
+        bytes.push(header.max_height as u8);
     } else {
-        file.write_all(&header.max_width .to_le_bytes())?;
-        file.write_all(&header.max_height.to_le_bytes())?;
+        bytes.extend_from_slice(&header.max_width .to_le_bytes());
+        bytes.extend_from_slice(&header.max_height.to_le_bytes());
     }
 
     // Write frame headers
     for frame in frames {
-        file.write_all(&[frame.x_offset])?;
-        file.write_all(&[frame.y_offset])?;
-        file.write_all(&[frame.width])?;
-        file.write_all(&[frame.height])?;
-        file.write_all(&frame.image_data_offset.to_le_bytes())?;
+        bytes.push(frame.x_offset);
+        bytes.push(frame.y_offset);
+        bytes.push(frame.width);
+        bytes.push(frame.height);
+        bytes.extend_from_slice(&frame.image_data_offset.to_le_bytes());
     }
 
     // Frames that share the same image_data_offset are duplicated frames.
@@ -676,30 +1187,76 @@ fn write_grp_file(path: &str, header: &GrpHeader, frames: &[GrpFrame], compressi
     let mut written_frames = HashSet::new();
 
     // Write image data
-    for frame in frames {
+    for (i, frame) in frames.iter().enumerate() {
         if written_frames.insert(&frame.image_data_offset) {
             // This offset hasn't been written yet — do it now.
 
+            // Verify that the image data ends up where the frame header says it is
+            let position = bytes.len() as u64;
+            let declared = (frame.image_data_offset & !EXTENDED_OFFSET_BIT) as u64;
+            if position != declared {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "The image data of frame {} would be written at offset 0x{:0>4X} ({}), \
+                    but its frame header declares offset 0x{:0>4X} ({})",
+                    i, position, position, declared, declared,
+                )));
+            }
+
             // Write row offset table
             for &offset in &frame.image_data.row_offsets {
-                file.write_all(&offset.to_le_bytes())?;
+                bytes.extend_from_slice(&offset.to_le_bytes());
             }
 
             // Write each row's raw RLE data
             for row in &frame.image_data.raw_row_data {
-                file.write_all(row)?;
+                bytes.extend_from_slice(row);
             }
         }
     }
 
+    Ok(bytes)
+}
+
+/// Verifies that only frames with extended widths have the extended bit set in their image data
+/// offsets. Any other frame with the bit set would be read back as being 256 pixels wider than it
+/// is, at an offset 2 GiB earlier.
+fn verify_extended_offset_bits(frames: &[GrpFrame]) -> Result<()> {
+    for (i, frame) in frames.iter().enumerate() {
+        let is_extended = frame.image_data.grp_type == GrpType::UncompressedExtended;
+        if offset_is_extended(frame.image_data_offset) && !is_extended {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "The image data of frame {} is at offset 0x{:0>8X}, which collides with the bit \
+                marking frames with extended widths. Image data can be at offsets up to 0x{:0>8X}",
+                i, frame.image_data_offset, MAX_IMAGE_DATA_OFFSET,
+            )));
+        }
+        if !offset_is_extended(frame.image_data_offset) && is_extended {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Frame {} has an extended width, but the bit marking that is not set in its \
+                image data offset 0x{:0>8X}",
+                i, frame.image_data_offset,
+            )));
+        }
+    }
     Ok(())
 }
 
+/// Appends zero bytes to the end of the GRP, until its size is a multiple of the given alignment
+fn pad_to_alignment(bytes: &mut Vec<u8>, alignment: u32) {
+    let padding = (alignment as usize - bytes.len() % alignment as usize) % alignment as usize;
+    if padding > 0 {
+        debug!("Padding the GRP with {} zero bytes to align it to {} bytes", padding, alignment);
+        bytes.resize(bytes.len() + padding, 0);
+    }
+}
+
 /// Read the PNG in the given file name, and turn it into a GrpFrame
 fn png_to_grpframe(
     image: PalettizedImageWithMetadata<u8, u16>,
     image_data_offset: u32,
     compression: &CompressionType,
+    omit_trailing_transparent: bool,
+    transparent_index: u8,
 ) -> Result<GrpFrame> {
 
     let mut offset = image_data_offset;
@@ -713,9 +1270,12 @@ fn png_to_grpframe(
             // is allowed for Extended Uncompressed GRPs. Here, we're dealing with Normal GRPs,
             // which have a max width of 255.
             return Err(Error::new(ErrorKind::InvalidInput, format!(
-                "Width ({}) is above limit of {}", image.width, u8::MAX)))
+                "Width ({}) is above limit of {} for compression type {}. Wider frames can only be \
+                stored in Uncompressed GRPs, which allow widths of up to {}. Try using \
+                '--compression-type uncompressed' or '--auto-uncompress-wide'",
+                image.width, u8::MAX, compression, 2 * u8::MAX as u16)))
         }
-        encode_grp_rle_data(image.width, image.height, image.palettized_image, compression)
+        encode_grp_rle_data(image.width, image.height, image.palettized_image, compression, omit_trailing_transparent, transparent_index)
 
     } else {
         let extended_width = image_should_be_extended(image.width);
@@ -743,28 +1303,125 @@ fn png_to_grpframe(
     })
 }
 
-/// Turn all the given PNG files into a set of GrpFrames.
-fn files_to_grp(
+/// Reads all the given PNG files as frames to encode into a GRP, named after the files
+fn files_to_frame_inputs(
     png_files: Vec<String>,
-    palette: &Vec<[u8; 3]>,
+    palette: &[[u8; 3]],
+    options: &FrameReadOptions,
+) -> Result<Vec<FrameInput>> {
+    let FrameReadOptions { trim, mapping, frame_records, offsets_in_filenames } = *options;
+
+    // Decode the PNGs in parallel. The frames are then created from them in order, so that the
+    // image data offsets and shared data are the same as when decoding one PNG at a time.
+    png_files
+        .into_par_iter()
+        .enumerate()
+        .map(|(i, png_file)| match frame_records {
+            Some(records) => png_to_frame_pixels(&png_file, palette, mapping, &records[i]),
+            None if offsets_in_filenames => {
+                let (x_offset, y_offset) = offsets_from_file_name(&png_file).ok_or_else(|| Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The name of {} does not end with offsets, like 'frame_003_x12_y8.png'", png_file),
+                ))?;
+                let mut image = png_to_pixels(&png_file, palette, TrimEdges { top: true, left: true, ..trim }, mapping)?;
+                place_at_offsets(&mut image, x_offset, y_offset, mapping.transparent_index, &png_file)?;
+                Ok(image)
+            },
+            None => png_to_pixels(&png_file, palette, trim, mapping),
+        }.map(|image| FrameInput { name: png_file, image }))
+        .collect()
+}
+
+/// Encodes the given frames into the bytes of a GRP file with the given compression type,
+/// without going through any files. Frames with identical pixels share image data.
+pub fn encode_grp(frames: &[FrameInput], compression_type: &CompressionType) -> Result<Vec<u8>> {
+    encode_grp_with_options(frames, compression_type, &GrpWriteOptions::default())
+}
+
+/// Encodes the given frames into the bytes of a GRP file, like [`encode_grp`], with the given options
+pub fn encode_grp_with_options(
+    frames: &[FrameInput],
+    compression_type: &CompressionType,
+    options: &GrpWriteOptions,
+) -> Result<Vec<u8>> {
+    let compression_type = determine_compression_type(&[], compression_type);
+    let is_rle_compressed = compression_type == CompressionType::Normal || compression_type == CompressionType::Optimised;
+    if options.pad_to.is_some() && !is_rle_compressed {
+        // Uncompressed GRPs are recognised by their image data ending exactly at the end of the file
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Only RLE compressed GRPs can be padded, but the GRP has compression type {}", compression_type,
+        )));
+    }
+
+    let images = frames.iter().map(|frame| Ok((frame.name.clone(), frame.image.clone())));
+    let (grp_frames, max_width, max_height) = images_to_grp(images, &compression_type, options)?;
+    if compression_type == CompressionType::War1 && (max_width > u8::MAX as u16 || max_height > u8::MAX as u16) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "The canvas is {}x{}, but WarCraft I GRPs have canvases of at most {}x{}",
+            max_width, max_height, u8::MAX, u8::MAX,
+        )));
+    }
+    let grp_header = create_grp_header(&grp_frames, max_width, max_height);
+    let mut bytes = grp_to_bytes(&grp_header, &grp_frames, &compression_type)?;
+    if let Some(alignment) = options.pad_to {
+        pad_to_alignment(&mut bytes, alignment);
+    }
+    Ok(bytes)
+}
+
+/// Turns RGBA pixel buffers in memory into a set of GrpFrames, without going through PNG files.
+/// Each buffer is given together with its width and height, and has 4 bytes per pixel, row by row.
+/// The pixels are mapped to the palette and trimmed in the same way as when reading PNGs.
+pub fn frames_from_buffers(
+    buffers: Vec<(Vec<u8>, u32, u32)>,
+    palette: &[[u8; 3]],
+    compression_type: &CompressionType,
+) -> Result<Vec<GrpFrame>> {
+
+    let compression_type = determine_compression_type(&[], compression_type);
+    let images = buffers
+        .into_iter()
+        .enumerate()
+        .map(|(i, (buffer, width, height))| {
+            rgba_to_pixels(&buffer, width, height, palette).map(|image| (format!("buffer {}", i), image))
+        });
+    let (frames, _, _) = images_to_grp(images, &compression_type, &GrpWriteOptions::default())?;
+    Ok(frames)
+}
+
+/// Turn the given palettized images into a set of GrpFrames. Each image comes with a name,
+/// used in messages. If canvas_from_first is set in the options, all images will be treated as
+/// having the canvas size of the first image. The padding of the options is not applied here.
+fn images_to_grp<I>(
+    images: I,
     compression_type: &CompressionType,
-) -> Result<(Vec<GrpFrame>, u16, u16)> {
+    options: &GrpWriteOptions,
+) -> Result<(Vec<GrpFrame>, u16, u16)>
+where
+    I: ExactSizeIterator<Item = Result<(String, PalettizedImageWithMetadata<u8, u16>)>>,
+{
 
-    let mut grp_frames: Vec<GrpFrame> = Vec::with_capacity(png_files.len());
-    let mut seen_frames: HashMap<u64, usize> = HashMap::new();
+    let mut grp_frames: Vec<GrpFrame> = Vec::with_capacity(images.len());
+    let mut frame_reuse = FrameReuse::new(compression_type, options.share_data_across_offsets);
 
     let header_len = get_header_size(*compression_type == CompressionType::War1);
-    let mut image_data_offset = (header_len + png_files.len() * 8) as u32; // Initialize to GRP header size
+    let mut image_data_offset = (header_len + images.len() * 8) as u32; // Initialize to GRP header size
     let mut max_width  = 0;
     let mut max_height = 0;
+    let mut canvas: Option<(u16, u16)> = None;
 
-    for (index, png_file) in png_files.iter().enumerate() {
-        let image = png_to_pixels(png_file.as_str(), palette)?;
-        let reuse_key = make_frame_reuse_key(&compression_type, &image);
-
-        if let Some(&existing_index) = seen_frames.get(&reuse_key) {
+    for (index, image) in images.enumerate() {
+        let (name, mut image) = image?;
+        if options.canvas_from_first {
+            let (canvas_width, canvas_height) = *canvas.get_or_insert((image.original_width, image.original_height));
+            place_on_canvas(&mut image, canvas_width, canvas_height, &name)?;
+        }
+        if let Some(existing_index) = frame_reuse.reused_frame(&image, grp_frames.len()) {
             let reused: GrpFrame = grp_frames[existing_index].clone();
             info!("Frame {} is identical to frame {} — reusing image data", index, existing_index);
+            if (reused.x_offset, reused.y_offset) != (image.x_offset, image.y_offset) {
+                check_war1_frame_size(compression_type, reused.width, reused.height, image.x_offset, image.y_offset)?;
+            }
 
             grp_frames.push(GrpFrame {
                 x_offset: image.x_offset,
@@ -778,28 +1435,17 @@ fn files_to_grp(
         } else {
             let orig_width  = image.original_width;
             let orig_height = image.original_height;
-            let grp_frame = png_to_grpframe(image, image_data_offset, &compression_type)?;
+            let grp_frame = png_to_grpframe(image, image_data_offset, compression_type, options.omit_trailing_transparent, options.transparent_index)?;
 
             image_data_offset += grp_frame.grp_frame_len() as u32;
             if offset_is_extended(image_data_offset) {
-                return Err(Error::new(ErrorKind::InvalidInput,
-                    "The image data offset is already too big to add more GRPs!",
-                ));
-            }
-            if *compression_type == CompressionType::War1 &&
-                (grp_frame.width  as u16 + grp_frame.x_offset as u16) > u8::MAX as u16 ||
-                (grp_frame.height as u16 + grp_frame.y_offset as u16) > u8::MAX as u16 {
                 return Err(Error::new(ErrorKind::InvalidInput, format!(
-                    "For compression type {}: \
-                    width ({}) added to x-offset ({}) is {} and must be below {}, or \
-                    height ({}) added to y-offset ({}) is {} and must be below {}. \
-                    Try making the number of rows and columns of all-transparent pixels fewer.",
-                    compression_type, grp_frame.width, grp_frame.x_offset, grp_frame.width + grp_frame.x_offset, u8::MAX,
-                    grp_frame.height, grp_frame.y_offset, grp_frame.height + grp_frame.y_offset, u8::MAX,
+                    "The image data offset is already too big to add more frames! \
+                    Image data can be at offsets up to 0x{:0>8X}", MAX_IMAGE_DATA_OFFSET,
                 )));
             }
+            check_war1_frame_size(compression_type, grp_frame.width, grp_frame.height, grp_frame.x_offset, grp_frame.y_offset)?;
 
-            seen_frames.insert(reuse_key, grp_frames.len());
             grp_frames.push(grp_frame);
 
             max_width  = std::cmp::max(max_width,  orig_width);
@@ -810,7 +1456,104 @@ fn files_to_grp(
     Ok((grp_frames, max_width, max_height))
 }
 
-fn get_header_size(war1_style: bool) -> usize {
+/// Checks that a frame of a WarCraft I GRP fits within its 255x255 canvas at its offsets
+fn check_war1_frame_size(compression_type: &CompressionType, width: u8, height: u8, x_offset: u8, y_offset: u8) -> Result<()> {
+    if *compression_type == CompressionType::War1 &&
+        ((width  as u16 + x_offset as u16) > u8::MAX as u16 ||
+         (height as u16 + y_offset as u16) > u8::MAX as u16) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "For compression type {}: \
+            width ({}) added to x-offset ({}) is {} and must be below {}, or \
+            height ({}) added to y-offset ({}) is {} and must be below {}. \
+            Try making the number of rows and columns of all-transparent pixels fewer.",
+            compression_type, width, x_offset, width as u16 + x_offset as u16, u8::MAX,
+            height, y_offset, height as u16 + y_offset as u16, u8::MAX,
+        )));
+    }
+    Ok(())
+}
+
+/// Moves the trimmed image to the given offsets, by adding transparent columns to its left and
+/// transparent rows to its top. Used when the offsets of the frame are known, and may be above
+/// or left of the pixels that aren't transparent.
+fn place_at_offsets(
+    image: &mut PalettizedImageWithMetadata<u8, u16>,
+    x_offset: u8,
+    y_offset: u8,
+    transparent_index: u8,
+    name: &str,
+) -> Result<()> {
+
+    if image.width == 0 || image.height == 0 {
+        image.x_offset = x_offset;
+        image.y_offset = y_offset;
+        image.width  = 0;
+        image.height = 0;
+        image.palettized_image.clear();
+        return Ok(());
+    }
+    if x_offset > image.x_offset || y_offset > image.y_offset {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "{} has pixels at ({}, {}), which is outside of the offsets ({}, {}) in its name",
+            name, image.x_offset, image.y_offset, x_offset, y_offset,
+        )));
+    }
+    let extra_columns = (image.x_offset - x_offset) as usize;
+    let extra_rows    = (image.y_offset - y_offset) as usize;
+    let width = image.width as usize + extra_columns;
+    let mut pixels = vec![transparent_index; width * extra_rows];
+    for row in image.palettized_image.chunks_exact(image.width as usize) {
+        pixels.extend(std::iter::repeat_n(transparent_index, extra_columns));
+        pixels.extend_from_slice(row);
+    }
+
+    image.x_offset = x_offset;
+    image.y_offset = y_offset;
+    image.width  = width as u16;
+    image.height += extra_rows as u16;
+    image.palettized_image = pixels;
+    Ok(())
+}
+
+/// Centres the image on a canvas of the given size, by adjusting its offsets. Used when
+/// the PNGs have been authored on a shared canvas, but some of them have been cropped.
+fn place_on_canvas(
+    image: &mut PalettizedImageWithMetadata<u8, u16>,
+    canvas_width:  u16,
+    canvas_height: u16,
+    name: &str,
+) -> Result<()> {
+
+    if image.original_width > canvas_width || image.original_height > canvas_height {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "The canvas of {} ({}x{}) is bigger than the canvas of the first image ({}x{})",
+            name, image.original_width, image.original_height, canvas_width, canvas_height,
+        )));
+    }
+    let x_offset = image.x_offset as u16 + (canvas_width  - image.original_width)  / 2;
+    let y_offset = image.y_offset as u16 + (canvas_height - image.original_height) / 2;
+    if x_offset > u8::MAX as u16 || y_offset > u8::MAX as u16 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "When placed on the canvas, the offsets of {} ({}, {}) are above the limit of {}",
+            name, x_offset, y_offset, u8::MAX,
+        )));
+    }
+    if image.original_width != canvas_width || image.original_height != canvas_height {
+        debug!(
+            "Centring {} ({}x{}) on a {}x{} canvas. New x-offset: {}, new y-offset: {}",
+            name, image.original_width, image.original_height,
+            canvas_width, canvas_height, x_offset, y_offset,
+        );
+    }
+
+    image.x_offset = x_offset as u8;
+    image.y_offset = y_offset as u8;
+    image.original_width  = canvas_width;
+    image.original_height = canvas_height;
+    Ok(())
+}
+
+pub(crate) fn get_header_size(war1_style: bool) -> usize {
     if war1_style {
         4
     } else {
@@ -818,7 +1561,7 @@ fn get_header_size(war1_style: bool) -> usize {
     }
 }
 
-fn determine_compression_type(png_files: &Vec<String>, compression_type: &CompressionType) -> CompressionType {
+fn determine_compression_type(png_files: &[String], compression_type: &CompressionType) -> CompressionType {
     let compression = if *compression_type != CompressionType::Auto {
         compression_type.clone()
     } else {
@@ -834,9 +1577,79 @@ fn determine_compression_type(png_files: &Vec<String>, compression_type: &Compre
     compression
 }
 
-/// Make a hash of the data that is relevant for determining whether to reuse a frame or not
-fn make_frame_reuse_key(compression_type: &CompressionType, image: &PalettizedImageWithMetadata<u8, u16>) -> u64 {
-    if (*compression_type == CompressionType::Normal) || (*compression_type == CompressionType::Optimised) {
+/// Finds the frames that would reuse the image data of an earlier frame, if a GRP was created
+/// from the given PNGs with the given arguments, without encoding anything. Returns pairs of
+/// (frame, duplicate of), where "duplicate of" is the index of the first frame with the same
+/// image data.
+pub fn find_duplicate_frames(
+    png_files: &[String],
+    palette: &[[u8; 3]],
+    args: &Args,
+) -> Result<Vec<(usize, usize)>> {
+
+    let compression_type = determine_compression_type(png_files, &args.compression_type);
+    let frame_records = match &args.metadata {
+        Some(metadata_path) => Some(read_frame_metadata(metadata_path, png_files.len())?),
+        None => None,
+    };
+    let frames = files_to_frame_inputs(png_files.to_vec(), palette, &FrameReadOptions {
+        trim: TrimEdges::from(args),
+        mapping: ColourMapping::from(args),
+        frame_records: frame_records.as_deref(),
+        offsets_in_filenames: args.offsets_in_filenames,
+    })?;
+
+    let mut frame_reuse = FrameReuse::new(&compression_type, args.share_data_across_offsets);
+    let mut canvas: Option<(u16, u16)> = None;
+    let mut duplicates = vec![];
+    for (index, mut frame) in frames.into_iter().enumerate() {
+        if args.canvas_from_first {
+            let (canvas_width, canvas_height) = *canvas.get_or_insert((frame.image.original_width, frame.image.original_height));
+            place_on_canvas(&mut frame.image, canvas_width, canvas_height, &frame.name)?;
+        }
+        if let Some(existing_index) = frame_reuse.reused_frame(&frame.image, index) {
+            duplicates.push((index, existing_index));
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Keeps track of the frames of a GRP being created, to find the earlier frame whose image data
+/// a frame can reuse. See [`make_frame_reuse_key`] for when image data can be reused.
+struct FrameReuse<'a> {
+    compression_type: &'a CompressionType,
+    share_data_across_offsets: bool,
+    seen_frames: HashMap<u64, usize>,
+}
+
+impl<'a> FrameReuse<'a> {
+    fn new(compression_type: &'a CompressionType, share_data_across_offsets: bool) -> Self {
+        FrameReuse { compression_type, share_data_across_offsets, seen_frames: HashMap::new() }
+    }
+
+    /// Returns the index of the first frame seen whose image data the given image can reuse.
+    /// If there is none, the image is remembered as the frame with the given index.
+    fn reused_frame(&mut self, image: &PalettizedImageWithMetadata<u8, u16>, index: usize) -> Option<usize> {
+        let reuse_key = make_frame_reuse_key(self.compression_type, image, self.share_data_across_offsets);
+        match self.seen_frames.get(&reuse_key) {
+            Some(&existing_index) => Some(existing_index),
+            None => {
+                self.seen_frames.insert(reuse_key, index);
+                None
+            },
+        }
+    }
+}
+
+/// Make a hash of the data that is relevant for determining whether to reuse a frame or not.
+/// If share_data_across_offsets is true, the offsets of frames of uncompressed GRPs are not
+/// relevant, since they are stored in the frame headers and not in the image data.
+fn make_frame_reuse_key(
+    compression_type: &CompressionType,
+    image: &PalettizedImageWithMetadata<u8, u16>,
+    share_data_across_offsets: bool,
+) -> u64 {
+    if (*compression_type == CompressionType::Normal) || (*compression_type == CompressionType::Optimised) {
         // For normal GRPs, we reference a previous frame if the current image data
         // is identical to a frame we've already seen.
         let mut hasher = DefaultHasher::new();
@@ -849,8 +1662,8 @@ fn make_frame_reuse_key(compression_type: &CompressionType, image: &PalettizedIm
         // is identical to a frame we've already seen.
         let key = FrameDedupKey {
             image_data: image.palettized_image.clone(),
-            x_offset:   image.x_offset,
-            y_offset:   image.y_offset,
+            x_offset:   if share_data_across_offsets { 0 } else { image.x_offset },
+            y_offset:   if share_data_across_offsets { 0 } else { image.y_offset },
             width:      image.width,
             height:     image.height,
         };
@@ -863,8 +1676,12 @@ fn make_frame_reuse_key(compression_type: &CompressionType, image: &PalettizedIm
 
 /// Detects whether the given GRP is uncompressed (unusual) or not (normal)
 pub fn detect_uncompressed(input_path: &String, header: &GrpHeader, war1_style: bool) -> Result<bool> {
+    detect_uncompressed_in(&mut File::open(input_path)?, header, war1_style)
+}
+
+/// Detects whether the GRP read from the given reader is uncompressed (unusual) or not (normal)
+fn detect_uncompressed_in<R: Read + Seek>(file: &mut R, header: &GrpHeader, war1_style: bool) -> Result<bool> {
 
-    let mut file = File::open(input_path)?;
     let file_len = file.seek(SeekFrom::End(0))?;
     file.seek(SeekFrom::Start(get_header_size(war1_style) as u64))?;
 
@@ -884,7 +1701,7 @@ pub fn detect_uncompressed(input_path: &String, header: &GrpHeader, war1_style:
         let (width, offset) = adjust_width_and_offset_if_extended_when_decoding(w, image_data_offset);
 
         if seen_offsets.insert(offset) {
-            total_frame_size += width as u32 * height as u32;
+            total_frame_size += width as u64 * height as u64;
         }
 
         if first_offset == 0 {
@@ -892,7 +1709,7 @@ pub fn detect_uncompressed(input_path: &String, header: &GrpHeader, war1_style:
         }
     }
 
-    let is_uncompressed = first_offset + total_frame_size == file_len as u32;
+    let is_uncompressed = first_offset as u64 + total_frame_size == file_len;
     let msg = format!("Is uncompressed: {}. Is WarCraft I style: {}", is_uncompressed, war1_style);
     if is_uncompressed {
         warn!("{}", msg);
@@ -903,62 +1720,769 @@ pub fn detect_uncompressed(input_path: &String, header: &GrpHeader, war1_style:
     Ok(is_uncompressed)
 }
 
-/// Converts a GRP to PNGs
+/// Reads the header of the given GRP file and determines its type, or uses the given forced type.
+/// Returns the header, whether it was in WarCraft I style or not, and the type. When forced to be
+/// UncompressedExtended, the type is Uncompressed, since whether frames are extended is given by
+/// their offsets, but it is checked that at least one frame is extended.
+pub fn read_grp_header_and_type<R: Read + Seek>(
+    file: &mut R,
+    input_path: &str,
+    force_grp_type: Option<GrpType>,
+) -> Result<(GrpHeader, bool, GrpType)> {
+    let (header, war1_style) = read_grp_header_as(file, force_grp_type.map(|grp_type| grp_type == GrpType::War1))?;
+
+    let grp_type = match force_grp_type {
+        Some(forced_type) => {
+            info!("Treating {} as a GRP of type {:?}, without detecting its type", input_path, forced_type);
+            if forced_type == GrpType::UncompressedExtended && !has_extended_frames(file, &header)? {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "{} cannot be of type {:?}, since none of its frames have extended offsets", input_path, forced_type,
+                )));
+            }
+            match forced_type {
+                GrpType::UncompressedExtended => GrpType::Uncompressed,
+                grp_type => grp_type,
+            }
+        },
+        None => {
+            let is_uncompressed = detect_uncompressed_in(file, &header, war1_style)?;
+            if is_uncompressed && war1_style {
+                GrpType::War1
+            } else if is_uncompressed {
+                GrpType::Uncompressed
+            } else {
+                GrpType::Normal
+            }
+        },
+    };
+    Ok((header, war1_style, grp_type))
+}
+
+/// True if any of the frame headers of the StarCraft style GRP have an extended image data offset
+fn has_extended_frames<R: Read + Seek>(file: &mut R, header: &GrpHeader) -> Result<bool> {
+    file.seek(SeekFrom::Start(get_header_size(false) as u64))?;
+    for _ in 0..header.frame_count {
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf)?;
+        if offset_is_extended(u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]])) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Reads the GRP file at the given path, detecting what type of GRP it is.
+/// GRPs exceeding the given limits are rejected before their frames are read.
+pub fn read_grp_file(
+    input_path: &String,
+    options: &GrpReadOptions,
+) -> Result<GrpFile> {
+    read_grp(&mut File::open(input_path)?, input_path, options)
+}
+
+/// Reads a GRP like [`read_grp_file`], except that a frame that can't be read doesn't stop the
+/// others from being read. Such frames are logged and replaced by empty frames, and their numbers
+/// are returned along with the GRP.
+fn read_grp_file_skipping_failed_frames(
+    input_path: &String,
+    options: &GrpReadOptions,
+) -> Result<(GrpFile, Vec<usize>)> {
+    let mut file = File::open(input_path)?;
+    let (header, _, grp_type) = read_grp_header_and_type(&mut file, input_path, options.force_grp_type)?;
+    check_grp_limits(&mut file, &header, grp_type, &options.limits)?;
+
+    let mut frames = Vec::with_capacity(header.frame_count as usize);
+    let mut failed_frames = Vec::new();
+    for i in 0..header.frame_count {
+        match read_single_frame(&mut file, i, grp_type, options) {
+            Ok(frame) => frames.push(frame),
+            Err(e) => {
+                error!("Failed to read frame {}: {}. Skipping it", i, e);
+                failed_frames.push(i as usize);
+                frames.push(GrpFrame {
+                    x_offset: 0,
+                    y_offset: 0,
+                    width:    0,
+                    height:   0,
+                    image_data_offset: 0,
+                    image_data: ImageData {
+                        row_offsets:  vec![],
+                        raw_row_data: vec![],
+                        converted_pixels: vec![],
+                        grp_type,
+                    },
+                });
+            },
+        }
+    }
+    Ok((GrpFile { header, frames, grp_type }, failed_frames))
+}
+
+/// Decodes a whole GRP from the given bytes, detecting what type of GRP it is. This is meant for
+/// untrusted input, e.g., for fuzzing: any input that is not a valid GRP gives an error, and is
+/// never supposed to cause a panic.
+pub fn decode_grp_checked(bytes: &[u8]) -> Result<GrpFile> {
+    read_grp(&mut std::io::Cursor::new(bytes), "the GRP data", &GrpReadOptions::default())
+}
+
+/// Decodes a whole GRP from the given bytes, without going through any files. Warns if the frames
+/// use palette indices that the given palette has no entries for.
+pub fn decode_grp(bytes: &[u8], palette: &[[u8; 3]]) -> Result<GrpFile> {
+    GrpFile::read(&mut std::io::Cursor::new(bytes), palette)
+}
+
+fn read_grp<R: Read + Seek>(
+    file: &mut R,
+    name: &str,
+    options: &GrpReadOptions,
+) -> Result<GrpFile> {
+    let (header, _, grp_type) = read_grp_header_and_type(file, name, options.force_grp_type)?;
+
+    check_grp_limits(file, &header, grp_type, &options.limits)?;
+    let frames = read_grp_frames(file, header.frame_count, grp_type, options)?;
+    Ok(GrpFile { header, frames, grp_type })
+}
+
+/// Converts a GRP to PNGs, or every GRP in the input directory if it is a directory
 pub fn grp_to_png(args: &Args) -> Result<()> {
-    let palette = get_palette(args)?;
-    let input_path = &args.input_path.clone().unwrap();
+    let input_path  = args.input_path .as_deref().unwrap();
+    let output_path = args.output_path.as_deref().unwrap();
+    if Path::new(input_path).is_dir() {
+        return grp_dir_to_png(args);
+    }
+    let mut inputs = vec![input_path.to_string()];
+    inputs.extend(args.pal_path.clone());
+    if is_zip_path(output_path) {
+        let manifest_dir = Path::new(output_path).parent().unwrap_or(Path::new("."));
+        return run_resumable(args, &inputs, input_path, output_path, manifest_dir, || convert_grp_to_zip(args));
+    }
+    run_resumable(args, &inputs, input_path, output_path, Path::new(output_path), || convert_grp_to_png(args))
+}
 
-    let mut f = File::open(input_path)?;
-    let (header, war1_style) = read_grp_header(&mut f)?;
-    let is_uncompressed = detect_uncompressed(input_path, &header, war1_style)?;
+/// Converts a GRP to PNGs, like convert_grp_to_png, but bundles them in a ZIP archive at the
+/// output path, together with the files describing them and the palette. The PNGs are first
+/// written to a new temporary directory, which is removed afterwards.
+fn convert_grp_to_zip(args: &Args) -> Result<()> {
+    let staging_dir = create_temp_dir()?;
+    let result = stage_and_zip_pngs(args, &staging_dir);
+    std::fs::remove_dir_all(&staging_dir)?;
+    result
+}
 
-    let grp_type = if is_uncompressed && war1_style {
-        GrpType::War1
-    } else if is_uncompressed {
-        GrpType::Uncompressed
+/// Creates a new, empty directory in the temporary directory of the system
+fn create_temp_dir() -> Result<PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let dir = std::env::temp_dir().join(format!("irongrp-{}-{}", std::process::id(), nanos));
+    std::fs::create_dir(&dir)?; // Fails rather than reusing a directory that already exists
+    Ok(dir)
+}
+
+/// Converts the GRP to PNGs in the staging directory, and bundles everything in it in the ZIP
+/// archive at the output path. The frame metadata and the dumped palette are written to the
+/// archive rather than to their own paths, and so is the palette that the PNGs are drawn with, if
+/// it is not the palette file. With continue_on_error, the frames that could be converted are
+/// bundled even if others could not.
+fn stage_and_zip_pngs(args: &Args, staging_dir: &Path) -> Result<()> {
+    let zip_path = args.output_path.as_deref().unwrap();
+    let in_staging_dir = |path: &str| {
+        staging_dir.join(Path::new(path).file_name().unwrap_or_default()).to_string_lossy().into_owned()
+    };
+    let palette_from_file = args.pal_path.is_some() && !args.embedded_palette && !args.index_heatmap;
+    let dump_palette = match &args.dump_palette {
+        Some(dump_path) => Some(in_staging_dir(dump_path)),
+        None if !palette_from_file => Some(in_staging_dir("palette.pal")),
+        None => None,
+    };
+    let staging_args = Args {
+        output_path: Some(staging_dir.to_string_lossy().into_owned()),
+        metadata: args.metadata.as_deref().map(in_staging_dir),
+        dump_palette,
+        ..args.clone()
+    };
+
+    let converted = convert_grp_to_png(&staging_args);
+    if converted.is_err() && !args.continue_on_error {
+        return converted;
+    }
+    let mut files = vec![];
+    for entry in std::fs::read_dir(staging_dir)? {
+        let path = entry?.path();
+        files.push((path.file_name().unwrap_or_default().to_string_lossy().into_owned(), std::fs::read(&path)?));
+    }
+    if files.is_empty() {
+        return converted; // Nothing could be converted
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Some(pal_path) = args.pal_path.as_ref().filter(|_| palette_from_file) {
+        let name = Path::new(pal_path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+        files.push((name, std::fs::read(pal_path)?));
+    }
+    write_zip(zip_path, &files)?;
+    info!("Saved {} files to {}", files.len(), zip_path);
+    converted
+}
+
+/// Converts every GRP in the input directory to PNGs, which are saved in a directory named after
+/// the GRP in the output directory. Other files are skipped with a warning. Unless the palettes
+/// are embedded in the GRPs, the palette is only read once, and shared by all GRPs. With
+/// continue_on_error, a GRP that can't be converted is logged and the rest are still converted,
+/// after which an error lists the GRPs that failed.
+fn grp_dir_to_png(args: &Args) -> Result<()> {
+    let input_dir   = args.input_path .as_deref().unwrap();
+    let output_path = args.output_path.as_deref().unwrap();
+    for entry in std::fs::read_dir(input_dir)? {
+        let path = entry?.path();
+        if path.is_file() && !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("grp")) {
+            warn!("Skipping {}, which is not a GRP file", path.display());
+        }
+    }
+    let grp_files = list_grp_files(input_dir)?;
+    if grp_files.is_empty() {
+        warn!("Found no GRP files in {}", input_dir);
+        return Ok(());
+    }
+
+    let shared_palette = if args.embedded_palette { None } else { Some(read_palette_for_pngs(args)?) };
+    let mut failed_files = vec![];
+    for grp_path in &grp_files {
+        let grp_name = Path::new(grp_path).file_stem().unwrap_or_default().to_string_lossy();
+        let grp_output_path = format!("{}/{}", output_path, grp_name);
+        let convert = || {
+            std::fs::create_dir_all(&grp_output_path)?;
+            let grp_args = Args {
+                input_path:  Some(grp_path.clone()),
+                output_path: Some(grp_output_path.clone()),
+                with_palette_swatch: args.with_palette_swatch && shared_palette.is_none(),
+                ..args.clone()
+            };
+            let palette = match &shared_palette {
+                Some(palette) => palette.clone(),
+                None => read_palette_for_pngs(&grp_args)?,
+            };
+
+            info!("Converting {} to {}", grp_path, grp_output_path);
+            let mut inputs = vec![grp_path.clone()];
+            inputs.extend(args.pal_path.clone());
+            run_resumable(
+                &grp_args, &inputs, grp_path, &grp_output_path, Path::new(&grp_output_path),
+                || convert_grp_to_png_with_palette(&grp_args, &palette),
+            )
+        };
+        match convert() {
+            Err(e) if args.continue_on_error => {
+                error!("Failed to convert {}: {}. Continuing with the other GRPs", grp_path, e);
+                failed_files.push(grp_path.clone());
+            },
+            result => result?,
+        }
+    }
+
+    if !failed_files.is_empty() {
+        return Err(Error::other(format!(
+            "{} of {} GRPs in {} could not be converted: {:?}", failed_files.len(), grp_files.len(), input_dir, failed_files,
+        )));
+    }
+    info!("Converted {} GRPs in {}", grp_files.len(), input_dir);
+    Ok(())
+}
+
+fn convert_grp_to_png(args: &Args) -> Result<()> {
+    let palette = read_palette_for_pngs(args)?;
+    convert_grp_to_png_with_palette(args, &palette)
+}
+
+/// Reads the palette to draw the PNGs with, and saves it and its swatch if asked to
+fn read_palette_for_pngs(args: &Args) -> Result<Vec<[u8; 3]>> {
+    let palette = if args.index_heatmap {
+        index_heatmap_palette()
+    } else {
+        get_palette(args)?
+    };
+    if let Some(dump_path) = &args.dump_palette {
+        write_rgb_palette(dump_path, &palette)?;
+        info!("Saved the palette to {}", dump_path);
+    }
+    if args.with_palette_swatch {
+        let (pixels, width, height) = palette_swatch(&palette);
+        let swatch_path = format!("{}/palette.{}", args.output_path.as_deref().unwrap(), args.output_format.extension());
+        save_rgb_pixels_to_image_file(pixels, &swatch_path, false, width, height)?;
+        info!("Saved a swatch of the palette to {}", swatch_path);
+    }
+    Ok(palette)
+}
+
+fn convert_grp_to_png_with_palette(args: &Args, palette: &[[u8; 3]]) -> Result<()> {
+    let input_path = &args.input_path.clone().unwrap();
+    let (grp, mut failed_frames) = if args.continue_on_error {
+        read_grp_file_skipping_failed_frames(input_path, &GrpReadOptions::from(args))?
     } else {
-        GrpType::Normal
+        (GrpFile::read_named(&mut File::open(input_path)?, input_path, palette, &GrpReadOptions::from(args))?, Vec::new())
     };
+    let GrpFile { header, frames, .. } = grp;
 
-    let frames = read_grp_frames(&mut f, header.frame_count, grp_type)?;
+    if let Some(frame_range) = args.frame_range {
+        frame_range.check_in_bounds(frames.len())?;
+    }
+
+    // Give the frames the names of the PNGs that the GRP was created from, if known
+    let frame_names = match read_source_manifest(input_path)? {
+        Some(manifest) if manifest.source_files.len() == frames.len() => {
+            info!("Naming the frames after the source PNGs listed in the manifest of {}", input_path);
+            Some(manifest.source_files)
+        },
+        Some(manifest) => {
+            warn!(
+                "The manifest of {} lists {} source PNGs, but the GRP has {} frames. Ignoring the manifest",
+                input_path, manifest.source_files.len(), frames.len(),
+            );
+            None
+        },
+        None => None,
+    };
 
     render_and_save_frames_to_png(
         &frames,
-        &palette,
+        palette,
         header.max_width  as u32,
         header.max_height as u32,
-        &args,
-    )
+        frame_names.as_deref(),
+        &mut failed_frames,
+        args,
+    )?;
+
+    if let Some(metadata_path) = &args.metadata {
+        write_frame_metadata(metadata_path, &frames)?;
+    }
+    if let Some(gif_path) = &args.gif {
+        save_frames_as_gif(&frames, palette, header.max_width, header.max_height, gif_path, args)?;
+        info!("Saved {} frames as an animated GIF to {}", frames.len(), gif_path);
+    }
+    if let Some(apng_path) = &args.apng {
+        save_frames_as_apng(&frames, palette, header.max_width as u32, header.max_height as u32, apng_path, args)?;
+        info!("Saved {} frames as an animated PNG to {}", frames.len(), apng_path);
+    }
+
+    if !failed_frames.is_empty() {
+        failed_frames.sort();
+        return Err(Error::other(format!(
+            "{} of {} frames could not be converted: {:?}", failed_frames.len(), frames.len(), failed_frames,
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that the palette has entries for all palette indices used by a sample of the frames of
+/// the input GRP, without converting anything. Palette indices beyond the entries of the palette
+/// suggest that the palette does not belong to the GRP, and give an error.
+pub fn check_palette_compatibility(args: &Args) -> Result<()> {
+    let input_path = args.input_path.as_deref().unwrap();
+    let palette_entries = match &args.pal_path {
+        _ if args.embedded_palette => FULL_PALETTE_LEN,
+        Some(pal_path) => read_palette_entries(pal_path)?.len(),
+        None => {
+            warn!("No palette given - checking against the greyscale palette");
+            FULL_PALETTE_LEN
+        },
+    };
+
+    let options = GrpReadOptions::from(args);
+    let mut file = File::open(input_path)?;
+    let (header, _, grp_type) = read_grp_header_and_type(&mut file, input_path, options.force_grp_type)?;
+    check_grp_limits(&mut file, &header, grp_type, &options.limits)?;
+
+    let sample = sample_frame_numbers(header.frame_count, PALETTE_CHECK_SAMPLE_FRAMES);
+    let frames = sample.iter()
+        .map(|&i| read_single_frame(&mut file, i, grp_type, &options))
+        .collect::<Result<Vec<_>>>()?;
+    let used_indices: HashSet<u8> = frames.iter()
+        .flat_map(|frame| frame.image_data.converted_pixels.iter().copied())
+        .collect();
+    info!(
+        "Frames {:?} of {} use {} different palette indices. The palette has {} entries",
+        sample, header.frame_count, used_indices.len(), palette_entries,
+    );
+
+    let mut missing_indices = indices_missing_from_palette(&used_indices, palette_entries);
+    if !missing_indices.is_empty() {
+        missing_indices.sort();
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The frames use palette indices that the palette has no entries for: {:?}. \
+            The palette probably does not belong to {}",
+            missing_indices, input_path,
+        )));
+    }
+    info!("The palette has entries for all palette indices used in the sampled frames");
+    Ok(())
+}
+
+/// Picks at most sample_size frame numbers, evenly spread out over all frames
+fn sample_frame_numbers(frame_count: u16, sample_size: u16) -> Vec<u16> {
+    let sample_size = sample_size.min(frame_count);
+    (0..sample_size)
+        .map(|i| (i as u32 * frame_count as u32 / sample_size as u32) as u16)
+        .collect()
+}
+
+/// The palette indices among the given ones that a palette with the given number of entries lacks
+fn indices_missing_from_palette(indices: &HashSet<u8>, palette_entries: usize) -> Vec<u8> {
+    indices.iter()
+        .copied()
+        .filter(|&index| index as usize >= palette_entries)
+        .collect()
 }
 
-fn get_palette(args: &Args) -> Result<Vec<[u8; 3]>> {
-    if let Some(path) = &args.pal_path {
-        read_rgb_palette(path)
+/// Re-derives the row offset tables of a Normal GRP, e.g., after its RLE data has been edited
+/// by hand, and writes the repaired GRP to the output path.
+pub fn fix_row_offsets(args: &Args) -> Result<()> {
+    let input_path = &args.input_path.clone().unwrap();
+    // Only the headers are read, since the row offset tables being broken is the point
+    let options = GrpReadOptions::from(args);
+    let mut file = File::open(input_path)?;
+    let (header, _, grp_type) = read_grp_header_and_type(&mut file, input_path, options.force_grp_type)?;
+    check_grp_limits(&mut file, &header, grp_type, &options.limits)?;
+    if grp_type != GrpType::Normal {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Only Normal GRPs have row offset tables, but {} is of type {:?}", input_path, grp_type,
+        )));
+    }
+
+    let output_path = args.output_path.as_deref().unwrap();
+    if args.normalize_transparent_runs {
+        // The frames are re-encoded from their pixels, which gives new row offset tables anyway.
+        // Repairing the tables first would break valid GRPs whose rows share data.
+        let grp = read_grp(&mut file, input_path, &options)?;
+        let frames = normalize_frames(&grp.header, grp.frames, options.transparent_index)?;
+        let grp = GrpFile { frames, ..grp };
+        grp.write(&mut File::create(output_path)?, &CompressionType::Normal)?;
+        info!("Re-encoded {} frames in the canonical form", grp.frames.len());
+        return Ok(());
+    }
+
+    let mut data = std::fs::read(input_path)?;
+    let changed_rows = fix_row_offset_tables(&mut data)?;
+    info!("Updated {} row offsets", changed_rows);
+    std::fs::write(output_path, data)
+}
+
+/// Re-encodes the frames of a Normal GRP from their pixels, so that GRPs with the same pixels,
+/// offsets and header produce identical bytes, regardless of how their image data was encoded:
+/// - Transparent pixels are encoded as one run, split into runs of at most 127 pixels
+/// - More than 3 pixels of one colour are encoded as a run, split into runs of at most 63 pixels
+/// - Other pixels are copied literally, at most 63 at a time
+/// - Transparent runs at the end of rows are kept, and rows don't share data
+/// - Frames with identical pixels share image data, in the order the frames appear
+fn normalize_frames(header: &GrpHeader, frames: Vec<GrpFrame>, transparent_index: u8) -> Result<Vec<GrpFrame>> {
+    let images = grp_frames_to_frame_inputs(header, frames)
+        .into_iter()
+        .map(|frame| Ok((frame.name, frame.image)))
+        .collect::<Vec<_>>();
+    let options = GrpWriteOptions { transparent_index, ..Default::default() };
+    let (frames, _, _) = images_to_grp(images.into_iter(), &CompressionType::Normal, &options)?;
+    Ok(frames)
+}
+
+/// Turns decoded GrpFrames into frames to encode into a GRP, with their pixels and offsets as
+/// they are, on the canvas given by the header
+fn grp_frames_to_frame_inputs(header: &GrpHeader, frames: Vec<GrpFrame>) -> Vec<FrameInput> {
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| FrameInput {
+            name: format!("frame {}", i),
+            image: PalettizedImageWithMetadata {
+                x_offset: frame.x_offset,
+                y_offset: frame.y_offset,
+                width:    frame.actual_width(),
+                height:   frame.height as u16,
+                original_width:  header.max_width,
+                original_height: header.max_height,
+                palettized_image: frame.image_data.converted_pixels,
+            },
+        })
+        .collect()
+}
+
+/// Re-encodes the frames of the input GRP with the given compression type, keeping their palette
+/// indices and offsets as they are, and writes the new GRP to the output path.
+pub fn transcode_grp(args: &Args) -> Result<()> {
+    let input_path = &args.input_path.clone().unwrap();
+    if args.compression_type == CompressionType::Auto {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "The compression type to transcode {} to must be given with '--compression-type'", input_path,
+        )));
+    }
+    let grp = read_grp_file(input_path, &GrpReadOptions::from(args))?;
+    info!(
+        "Transcoding {} frames of {}, which is of type {:?}, to compression type {}",
+        grp.frames.len(), input_path, grp.grp_type, args.compression_type,
+    );
+
+    let frames = grp_frames_to_frame_inputs(&grp.header, grp.frames);
+    let bytes = encode_grp_with_options(&frames, &args.compression_type, &GrpWriteOptions::from(args))?;
+    std::fs::write(args.output_path.as_deref().unwrap(), bytes)
+}
+
+/// Moves the frames of the input GRP towards the upper left corner, so that the smallest x and
+/// y offsets become zero, and shrinks the maximum width and height in the header accordingly.
+pub fn reorigin_grp(args: &Args) -> Result<()> {
+    let input_path = &args.input_path.clone().unwrap();
+    let grp_type = read_grp_file(input_path, &GrpReadOptions::from(args))?.grp_type;
+
+    let mut data = std::fs::read(input_path)?;
+    let (min_x, min_y) = reorigin_frames(&mut data, grp_type == GrpType::War1)?;
+    std::fs::write(args.output_path.as_deref().unwrap(), data)?;
+    info!("Moved all frames {} pixels to the left and {} pixels up", min_x, min_y);
+    Ok(())
+}
+
+/// Subtracts the smallest x and y offset of all frames from the offsets of every frame in the
+/// given GRP data, and from the maximum width and height in the header. The relative placement
+/// of the frames is kept. Returns the subtracted x and y offsets.
+fn reorigin_frames(data: &mut [u8], war1_style: bool) -> Result<(u8, u8)> {
+    let (header, _) = read_grp_header(&mut std::io::Cursor::new(&*data))?;
+    let header_size = get_header_size(war1_style);
+    let frame_headers = header_size .. header_size + header.frame_count as usize * 8;
+    if data.len() < frame_headers.end {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Not enough data for the frame headers"));
+    }
+
+    let (min_x, min_y) = data[frame_headers.clone()]
+        .chunks_exact(8)
+        .fold((u8::MAX, u8::MAX), |(x, y), frame_header| (x.min(frame_header[0]), y.min(frame_header[1])));
+    if header.frame_count == 0 || (min_x == 0 && min_y == 0) {
+        return Ok((0, 0));
+    }
+
+    for frame_header in data[frame_headers].chunks_exact_mut(8) {
+        frame_header[0] -= min_x;
+        frame_header[1] -= min_y;
+    }
+
+    let max_width  = header.max_width .saturating_sub(min_x as u16);
+    let max_height = header.max_height.saturating_sub(min_y as u16);
+    debug!(
+        "Changing maximum width from {} to {} and maximum height from {} to {}",
+        header.max_width, max_width, header.max_height, max_height,
+    );
+    if war1_style {
+        data[2] = max_width  as u8;
+        data[3] = max_height as u8;
     } else {
-        warn!("No palette given - defaulting to greyscale palette");
-        greyscale_palette()
+        data[2..4].copy_from_slice(&max_width .to_le_bytes());
+        data[4..6].copy_from_slice(&max_height.to_le_bytes());
     }
+    Ok((min_x, min_y))
+}
+
+/// Rewrites the row offset table of every frame in the given Normal GRP data, by decoding the
+/// rows one after another to find out where each row ends and thus where the next one begins.
+/// Since the rows are assumed to follow directly after each other, any optimisations where
+/// rows share data cannot be recovered. The last row of a frame is decoded from the data up to
+/// where the image data of the next frame begins, so it may have its trailing transparent run
+/// omitted; other rows that omit it cannot be told apart from rows that are longer.
+/// Returns the number of row offsets that were changed.
+fn fix_row_offset_tables(data: &mut [u8]) -> Result<usize> {
+    let (header, _) = read_grp_header(&mut std::io::Cursor::new(&*data))?;
+    let header_size = get_header_size(false);
+    let frame_headers = read_frame_headers(&mut std::io::Cursor::new(&*data), header.frame_count, GrpType::Normal)?;
+
+    let mut fixed_offsets = HashSet::new();
+    let mut changed_rows = 0;
+    for i in 0..header.frame_count as usize {
+        let frame_header = &data[header_size + i * 8 .. header_size + (i + 1) * 8];
+        let width  = frame_header[2] as u16;
+        let height = frame_header[3] as usize;
+        let image_data_offset = u32::from_le_bytes([frame_header[4], frame_header[5], frame_header[6], frame_header[7]]) as usize;
+        if !fixed_offsets.insert(image_data_offset) {
+            continue; // Frames sharing image data also share the row offset table
+        }
+
+        let data_end = frame_data_end(&frame_headers, image_data_offset as u32, data.len() as u64) as usize;
+        let mut row_offset = height * 2; // The first row follows directly after the row offset table
+        for row in 0..height {
+            if image_data_offset + row_offset >= data_end || row_offset > u16::MAX as usize {
+                return Err(Error::new(ErrorKind::UnexpectedEof, format!(
+                    "Row {} of frame {} would start at offset {}, which is beyond the end of its image data",
+                    row, i, image_data_offset + row_offset,
+                )));
+            }
+            // The transparent index does not affect how long the encoded row is
+            let (_, encoded_length) = decode_grp_rle_row(&data[image_data_offset + row_offset..data_end], width, 0);
+
+            let table_entry = image_data_offset + row * 2;
+            let old_offset = u16::from_le_bytes([data[table_entry], data[table_entry + 1]]);
+            if old_offset as usize != row_offset {
+                debug!(
+                    "Frame {}, row {}: Changing row offset from 0x{:0>4X} to 0x{:0>4X}",
+                    i, row, old_offset, row_offset,
+                );
+                data[table_entry..table_entry + 2].copy_from_slice(&(row_offset as u16).to_le_bytes());
+                changed_rows += 1;
+            }
+            row_offset += encoded_length;
+        }
+    }
+    Ok(changed_rows)
+}
+
+/// Returns the first frame that is too wide to fit in a frame of a Normal GRP, if any.
+fn find_too_wide_frame(frames: &[FrameInput]) -> Option<&FrameInput> {
+    frames.iter().find(|frame| frame.image.width > u8::MAX as u16)
 }
 
 /// Converts PNGs to a GRP
 pub fn png_to_grp(args: &Args) -> Result<()> {
+    let input_path  = args.input_path .as_deref().unwrap();
+    let output_path = args.output_path.as_deref().unwrap();
+    let mut inputs = list_png_files(input_path)?;
+    inputs.extend(args.pal_path.clone());
+    let output_dir = Path::new(output_path).parent().unwrap_or(Path::new(""));
+    run_resumable(args, &inputs, input_path, output_path, output_dir, || convert_png_to_grp(args))
+}
+
+/// Decodes the given bytes of a GRP, and checks that its frames have the same palette indices and
+/// dimensions as the frames it was encoded from, and the same offsets if compare_offsets is true.
+/// Every frame that differs is logged, and gives an error with a summary of the differences.
+fn verify_grp(bytes: &[u8], frames: &[FrameInput], palette: &[[u8; 3]], compare_offsets: bool) -> Result<()> {
+    let grp = decode_grp(bytes, palette)?;
+    if grp.frames.len() != frames.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The GRP decodes to {} frames, but was encoded from {}", grp.frames.len(), frames.len(),
+        )));
+    }
+
+    let mut differences = vec![];
+    for (i, (input, frame)) in frames.iter().zip(grp.frames()).enumerate() {
+        let expected = &input.image;
+        let (width, height) = (frame.actual_width(), frame.height as u16);
+        let difference = if (width, height) != (expected.width, expected.height) {
+            Some(format!("is {}x{}, but {} is {}x{}", width, height, input.name, expected.width, expected.height))
+        } else if compare_offsets && (frame.x_offset, frame.y_offset) != (expected.x_offset, expected.y_offset) {
+            Some(format!(
+                "has the offsets {}, {}, but {} has {}, {}",
+                frame.x_offset, frame.y_offset, input.name, expected.x_offset, expected.y_offset,
+            ))
+        } else {
+            let differing: Vec<usize> = (0..expected.palettized_image.len())
+                .filter(|&p| frame.image_data.converted_pixels.get(p) != expected.palettized_image.get(p))
+                .collect();
+            differing.first().map(|&first| format!(
+                "has {} of {} pixels that differ from {}. The first is at {}, {}, which is {} instead of {}",
+                differing.len(), expected.palettized_image.len(), input.name,
+                first % width as usize, first / width as usize,
+                frame.image_data.converted_pixels.get(first).map_or("missing".to_string(), |index| index.to_string()),
+                expected.palettized_image[first],
+            ))
+        };
+        if let Some(difference) = difference {
+            error!("Frame {} {}", i, difference);
+            differences.push(i);
+        }
+    }
+
+    if !differences.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "{} of {} frames of the GRP don't decode to what they were encoded from: {:?}",
+            differences.len(), frames.len(), differences,
+        )));
+    }
+    Ok(())
+}
+
+/// Runs the given conversion, unless resuming and the resume manifest in the given directory
+/// says that the conversion has already been done with the same input files and options. The
+/// conversion is recorded in the manifest when it succeeds.
+fn run_resumable<F: FnOnce() -> Result<()>>(
+    args: &Args,
+    input_files: &[String],
+    input_path:  &str,
+    output_path: &str,
+    manifest_dir: &Path,
+    convert: F,
+) -> Result<()> {
+    if !args.resume {
+        return convert();
+    }
+    let manifest_path = resume_manifest_path(manifest_dir);
+    let mut manifest = ResumeManifest::read(&manifest_path)?;
+    // All options but the log level may affect the output, e.g., the compression type, palette,
+    // trimming and colour mapping
+    let options = format!("{:?}", Args { log_level: LogLevel::Info, ..args.clone() });
+    let input_hash = hash_input_files(input_files, &options)?;
+    if manifest.is_completed(input_path, output_path, &input_hash) {
+        info!("Skipping {}, which has already been converted to {}", input_path, output_path);
+        return Ok(());
+    }
+
+    convert()?;
+    manifest.mark_completed(input_path, output_path, &input_hash);
+    manifest.write(&manifest_path)
+}
+
+fn convert_png_to_grp(args: &Args) -> Result<()> {
     let out_path  = args.output_path.as_deref().unwrap();
     let palette   = get_palette(args)?;
     let png_files = list_png_files(&args.input_path.clone().unwrap())?;
-    let compression_type = determine_compression_type(&png_files, &args.compression_type);
+    let mut compression_type = determine_compression_type(&png_files, &args.compression_type);
 
-    let (grp_frames, max_width, max_height) = files_to_grp(png_files, &palette, &compression_type)?;
-    let grp_header = create_grp_header(&grp_frames, max_width, max_height);
-    write_grp_file(out_path, &grp_header, &grp_frames, &compression_type)
+    let frame_records = match &args.metadata {
+        Some(metadata_path) => Some(read_frame_metadata(metadata_path, png_files.len())?),
+        None => None,
+    };
+    let frames = files_to_frame_inputs(png_files.clone(), &palette, &FrameReadOptions {
+        trim: TrimEdges::from(args),
+        mapping: ColourMapping::from(args),
+        frame_records: frame_records.as_deref(),
+        offsets_in_filenames: args.offsets_in_filenames,
+    })?;
+    if args.auto_uncompress_wide &&
+        (compression_type == CompressionType::Normal || compression_type == CompressionType::Optimised) {
+        if let Some(frame) = find_too_wide_frame(&frames) {
+            // A GRP is either entirely RLE compressed or entirely uncompressed, so the
+            // frames cannot be mixed. The whole GRP is made uncompressed instead.
+            warn!(
+                "{} is too wide for compression type {}. The GRP will be Uncompressed instead",
+                frame.name, compression_type,
+            );
+            compression_type = CompressionType::Uncompressed;
+        }
+    }
+    let bytes = encode_grp_with_options(&frames, &compression_type, &GrpWriteOptions::from(args))?;
+    std::fs::write(out_path, &bytes)?;
+    if args.verify {
+        verify_grp(&bytes, &frames, &palette, !args.canvas_from_first)?;
+        info!("Verified that the {} frames of {} decode to the palette indices of the PNGs", frames.len(), out_path);
+    }
+
+    if args.write_source_manifest {
+        write_source_manifest(out_path, &png_files)?;
+    }
+    Ok(())
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::palpng::greyscale_palette;
     use proptest::prelude::*;
     use std::fs;
 
+    /// Turns all the given PNG files into a set of GrpFrames, like png-to-grp does
+    fn files_to_grp(
+        png_files: Vec<String>,
+        palette: &[[u8; 3]],
+        compression_type: &CompressionType,
+        read_options: &FrameReadOptions,
+        write_options: &GrpWriteOptions,
+    ) -> Result<(Vec<GrpFrame>, u16, u16)> {
+        let frames = files_to_frame_inputs(png_files, palette, read_options)?;
+        let images = frames.into_iter().map(|frame| Ok((frame.name, frame.image)));
+        images_to_grp(images, compression_type, write_options)
+    }
+
     fn create_test_png(path: &str, colour: [u8; 3], width: u32, height: u32) {
         use image::{Rgb, RgbImage};
         let mut img = RgbImage::new(width, height);
@@ -980,6 +2504,23 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn rejects_byte_swapped_header() {
+        use std::io::Cursor;
+        let mut data = vec![0x00, 0x02, 0x00, 0x0A, 0x00, 0x0A]; // 2 frames, 10x10 size, big-endian
+        data.extend(vec![0, 0, 1, 1, 22, 0, 0, 0]); // frame headers
+        data.extend(vec![0, 0, 1, 1, 23, 0, 0, 0]);
+        data.extend(vec![0x71, 0x72]);
+
+        let error = read_grp_header(&mut Cursor::new(data.clone())).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("would be 2"), "Unexpected error: {}", error);
+
+        data[0..2].copy_from_slice(&[0x02, 0x00]); // Only the dimensions are big-endian now
+        let error = read_grp_header(&mut Cursor::new(data)).unwrap_err();
+        assert!(error.to_string().contains("would be 10x10"), "Unexpected error: {}", error);
+    }
+
     #[test]
     fn test_incomplete_frame_header() {
         use std::io::Cursor;
@@ -988,7 +2529,7 @@ mod tests {
         let mut cursor = Cursor::new(data);
 
         let _ = read_grp_header(&mut cursor); // skip header
-        let result = read_grp_frames(&mut cursor, 1, GrpType::Normal);
+        let result = read_grp_frames(&mut cursor, 1, GrpType::Normal, &GrpReadOptions::default());
 
         assert!(result.is_err());
     }
@@ -1003,11 +2544,12 @@ mod tests {
 
         let mut cursor = Cursor::new(data);
         let _ = read_grp_header(&mut cursor);
-        let result = read_grp_frames(&mut cursor, 1, GrpType::Normal);
+        let result = read_grp_frames(&mut cursor, 1, GrpType::Normal, &GrpReadOptions::default());
         assert!(result.is_err());
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_uncompressed_style_header() -> Result<()> {
         use std::io::Cursor;
         // Valid header + 1 frame header
@@ -1021,7 +2563,7 @@ mod tests {
         let mut cursor = Cursor::new(data);
         let (header, war1_style) = read_grp_header(&mut cursor)?;
         cursor.seek(SeekFrom::Start(header_len))?;
-        let result = read_grp_frames(&mut cursor, 1, GrpType::Uncompressed);
+        let result = read_grp_frames(&mut cursor, 1, GrpType::Uncompressed, &GrpReadOptions::default());
         assert_eq!(war1_style, false);
         assert_eq!(header.frame_count, 1);
         assert_eq!(header.max_width,   1);
@@ -1031,6 +2573,232 @@ mod tests {
     }
 
     #[test]
+    fn encodes_and_decodes_grps_in_memory() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let frame = |name: &str, x_offset: u8, pixels: Vec<u8>| FrameInput {
+            name: name.to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 1,
+                width:    3,
+                height:   2,
+                original_width:  8,
+                original_height: 4,
+                palettized_image: pixels,
+            },
+        };
+        let frames = vec![
+            frame("first",  2, vec![1, 2, 3, 0, 5, 5]),
+            frame("second", 4, vec![1, 2, 3, 0, 5, 5]),
+            frame("third",  0, vec![7; 6]),
+        ];
+
+        for compression_type in [CompressionType::Normal, CompressionType::Optimised, CompressionType::Uncompressed] {
+            let bytes = encode_grp(&frames, &compression_type)?;
+            let grp = decode_grp(&bytes, &palette)?;
+
+            assert_eq!((grp.header.frame_count, grp.header.max_width, grp.header.max_height), (3, 8, 4));
+            for (input, frame) in frames.iter().zip(grp.frames()) {
+                assert_eq!((frame.x_offset, frame.y_offset), (input.image.x_offset, input.image.y_offset));
+                assert_eq!(frame.image_data.converted_pixels, input.image.palettized_image, "{} of {}", input.name, compression_type);
+            }
+        }
+        let decoded = decode_grp(&encode_grp(&frames, &CompressionType::Normal)?, &palette)?.frames;
+        assert_eq!(decoded[0].image_data_offset, decoded[1].image_data_offset, "Identical frames share image data");
+
+        let padding = GrpWriteOptions { pad_to: Some(16), ..GrpWriteOptions::default() };
+        assert_eq!(encode_grp_with_options(&frames, &CompressionType::Normal, &padding)?.len() % 16, 0);
+        assert!(encode_grp_with_options(&frames, &CompressionType::Uncompressed, &padding).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn transcodes_grps_between_compression_types() -> Result<()> {
+        use clap::Parser;
+        let frame = |x_offset: u8, pixels: Vec<u8>| FrameInput {
+            name: format!("frame at {}", x_offset),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 3,
+                width:    4,
+                height:   2,
+                original_width:  16,
+                original_height: 8,
+                palettized_image: pixels,
+            },
+        };
+        let frames = vec![frame(1, vec![0, 9, 9, 9, 9, 9, 9, 0]), frame(12, vec![4; 8])];
+        let normal_path = "test_transcode_normal.grp";
+        fs::write(normal_path, encode_grp(&frames, &CompressionType::Normal)?)?;
+        let transcode = |input: &str, output: &str, compression_type: &str| transcode_grp(&Args::parse_from([
+            "irongrp", "--mode", "transcode-grp", "--input-path", input, "--output-path", output, "--compression-type", compression_type,
+        ]));
+
+        for (compression_type, grp_type) in [("optimised", GrpType::Normal), ("uncompressed", GrpType::Uncompressed), ("war1", GrpType::War1)] {
+            let path = format!("test_transcode_{}.grp", compression_type);
+            transcode(normal_path, &path, compression_type)?;
+            let grp = read_grp_file(&path, &GrpReadOptions::default())?;
+            assert_eq!(grp.grp_type, grp_type);
+            assert_eq!((grp.header.max_width, grp.header.max_height), (16, 8));
+            for (input, frame) in frames.iter().zip(grp.frames()) {
+                assert_eq!((frame.x_offset, frame.y_offset), (input.image.x_offset, input.image.y_offset));
+                assert_eq!(frame.image_data.converted_pixels, input.image.palettized_image);
+            }
+
+            transcode(&path, "test_transcode_back.grp", "normal")?;
+            assert_eq!(fs::read("test_transcode_back.grp")?, fs::read(normal_path)?, "Transcoding {} back to normal", compression_type);
+            fs::remove_file(path)?;
+        }
+
+        // A frame that goes beyond 255 pixels from the left can't be in a WarCraft I GRP
+        let frames = vec![frame(254, vec![4; 8])];
+        fs::write(normal_path, encode_grp(&frames, &CompressionType::Normal)?)?;
+        assert_eq!(transcode(normal_path, "test_transcode_back.grp", "war1").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(transcode(normal_path, "test_transcode_back.grp", "auto").is_err());
+
+        fs::remove_file(normal_path)?;
+        fs::remove_file("test_transcode_back.grp")?;
+        Ok(())
+    }
+
+    #[test]
+    fn converts_every_grp_in_a_directory() -> Result<()> {
+        use clap::Parser;
+        let frame = FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset: 1,
+                y_offset: 1,
+                width:    2,
+                height:   2,
+                original_width:  4,
+                original_height: 4,
+                palettized_image: vec![1, 2, 3, 4],
+            },
+        };
+        let input_dir  = "test_grp_dir_to_png_input";
+        let output_dir = "test_grp_dir_to_png_output";
+        fs::create_dir_all(input_dir)?;
+        fs::write(format!("{}/one.grp", input_dir), encode_grp(std::slice::from_ref(&frame), &CompressionType::Normal)?)?;
+        fs::write(format!("{}/two.GRP", input_dir), encode_grp(&[frame.clone(), frame], &CompressionType::Uncompressed)?)?;
+        fs::write(format!("{}/readme.txt", input_dir), "Not a GRP")?;
+
+        let result = grp_to_png(&Args::parse_from([
+            "irongrp", "--mode", "grp-to-png", "--input-path", input_dir, "--output-path", output_dir,
+        ]));
+        let png_count = |dir: &str| fs::read_dir(dir).map(|entries| entries.count()).unwrap_or(0);
+        let counts = (png_count(&format!("{}/one", output_dir)), png_count(&format!("{}/two", output_dir)), png_count(output_dir));
+        fs::remove_dir_all(input_dir)?;
+        fs::remove_dir_all(output_dir)?;
+
+        result?;
+        assert_eq!(counts, (1, 2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn converts_the_other_grps_in_a_directory_when_one_fails() -> Result<()> {
+        use clap::Parser;
+        let frame = FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset: 0,
+                y_offset: 0,
+                width:    2,
+                height:   1,
+                original_width:  2,
+                original_height: 1,
+                palettized_image: vec![1, 2],
+            },
+        };
+        let input_dir  = "test_grp_dir_continue_input";
+        let output_dir = "test_grp_dir_continue_output";
+        fs::create_dir_all(input_dir)?;
+        fs::write(format!("{}/a_broken.grp", input_dir), [0x01, 0x00])?;
+        fs::write(format!("{}/b_fine.grp", input_dir), encode_grp(std::slice::from_ref(&frame), &CompressionType::Normal)?)?;
+        let convert = |continue_on_error: bool| {
+            let mut arguments = vec!["irongrp", "--mode", "grp-to-png", "--input-path", input_dir, "--output-path", output_dir];
+            if continue_on_error {
+                arguments.push("--continue-on-error");
+            }
+            let result = grp_to_png(&Args::parse_from(arguments));
+            let converted = Path::new(&format!("{}/b_fine", output_dir)).read_dir().map(|entries| entries.count()).unwrap_or(0);
+            let _ = fs::remove_dir_all(output_dir);
+            (result, converted)
+        };
+
+        let (stopped, stopped_converted) = convert(false);
+        let (continued, continued_converted) = convert(true);
+        fs::remove_dir_all(input_dir)?;
+
+        assert!(stopped.is_err());
+        assert_eq!(stopped_converted, 0, "Without continue-on-error, the first failure stops the batch");
+        let error = continued.unwrap_err().to_string();
+        assert!(error.contains("a_broken.grp") && !error.contains("b_fine.grp"), "{}", error);
+        assert_eq!(continued_converted, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_that_grps_decode_to_their_frames() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let frame = |pixels: Vec<u8>| FrameInput {
+            name: "frame.png".to_string(),
+            image: PalettizedImageWithMetadata {
+                x_offset: 2,
+                y_offset: 1,
+                width:    3,
+                height:   2,
+                original_width:  8,
+                original_height: 4,
+                palettized_image: pixels,
+            },
+        };
+        let mut frames = vec![frame(vec![0, 1, 2, 3, 4, 0]), frame(vec![7; 6])];
+        for compression_type in [CompressionType::Normal, CompressionType::Optimised, CompressionType::Uncompressed, CompressionType::War1] {
+            let bytes = encode_grp(&frames, &compression_type)?;
+            verify_grp(&bytes, &frames, &palette, true)?;
+        }
+
+        let bytes = encode_grp(&frames, &CompressionType::Normal)?;
+        frames[1].image.palettized_image[4] = 8;
+        assert_eq!(verify_grp(&bytes, &frames, &palette, true).unwrap_err().kind(), ErrorKind::InvalidData);
+        frames[1].image.palettized_image[4] = 7;
+        frames[0].image.x_offset = 3;
+        assert!(verify_grp(&bytes, &frames, &palette, true).is_err());
+        assert!(verify_grp(&bytes, &frames, &palette, false).is_ok());
+        assert!(verify_grp(&bytes, &frames[..1], &palette, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pads_to_alignment() {
+        let mut bytes = vec![1, 2, 3, 4, 5];
+        pad_to_alignment(&mut bytes, 4);
+        assert_eq!(bytes, vec![1, 2, 3, 4, 5, 0, 0, 0]);
+        pad_to_alignment(&mut bytes, 4);
+        assert_eq!(bytes.len(), 8, "Already aligned GRPs should not be padded");
+    }
+
+    #[test]
+    fn rejects_grps_exceeding_limits() -> Result<()> {
+        use std::io::Cursor;
+        let mut data = vec![0x02, 0x00, 0x10, 0x00, 0x10, 0x00]; // 2 frames, 16x16 size
+        data.extend(vec![0, 0, 16, 16, 22, 0, 0, 0]); // frame header, 16x16
+        data.extend(vec![0, 0,  4,  4, 22, 0, 0, 0]); // frame header, 4x4
+        let header = GrpHeader { frame_count: 2, max_width: 16, max_height: 16 };
+        let mut cursor = Cursor::new(data);
+
+        let limits = |max_frames, max_frame_bytes| GrpLimits { max_frames, max_frame_bytes };
+        assert!(check_grp_limits(&mut cursor, &header, GrpType::Normal, &GrpLimits::default()).is_ok());
+        assert!(check_grp_limits(&mut cursor, &header, GrpType::Normal, &limits(2, 256)).is_ok());
+        assert!(check_grp_limits(&mut cursor, &header, GrpType::Normal, &limits(1, 256)).is_err());
+        assert!(check_grp_limits(&mut cursor, &header, GrpType::Normal, &limits(2, 255)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_war1_style_header() -> Result<()> {
         use std::io::Cursor;
         // Valid header + 1 frame header
@@ -1044,7 +2812,7 @@ mod tests {
         let mut cursor = Cursor::new(data);
         let (header, war1_style) = read_grp_header(&mut cursor)?;
         cursor.seek(SeekFrom::Start(header_len))?;
-        let result = read_grp_frames(&mut cursor, 1, GrpType::War1);
+        let result = read_grp_frames(&mut cursor, 1, GrpType::War1, &GrpReadOptions::default());
         assert_eq!(war1_style, true);
         assert_eq!(header.frame_count, 1);
         assert_eq!(header.max_width,   1);
@@ -1053,11 +2821,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn decode_grp_checked_decodes_grp_bytes() -> Result<()> {
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x01, 0x00]; // 1 frame, 3x1 size
+        data.extend(vec![0, 0, 3, 1, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x02, 0x00, 0x43, 0x07]); // row offset table and one row of colour 7
+
+        let grp = decode_grp_checked(&data)?;
+        assert_eq!((grp.header.frame_count, grp.grp_type), (1, GrpType::Normal));
+        assert_eq!(grp.frames[0].image_data.converted_pixels, vec![7, 7, 7]);
+
+        assert!(decode_grp_checked(&data[..data.len() - 3]).is_err());
+        assert!(decode_grp_checked(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_grp_as_forced_type() -> Result<()> {
+        let path = "test_forced_grp_type.grp";
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x01, 0x00]; // 1 frame, 3x1 size
+        data.extend(vec![0, 0, 3, 1, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x02, 0x00, 0x43, 0x07]); // row offset table and one row of colour 7
+        fs::write(path, &data)?;
+        let mut file = File::open(path)?;
+
+        let (_, war1_style, grp_type) = read_grp_header_and_type(&mut file, path, None)?;
+        assert_eq!((war1_style, grp_type), (false, GrpType::Normal));
+
+        let (_, _, grp_type) = read_grp_header_and_type(&mut file, path, Some(GrpType::Uncompressed))?;
+        assert_eq!(grp_type, GrpType::Uncompressed);
+
+        // The frame headers don't fit the header size of War1 GRPs, and no frame is extended
+        assert!(read_grp_header_and_type(&mut file, path, Some(GrpType::War1)).is_err());
+        assert!(read_grp_header_and_type(&mut file, path, Some(GrpType::UncompressedExtended)).is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
     #[test]
     fn test_decode_transparent_only() {
         let data = vec![0x85]; // skip 5 transparent pixels
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 5);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 5, 0);
 
         assert_eq!(result, vec![0, 0, 0, 0, 0]);
         assert_eq!(encoded_length, data.len());
@@ -1067,7 +2873,7 @@ mod tests {
     fn test_decode_solid_colour_run() {
         let data = vec![0x42, 7]; // repeat colour 7 for 2 pixels
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 2);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 2, 0);
 
         assert_eq!(result, vec![7, 7]);
         assert_eq!(encoded_length, data.len());
@@ -1077,7 +2883,7 @@ mod tests {
     fn test_decode_raw_pixels() {
         let data = vec![3, 5, 6, 7]; // copy 3 pixels directly
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 3);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 3, 0);
 
         assert_eq!(result, vec![5, 6, 7]);
         assert_eq!(encoded_length, data.len());
@@ -1088,20 +2894,84 @@ mod tests {
         let data = vec![0x81, 0x43, 9, 2, 8, 7];
         // skip 1 transparent, repeat 9 for 3, then copy 2 pixels (8, 7)
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 6);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 6, 0);
 
         assert_eq!(result, vec![0, 9, 9, 9, 8, 7]);
         assert_eq!(encoded_length, data.len());
     }
 
 
+    #[test]
+    fn encodes_and_decodes_runs_of_a_non_zero_transparent_index() {
+        let row = vec![255, 255, 0, 0, 0, 4, 255];
+
+        let encoded = encode_grp_rle_row(&row, &CompressionType::Normal, 255);
+        // Index 0 is an ordinary colour, so only index 255 becomes transparent runs
+        assert_eq!(encoded, vec![0x82, 4, 0, 0, 0, 4, 0x81]);
+
+        let (decoded, encoded_length) = decode_grp_rle_row(&encoded, row.len() as u16, 255);
+        assert_eq!(decoded, row);
+        assert_eq!(encoded_length, encoded.len());
+    }
+
+    #[test]
+    fn cuts_frames_out_at_the_offsets_of_the_metadata() -> Result<()> {
+        let path = "test_metadata_frame.png";
+        let mut img = image::RgbaImage::new(4, 3);
+        img.put_pixel(2, 1, image::Rgba([9, 9, 9, 255]));
+        img.save(path).map_err(Error::other)?;
+        let record = FrameRecord {
+            index: 0, x_offset: 1, y_offset: 0, width: 3, height: 2, image_data_offset: 0, reuses_frame: None,
+        };
+
+        let (frames, max_width, max_height) = files_to_grp(
+            vec![path.to_string()],
+            &greyscale_palette()?,
+            &CompressionType::Normal,
+            &FrameReadOptions { frame_records: Some(&[record]), ..Default::default() },
+            &GrpWriteOptions::default(),
+        )?;
+        // The transparent margins of the record are kept, instead of being trimmed away
+        assert_eq!((frames[0].x_offset, frames[0].y_offset, frames[0].width, frames[0].height), (1, 0, 3, 2));
+        assert_eq!(frames[0].image_data.converted_pixels, vec![0, 0, 0, 0, 9, 0]);
+        assert_eq!((max_width, max_height), (4, 3));
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn places_frames_at_the_offsets_in_their_file_names() -> Result<()> {
+        let path = "test_offsets_frame_x1_y0.png";
+        let mut img = image::RgbaImage::new(4, 3);
+        img.put_pixel(2, 1, image::Rgba([9, 9, 9, 255]));
+        img.save(path).map_err(Error::other)?;
+        let files_to_grp_at_offsets = |path: &str| files_to_grp(
+            vec![path.to_string()], &greyscale_palette()?, &CompressionType::Normal,
+            &FrameReadOptions { offsets_in_filenames: true, ..Default::default() }, &GrpWriteOptions::default(),
+        );
+
+        let (frames, _, _) = files_to_grp_at_offsets(path)?;
+        // The frame starts at the offsets of the name, and is trimmed at the bottom and right
+        assert_eq!((frames[0].x_offset, frames[0].y_offset, frames[0].width, frames[0].height), (1, 0, 2, 2));
+        assert_eq!(frames[0].image_data.converted_pixels, vec![0, 0, 0, 9]);
+
+        // Pixels left of the offsets in the name can't be part of the frame
+        let left_of_pixels = "test_offsets_frame_x3_y0.png";
+        fs::rename(path, left_of_pixels)?;
+        assert!(files_to_grp_at_offsets(left_of_pixels).is_err());
+
+        fs::remove_file(left_of_pixels)?;
+        Ok(())
+    }
+
     #[test]
     fn test_encode_transparent_only() {
         // A row with 5 transparent pixels (palette index 0)
         let row = vec![0; 5];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         // 0x80 means transparent run; 0x80 | 5 = 0x85
         assert_eq!(encoded_normal, vec![0x85]);
@@ -1113,8 +2983,8 @@ mod tests {
         // A row with 4 pixels of the same colour (e.g. 7)
         let row = vec![7; 4];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         // 0x40 means repeated colour; 0x40 | 4 = 0x44, followed by the colour
         assert_eq!(encoded_normal, vec![0x44, 7]);
@@ -1126,8 +2996,8 @@ mod tests {
         // A row with 3 different pixels (no repetition)
         let row = vec![5, 6, 7];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         // No compression, just copy 3 pixels: [3, 5, 6, 7]
         assert_eq!(encoded_normal, vec![0x03, 5, 6, 7]);
@@ -1140,8 +3010,8 @@ mod tests {
         // 1 transparent pixel, 3 repeated 9s, and then 2 different pixels
         let row = vec![0, 9, 9, 9, 8, 7];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         // Breakdown:
         // - 0x81: skip 1 transparent
@@ -1156,8 +3026,8 @@ mod tests {
     fn test_encode_max_transparent_run() {
         let row = vec![0; 127];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         assert_eq!(encoded_normal, vec![0xFF]); // 0x80 | 127
         assert_eq!(encoded_optim,  vec![0xFF]); // 0x80 | 127
@@ -1167,8 +3037,8 @@ mod tests {
     fn test_encode_max_solid_colour_run() {
         let row = vec![12; 63];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         assert_eq!(encoded_normal, vec![0x7F, 12]); // 0x40 | 63 = 0x7F
         assert_eq!(encoded_optim,  vec![0x7F, 12]); // 0x40 | 63 = 0x7F
@@ -1178,8 +3048,8 @@ mod tests {
     fn test_encode_max_raw_copy() {
         let row: Vec<u8> = (1..63).collect();
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         let mut expected = vec![62];
         expected.extend(row.iter());
@@ -1191,8 +3061,8 @@ mod tests {
     fn test_encode_alternating_transparency() {
         let row = vec![0, 1, 0, 2, 0, 3];
 
-        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
+        let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
 
         // Should encode as a series of transparent skips and literal copies.
         // Before each literal copy there is a number (here 1 in each case)
@@ -1206,9 +3076,9 @@ mod tests {
         let original = vec![0x8F, 0x02, 0x8A, 0x40, 0x48, 0x8B, 0x04, 0x40, 0x40, 0x40, 0x8A, 0x8F];
         let width = 44;
 
-        let (decoded, encoded_length) = decode_grp_rle_row(&original, width);
-        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised);
+        let (decoded, encoded_length) = decode_grp_rle_row(&original, width, 0);
+        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised, 0);
 
         assert_eq!(encoded_normal, original);
         assert_eq!(encoded_optim,  vec![0x8F, 0x02, 138, 64, 0x48, 139, 0x43, 64, 0x01, 138, 0x8F]);
@@ -1223,9 +3093,9 @@ mod tests {
             0x77, 0x2B, 0x42, 0x43, 0x0A, 0x44, 0x08, 0x06, 0x0A, 0xA1, 0x8C, 0x40, 0x0B, 0x0F, 0x81];
         let width = 44;
 
-        let (decoded, encoded_length) = decode_grp_rle_row(&original, width);
-        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised);
+        let (decoded, encoded_length) = decode_grp_rle_row(&original, width, 0);
+        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised, 0);
 
         let expected_optim = vec![
             0x81, 0x06, 0x0D, 0x43, 0x40, 0x8C, 0xA3, 0x09, 0x44, 0x08, 0x4, 0x0C, 0x42, 0x77,
@@ -1250,9 +3120,9 @@ mod tests {
             0x97, 0x95, 0x8A, 0x81];
         let width = 87;
 
-        let (decoded, encoded_length) = decode_grp_rle_row(&original, width);
-        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised);
+        let (decoded, encoded_length) = decode_grp_rle_row(&original, width, 0);
+        let encoded_normal = encode_grp_rle_row(&decoded, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&decoded, &CompressionType::Optimised, 0);
 
         let expected_optim = vec![
             130, 5, 138, 138, 64, 138, 64, 67, 139, 14, 64, 64, 139, 139, 64, 64, 138, 138,
@@ -1272,10 +3142,10 @@ mod tests {
         let original = vec![0, 0, 7, 7, 7, 8, 9];
         let width = original.len() as u16;
 
-        let encoded_normal = encode_grp_rle_row(&original, &CompressionType::Normal);
-        let encoded_optim  = encode_grp_rle_row(&original, &CompressionType::Optimised);
-        let (decoded_normal, encoded_normal_length) = decode_grp_rle_row(&encoded_normal, width);
-        let (decoded_optim , encoded_optim_length)  = decode_grp_rle_row(&encoded_optim,  width);
+        let encoded_normal = encode_grp_rle_row(&original, &CompressionType::Normal, 0);
+        let encoded_optim  = encode_grp_rle_row(&original, &CompressionType::Optimised, 0);
+        let (decoded_normal, encoded_normal_length) = decode_grp_rle_row(&encoded_normal, width, 0);
+        let (decoded_optim , encoded_optim_length)  = decode_grp_rle_row(&encoded_optim,  width, 0);
 
         assert_eq!(original, decoded_normal);
         assert_eq!(original, decoded_optim);
@@ -1318,7 +3188,7 @@ mod tests {
         // Claims to repeat a colour, but colour byte is missing
         let data = vec![0x41]; // run-length of 1, but no colour follows
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 1);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 1, 0);
 
         // Expect a fallback to default pixel value (0)
         assert_eq!(result, vec![0]);
@@ -1330,25 +3200,272 @@ mod tests {
         // Claims to repeat 5 pixels but only room for 3
         let data = vec![0x45, 7]; // run-length of 5 with colour 7
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 3);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 3, 0);
 
         // Should clamp at width
         assert_eq!(result, vec![7, 7, 7]);
         assert_eq!(encoded_length, data.len());
     }
 
+    #[test]
+    fn decode_row_reports_consumed_length() {
+        let pixels = vec![0, 0, 7, 7, 7, 7, 1, 2, 0];
+        let mut encoded = encode_row(&pixels, &CompressionType::Normal, 0);
+        let encoded_length = encoded.len();
+        encoded.extend([0x43, 0x09]); // Data of the next row is not consumed
+
+        let (decoded, consumed) = decode_row(&encoded, pixels.len() as u16, 0);
+
+        assert_eq!(decoded, pixels);
+        assert_eq!(consumed, encoded_length);
+    }
+
+    #[test]
+    fn decode_row_steps_over_zero_control_bytes() {
+        let data = vec![0x00, 0xFF, 0x42, 0x07];
+
+        let (decoded, consumed) = decode_row(&data, 2, 0);
+
+        assert_eq!(decoded, vec![7, 7]);
+        assert_eq!(consumed, 4);
+    }
+
+    #[test]
+    fn decode_row_consumes_no_more_than_the_data_with_a_truncated_final_run() {
+        let data = vec![0x41, 0x07, 0x00]; // The byte following the final zero control byte is missing
+
+        let (decoded, consumed) = decode_row(&data, 3, 0);
+
+        assert_eq!(decoded, vec![7, 0, 0]);
+        assert_eq!(consumed, data.len());
+    }
+
     #[test]
     fn test_decode_raw_exceeds_data() {
         // Claims to copy 3 pixels but only 2 are present
         let data = vec![3, 1, 2];
 
-        let (result, encoded_length) = decode_grp_rle_row(&data, 3);
+        let (result, encoded_length) = decode_grp_rle_row(&data, 3, 0);
 
         assert_eq!(result, vec![1, 2, 0]);
         assert_eq!(encoded_length, data.len());
     }
 
 
+    #[test]
+    fn fixes_inconsistent_row_offset_tables() -> Result<()> {
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x02, 0x00]; // 1 frame, 3x2 size
+        data.extend(vec![0, 0, 3, 2, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x04, 0x00, 0x04, 0x00]); // row offset table, where the second row is wrong
+        data.extend(vec![0x43, 0x07]); // first row: repeat colour 7 three times
+        data.extend(vec![0x81, 0x02, 0x05, 0x06]); // second row: skip 1, then copy 2 pixels
+
+        let changed_rows = fix_row_offset_tables(&mut data)?;
+        assert_eq!(changed_rows, 1);
+        assert_eq!(data[14..18], [0x04, 0x00, 0x06, 0x00]);
+
+        let frames = read_grp_frames(&mut std::io::Cursor::new(data), 1, GrpType::Normal, &GrpReadOptions::default())?;
+        assert_eq!(frames[0].image_data.converted_pixels, vec![7, 7, 7, 0, 5, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn fixes_row_offsets_pointing_beyond_the_frame_data() -> Result<()> {
+        use clap::Parser;
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x02, 0x00]; // 1 frame, 3x2 size
+        data.extend(vec![0, 0, 3, 2, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x04, 0x00, 0xFF, 0x00]); // row offset table, where the second row is beyond the data
+        data.extend(vec![0x43, 0x07]); // first row: repeat colour 7 three times
+        data.extend(vec![0x81, 0x02, 0x05, 0x06]); // second row: skip 1, then copy 2 pixels
+        let (input_path, output_path) = ("test_fix_row_offsets_input.grp", "test_fix_row_offsets_output.grp");
+        fs::write(input_path, &data)?;
+        assert!(read_grp_file(&input_path.to_string(), &GrpReadOptions::default()).is_err());
+
+        let result = fix_row_offsets(&Args::parse_from([
+            "irongrp", "--mode", "fix-row-offsets", "--input-path", input_path, "--output-path", output_path,
+        ]));
+        let grp = result.and_then(|_| read_grp_file(&output_path.to_string(), &GrpReadOptions::default()));
+        fs::remove_file(input_path)?;
+        let _ = fs::remove_file(output_path);
+
+        assert_eq!(grp?.frames[0].image_data.converted_pixels, vec![7, 7, 7, 0, 5, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn normalizes_grps_whose_rows_share_data() -> Result<()> {
+        use clap::Parser;
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x03, 0x00]; // 1 frame, 3x3 size
+        data.extend(vec![0, 0, 3, 3, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x06, 0x00, 0x08, 0x00, 0x06, 0x00]); // row offset table, where rows 0 and 2 share data
+        data.extend(vec![0x43, 0x07]); // first and third row: repeat colour 7 three times
+        data.extend(vec![0x81, 0x02, 0x05, 0x06]); // second row: skip 1, then copy 2 pixels
+        let (input_path, output_path) = ("test_normalize_shared_rows_input.grp", "test_normalize_shared_rows_output.grp");
+        fs::write(input_path, &data)?;
+
+        let result = fix_row_offsets(&Args::parse_from([
+            "irongrp", "--mode", "fix-row-offsets", "--input-path", input_path, "--output-path", output_path,
+            "--normalize-transparent-runs",
+        ]));
+        let grp = result.and_then(|_| read_grp_file(&output_path.to_string(), &GrpReadOptions::default()));
+        fs::remove_file(input_path)?;
+        let _ = fs::remove_file(output_path);
+
+        let grp = grp?;
+        assert_eq!(grp.frames[0].image_data.converted_pixels, vec![7, 7, 7, 0, 5, 6, 7, 7, 7]);
+        assert_eq!(grp.frames[0].image_data.row_offsets, vec![6, 10, 14], "Rows don't share data when normalized");
+        Ok(())
+    }
+
+    #[test]
+    fn reads_back_rows_with_omitted_trailing_transparent_runs() -> Result<()> {
+        let frame = |x_offset: u8, pixels: Vec<u8>| FrameInput {
+            name: format!("frame at {}", x_offset),
+            image: PalettizedImageWithMetadata {
+                x_offset,
+                y_offset: 0,
+                width:    4,
+                height:   3,
+                original_width:  8,
+                original_height: 3,
+                palettized_image: pixels,
+            },
+        };
+        // Every row ends with transparent pixels, so every row omits its trailing run
+        let frames = vec![
+            frame(0, vec![7, 7, 0, 0, 5, 0, 0, 0, 6, 6, 6, 0]),
+            frame(4, vec![1, 2, 3, 0, 4, 0, 0, 0, 9, 9, 0, 0]),
+        ];
+        let options = GrpWriteOptions { omit_trailing_transparent: true, ..GrpWriteOptions::default() };
+        let path = "test_omitted_trailing_runs.grp";
+        fs::write(path, encode_grp_with_options(&frames, &CompressionType::Normal, &options)?)?;
+        let grp = read_grp_file(&path.to_string(), &GrpReadOptions::default());
+        fs::remove_file(path)?;
+
+        let grp = grp?;
+        for (input, frame) in frames.iter().zip(grp.frames()) {
+            assert_eq!(frame.image_data.converted_pixels, input.image.palettized_image, "{}", input.name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn normalizes_differently_encoded_frames_to_the_same_bytes() -> Result<()> {
+        let encode = |row: &[u8]| {
+            let mut data = vec![0x02, 0x00, 0x06, 0x00, 0x01, 0x00]; // 2 frames, 6x1 size
+            data.extend(vec![0, 0, 6, 1, 22, 0, 0, 0]); // frame headers, both at offset 22
+            data.extend(vec![1, 0, 6, 1, 22, 0, 0, 0]);
+            data.extend(vec![0x02, 0x00]); // row offset table
+            data.extend(row);
+            let grp = decode_grp_checked(&data)?;
+            normalize_frames(&grp.header, grp.frames, 0)
+        };
+        // Two adjacent transparent runs, and a literal copy of four pixels of one colour
+        let first  = encode(&[0x81, 0x81, 0x04, 0x07, 0x07, 0x07, 0x07])?;
+        // One transparent run, and two runs of the same colour
+        let second = encode(&[0x82, 0x42, 0x07, 0x42, 0x07])?;
+
+        assert_eq!(first[0].image_data.raw_row_data, vec![vec![0x82, 0x44, 0x07]]);
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.image_data.raw_row_data, b.image_data.raw_row_data);
+            assert_eq!(a.image_data.row_offsets, b.image_data.row_offsets);
+            assert_eq!((a.x_offset, a.image_data_offset), (b.x_offset, b.image_data_offset));
+        }
+        // The frames still share their image data, but keep their own offsets
+        assert_eq!(first[0].image_data_offset, first[1].image_data_offset);
+        assert_eq!(first[1].x_offset, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_frames_that_cannot_be_read() -> Result<()> {
+        let path = "test_skip_failed_frames.grp";
+        let mut data = vec![0x02, 0x00, 0x03, 0x00, 0x01, 0x00]; // 2 frames, 3x1 size
+        data.extend(vec![0, 0, 3, 1, 22, 0, 0, 0]); // frame headers
+        data.extend(vec![0, 0, 3, 1, 26, 0, 0, 0]);
+        data.extend(vec![0x02, 0x00, 0x43, 0x07]); // first frame: repeat colour 7 three times
+        data.extend(vec![0xFF, 0x00, 0x43, 0x08]); // second frame: row offset beyond the data
+        std::fs::write(path, &data)?;
+
+        assert!(read_grp_file(&path.to_string(), &GrpReadOptions::default()).is_err());
+        let (GrpFile { header, frames, .. }, failed_frames) = read_grp_file_skipping_failed_frames(&path.to_string(), &GrpReadOptions::default())?;
+        std::fs::remove_file(path)?;
+
+        assert_eq!(header.frame_count, 2);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].image_data.converted_pixels, vec![7, 7, 7]);
+        assert_eq!(failed_frames, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn zips_the_frames_that_can_be_converted_with_their_sidecars() -> Result<()> {
+        use clap::Parser;
+        let (input_path, zip_path, metadata_path) = ("test_zip_input.grp", "test_zip_output.zip", "test_zip_metadata.json");
+        let mut data = vec![0x02, 0x00, 0x03, 0x00, 0x01, 0x00]; // 2 frames, 3x1 size
+        data.extend(vec![0, 0, 3, 1, 22, 0, 0, 0]); // frame headers
+        data.extend(vec![0, 0, 3, 1, 26, 0, 0, 0]);
+        data.extend(vec![0x02, 0x00, 0x43, 0x07]); // first frame: repeat colour 7 three times
+        data.extend(vec![0xFF, 0x00, 0x43, 0x08]); // second frame: row offset beyond the data
+        fs::write(input_path, &data)?;
+
+        let result = grp_to_png(&Args::parse_from([
+            "irongrp", "--mode", "grp-to-png", "--input-path", input_path, "--output-path", zip_path,
+            "--metadata", metadata_path, "--continue-on-error",
+        ]));
+        let zip = fs::read(zip_path);
+        let metadata_outside_zip = Path::new(metadata_path).exists();
+        fs::remove_file(input_path)?;
+        let _ = fs::remove_file(zip_path);
+        let _ = fs::remove_file(metadata_path);
+
+        assert!(result.is_err(), "The frame that could not be read is still an error");
+        let zip = zip?;
+        let contains = |name: &str| zip.windows(name.len()).any(|window| window == name.as_bytes());
+        assert!(contains(".png"), "The frame that could be read is in the archive");
+        assert!(contains(metadata_path) && contains("palette.pal"));
+        assert!(!metadata_outside_zip);
+        Ok(())
+    }
+
+    #[test]
+    fn detects_row_offset_tables_missing_rows() -> Result<()> {
+        let mut data = vec![0x01, 0x00, 0x03, 0x00, 0x03, 0x00]; // 1 frame, 3x3 size
+        data.extend(vec![0, 0, 3, 3, 14, 0, 0, 0]); // frame header (offset 14)
+        data.extend(vec![0x04, 0x00, 0x06, 0x00]); // row offset table, lacking the third row
+        data.extend(vec![0x43, 0x07]); // first row: repeat colour 7 three times
+        data.extend(vec![0x81, 0x02, 0x05, 0x06]); // second row: skip 1, then copy 2 pixels
+        data.extend(vec![0x43, 0x08]); // third row: repeat colour 8 three times
+
+        // The row data following the table is read as a third row offset, which is beyond the data
+        let result = read_grp_frames(&mut std::io::Cursor::new(data.clone()), 1, GrpType::Normal, &GrpReadOptions::default());
+        assert!(result.is_err());
+
+        let frames = read_grp_frames(&mut std::io::Cursor::new(data), 1, GrpType::Normal, &GrpReadOptions { assume_table_rows: Some(2), ..Default::default() })?;
+        assert_eq!(frames[0].image_data.converted_pixels, vec![7, 7, 7, 0, 5, 6, 8, 8, 8]);
+        assert_eq!(frames[0].image_data.row_offsets, vec![4, 6, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn reorigins_frames_to_smallest_offsets() -> Result<()> {
+        let mut data = vec![0x02, 0x00, 0x0A, 0x00, 0x0C, 0x00]; // 2 frames, 10x12 size
+        data.extend(vec![3, 5, 2, 1, 22, 0, 0, 0]); // frame header (offset 22)
+        data.extend(vec![4, 2, 2, 1, 22, 0, 0, 0]); // frame header (offset 22)
+        data.extend(vec![0x02, 0x00, 0x42, 0x07]); // row offset table and one row of colour 7
+
+        let (min_x, min_y) = reorigin_frames(&mut data, false)?;
+        assert_eq!((min_x, min_y), (3, 2));
+
+        let (header, _) = read_grp_header(&mut std::io::Cursor::new(&data))?;
+        assert_eq!((header.max_width, header.max_height), (7, 10));
+        let frames = read_grp_frames(&mut std::io::Cursor::new(data), 2, GrpType::Normal, &GrpReadOptions::default())?;
+        assert_eq!((frames[0].x_offset, frames[0].y_offset), (0, 3));
+        assert_eq!((frames[1].x_offset, frames[1].y_offset), (1, 0));
+        assert_eq!(frames[1].image_data.converted_pixels, vec![7, 7]);
+        Ok(())
+    }
+
     #[test]
     fn detects_duplicate_frames() {
         let palette = greyscale_palette().unwrap();
@@ -1367,6 +3484,8 @@ mod tests {
             vec![file1.clone(), file2.clone(), file3.clone()],
             &palette,
             &CompressionType::Normal,
+            &FrameReadOptions::default(),
+            &GrpWriteOptions::default(),
         ).unwrap();
         let frames = result.0;
 
@@ -1382,9 +3501,110 @@ mod tests {
             "Duplicate frames should be identical"
         );
 
+        use clap::Parser;
+        let args = Args::parse_from(["irongrp", "--mode", "png-to-grp", "--compression-type", "normal"]);
+        let duplicates = find_duplicate_frames(&[file1, file2, file3], &palette, &args).unwrap();
+        assert_eq!(duplicates, vec![(2, 0)]);
+
         fs::remove_dir_all(temp_dir).unwrap();
     }
 
+    #[test]
+    fn shares_uncompressed_data_across_offsets_when_asked() -> Result<()> {
+        let palette = greyscale_palette()?;
+        // The same 2x2 square, once in the top left and once in the bottom right of a 4x4 image
+        let square_at = |x: u32, y: u32| -> Vec<u8> {
+            (0..16).flat_map(|i| {
+                let inside = (x..x + 2).contains(&(i % 4)) && (y..y + 2).contains(&(i / 4));
+                if inside { [71, 71, 71, 255] } else { [0, 0, 0, 0] }
+            }).collect()
+        };
+        let images = || vec![square_at(0, 0), square_at(2, 2)].into_iter().enumerate()
+            .map(|(i, buffer)| rgba_to_pixels(&buffer, 4, 4, &palette).map(|image| (format!("image {}", i), image)))
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        for compression_type in [CompressionType::Uncompressed, CompressionType::War1] {
+            let (frames, _, _) = images_to_grp(images(), &compression_type, &GrpWriteOptions::default())?;
+            assert_ne!(frames[0].image_data_offset, frames[1].image_data_offset);
+
+            let options = GrpWriteOptions { share_data_across_offsets: true, ..Default::default() };
+            let (frames, _, _) = images_to_grp(images(), &compression_type, &options)?;
+            assert_eq!(frames[0].image_data_offset, frames[1].image_data_offset);
+            assert_eq!((frames[0].x_offset, frames[0].y_offset), (0, 0));
+            assert_eq!((frames[1].x_offset, frames[1].y_offset), (2, 2));
+        }
+
+        // The same arguments give the same duplicates when previewing them
+        use clap::Parser;
+        let png_files = vec!["test_share_data_0.png".to_string(), "test_share_data_1.png".to_string()];
+        for (png_file, buffer) in png_files.iter().zip([square_at(0, 0), square_at(2, 2)]) {
+            image::RgbaImage::from_raw(4, 4, buffer).unwrap().save(png_file).map_err(Error::other)?;
+        }
+        let duplicates = |share_data_across_offsets: bool| {
+            let args = Args::parse_from(["irongrp", "--mode", "png-to-grp", "--compression-type", "uncompressed"]);
+            find_duplicate_frames(&png_files, &palette, &Args { share_data_across_offsets, ..args })
+        };
+        let (not_shared, shared) = (duplicates(false), duplicates(true));
+        for png_file in &png_files {
+            fs::remove_file(png_file)?;
+        }
+        assert_eq!(not_shared?, vec![]);
+        assert_eq!(shared?, vec![(1, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_offsets_colliding_with_extended_bit() {
+        let frame = |image_data_offset, grp_type| GrpFrame {
+            x_offset: 0, y_offset: 0, width: 1, height: 1,
+            image_data_offset,
+            image_data: ImageData {
+                row_offsets: vec![],
+                raw_row_data: vec![vec![0]],
+                converted_pixels: vec![0],
+                grp_type,
+            },
+        };
+        assert!(verify_extended_offset_bits(&[
+            frame(0x0000_0010, GrpType::Uncompressed),
+            frame(0x8000_0011, GrpType::UncompressedExtended),
+        ]).is_ok());
+        assert!(verify_extended_offset_bits(&[frame(0x8000_0010, GrpType::Uncompressed)]).is_err());
+        assert!(verify_extended_offset_bits(&[frame(0x8000_0010, GrpType::Normal)]).is_err());
+        assert!(verify_extended_offset_bits(&[frame(0x0000_0010, GrpType::UncompressedExtended)]).is_err());
+    }
+
+    #[test]
+    fn finds_smallest_rle_encoding() {
+        // Two identical rows ending with a transparent run, and one all-transparent row
+        let pixels = [7, 7, 0, 0, 7, 7, 0, 0, 0, 0, 0, 0];
+        let (size, description) = smallest_rle_encoding(4, 3, &pixels, 0);
+
+        // Row offset table, one shared row with a run of 7s, and the transparent row
+        let mut row = encode_row(&[7, 7, 0, 0], &CompressionType::Normal, 0);
+        omit_trailing_transparent_run(&mut row);
+        let transparent_row = encode_row(&[0, 0, 0, 0], &CompressionType::Normal, 0);
+        assert_eq!(size, 3 * 2 + row.len() + transparent_row.len());
+        assert_eq!(description, "Normal, omitting trailing transparent runs");
+    }
+
+    #[test]
+    fn samples_frames_evenly() {
+        assert_eq!(sample_frame_numbers(3, 8), vec![0, 1, 2]);
+        assert_eq!(sample_frame_numbers(16, 4), vec![0, 4, 8, 12]);
+        assert_eq!(sample_frame_numbers(0, 8), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn finds_indices_missing_from_palette() {
+        let indices = HashSet::from([0, 15, 16, 200]);
+        let mut missing = indices_missing_from_palette(&indices, 16);
+        missing.sort();
+        assert_eq!(missing, vec![16, 200]);
+        assert!(indices_missing_from_palette(&indices, FULL_PALETTE_LEN).is_empty());
+    }
+
     #[test]
     fn does_not_deduplicate_different_frames() {
         let palette = greyscale_palette().unwrap();
@@ -1401,6 +3621,8 @@ mod tests {
             vec![file_a.clone(), file_b.clone()],
             &palette,
             &CompressionType::Normal,
+            &FrameReadOptions::default(),
+            &GrpWriteOptions::default(),
         ).unwrap();
         let frames = result.0;
 
@@ -1414,12 +3636,270 @@ mod tests {
         fs::remove_dir_all(temp_dir).unwrap();
     }
 
+    #[test]
+    fn centres_cropped_frames_on_canvas_of_first_frame() {
+        let palette = greyscale_palette().unwrap();
+        let temp_dir = "temp_test_output_canvas";
+        fs::create_dir_all(temp_dir).unwrap();
+
+        let file1 = format!("{}/frame1.png", temp_dir);
+        let file2 = format!("{}/frame2.png", temp_dir);
+
+        create_test_png(&file1, [71, 71, 71], 16, 16);
+        create_test_png(&file2, [42, 42, 42],  8,  6); // cropped by an image editor
+
+        let (frames, max_width, max_height) = files_to_grp(
+            vec![file1.clone(), file2.clone()],
+            &palette,
+            &CompressionType::Normal,
+            &FrameReadOptions::default(),
+            &GrpWriteOptions { canvas_from_first: true, ..Default::default() },
+        ).unwrap();
+
+        assert_eq!((frames[0].x_offset, frames[0].y_offset), (0, 0));
+        assert_eq!((frames[1].x_offset, frames[1].y_offset), (4, 5));
+        assert_eq!((frames[1].width,    frames[1].height),   (8, 6));
+        assert_eq!((max_width, max_height), (16, 16));
+
+        let result = files_to_grp(
+            vec![file2.clone(), file1.clone()],
+            &palette,
+            &CompressionType::Normal,
+            &FrameReadOptions::default(),
+            &GrpWriteOptions { canvas_from_first: true, ..Default::default() },
+        );
+        assert!(result.is_err(), "Canvases bigger than the first one should be rejected");
+
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn finds_pngs_too_wide_for_normal_grps() {
+        let palette = greyscale_palette().unwrap();
+        let temp_dir = "temp_test_output_wide";
+        fs::create_dir_all(temp_dir).unwrap();
+
+        let narrow = format!("{}/narrow.png", temp_dir);
+        let wide   = format!("{}/wide.png",   temp_dir);
+        create_test_png(&narrow, [71, 71, 71], 255, 4);
+        create_test_png(&wide,   [42, 42, 42], 300, 4);
+
+        let result = files_to_grp(vec![narrow.clone(), wide.clone()], &palette, &CompressionType::Normal, &FrameReadOptions::default(), &GrpWriteOptions::default());
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("--compression-type uncompressed"), "Unexpected error: {}", message);
+
+        let frames = files_to_frame_inputs(vec![narrow, wide.clone()], &palette, &FrameReadOptions::default()).unwrap();
+        assert!(find_too_wide_frame(&frames[..1]).is_none());
+        assert_eq!(find_too_wide_frame(&frames).map(|frame| &frame.name), Some(&wide));
+
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    /// Creates a PNG with a transparent border around a pattern of opaque grey pixels, some of
+    /// which are transparent, so that both trimming and transparent runs are exercised.
+    fn create_patterned_png(path: &str, seed: u8, width: u32, height: u32) {
+        use image::{Rgba, RgbaImage};
+        let mut img = RgbaImage::new(width, height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let on_border = x < 2 || y < 1 || x >= width - 3 || y >= height - 2;
+            let value = (x * 7 + y * 3) as u8 ^ seed;
+            *pixel = if on_border || value.is_multiple_of(5) {
+                Rgba([0, 0, 0, 0])
+            } else {
+                Rgba([value, value, value, 255])
+            };
+        }
+        img.save(path).expect("Failed to save test PNG");
+    }
+
+    #[test]
+    fn multi_frame_grp_roundtrips_for_all_compression_types() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let temp_dir = "temp_test_output_roundtrip";
+        fs::create_dir_all(temp_dir)?;
+
+        let mut png_files = vec![];
+        for (i, (width, height)) in [(16, 12), (40, 30), (16, 12), (64, 8)].iter().enumerate() {
+            let path = format!("{}/frame_{:03}.png", temp_dir, i);
+            create_patterned_png(&path, i as u8 % 2 * 13, *width, *height);
+            png_files.push(path);
+        }
+        let wide_png = format!("{}/wide.png", temp_dir);
+        create_patterned_png(&wide_png, 71, 300, 20);
+
+        let cases = [
+            (CompressionType::Normal,       GrpType::Normal,       png_files.clone()),
+            (CompressionType::Optimised,    GrpType::Normal,       png_files.clone()),
+            (CompressionType::Uncompressed, GrpType::Uncompressed, [png_files.clone(), vec![wide_png]].concat()),
+            (CompressionType::War1,         GrpType::War1,         png_files.clone()),
+        ];
+        for (compression_type, grp_type, pngs) in cases {
+            let grp_path = format!("{}/{}.grp", temp_dir, compression_type);
+            let (frames, max_width, max_height) = files_to_grp(pngs.clone(), &palette, &compression_type, &FrameReadOptions::default(), &GrpWriteOptions::default())?;
+            let grp = GrpFile { header: create_grp_header(&frames, max_width, max_height), frames, grp_type };
+            grp.write(&mut File::create(&grp_path)?, &compression_type)?;
+
+            let mut file = File::open(&grp_path)?;
+            let (header, _) = read_grp_header(&mut file)?;
+            assert_eq!(header.frame_count as usize, pngs.len());
+            assert_eq!((header.max_width, header.max_height), (max_width, max_height));
+            let read_frames = read_grp_frames(&mut file, header.frame_count, grp_type, &GrpReadOptions::default())?;
+
+            for (i, (frame, png)) in read_frames.iter().zip(&pngs).enumerate() {
+                let expected = png_to_pixels(png, &palette, TrimEdges::ALL, ColourMapping::default())?;
+                let width = adjust_width_and_offset_if_extended_when_decoding(frame.width, frame.image_data_offset).0;
+                let width = if grp_type == GrpType::Normal { frame.width as u16 } else { width };
+                assert_eq!(
+                    (frame.x_offset, frame.y_offset, width, frame.height as u16),
+                    (expected.x_offset, expected.y_offset, expected.width, expected.height),
+                    "Frame {} of {} GRP has the wrong dimensions", i, compression_type,
+                );
+                assert_eq!(
+                    frame.image_data.converted_pixels, expected.palettized_image,
+                    "Frame {} of {} GRP has the wrong pixels", i, compression_type,
+                );
+            }
+        }
+
+        fs::remove_dir_all(temp_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn multi_frame_war1_grp_with_duplicates_roundtrips() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let temp_dir = "temp_test_output_war1_roundtrip";
+        fs::create_dir_all(temp_dir)?;
+
+        let mut png_files = vec![];
+        for (i, (seed, width, height)) in [(0, 16, 12), (13, 24, 20), (0, 16, 12), (42, 8, 30)].iter().enumerate() {
+            let path = format!("{}/frame_{:03}.png", temp_dir, i);
+            create_patterned_png(&path, *seed, *width, *height);
+            png_files.push(path);
+        }
+        let grp_path = format!("{}/war1.grp", temp_dir);
+        let (frames, max_width, max_height) = files_to_grp(png_files.clone(), &palette, &CompressionType::War1, &FrameReadOptions::default(), &GrpWriteOptions::default())?;
+        let grp = GrpFile { header: create_grp_header(&frames, max_width, max_height), frames, grp_type: GrpType::War1 };
+        grp.write(&mut File::create(&grp_path)?, &CompressionType::War1)?;
+        let frames = grp.frames;
+
+        let data = fs::read(&grp_path)?;
+        let mut written_offsets = HashSet::new();
+        let unique_data_len: usize = frames.iter()
+            .filter(|frame| written_offsets.insert(frame.image_data_offset))
+            .map(|frame| frame.actual_width() as usize * frame.height as usize)
+            .sum();
+        // The header of WarCraft I GRPs is 4 bytes, and the duplicated frame is only written once
+        assert_eq!(data.len(), 4 + frames.len() * 8 + unique_data_len);
+
+        let GrpFile { header, frames: read_frames, grp_type } = read_grp_file(&grp_path, &GrpReadOptions::default())?;
+        assert_eq!(grp_type, GrpType::War1);
+        assert_eq!((header.frame_count as usize, header.max_width, header.max_height), (png_files.len(), max_width, max_height));
+        assert_eq!(read_frames[2].image_data_offset, read_frames[0].image_data_offset, "Identical frames should share data");
+        assert_ne!(read_frames[1].image_data_offset, read_frames[0].image_data_offset);
+        for (i, (frame, png)) in read_frames.iter().zip(&png_files).enumerate() {
+            let expected = png_to_pixels(png, &palette, TrimEdges::ALL, ColourMapping::default())?;
+            assert_eq!(
+                (frame.x_offset, frame.y_offset, frame.width as u16, frame.height as u16),
+                (expected.x_offset, expected.y_offset, expected.width, expected.height),
+                "Frame {} has the wrong dimensions", i,
+            );
+            assert_eq!(frame.image_data.converted_pixels, expected.palettized_image, "Frame {} has the wrong pixels", i);
+        }
+
+        fs::remove_dir_all(temp_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_frames_from_rgba_buffers() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let transparent = [0, 0, 0, 0];
+        let grey = |v: u8| [v, v, v, 255];
+        let frame = |pixels: Vec<[u8; 4]>| pixels.concat();
+        let buffers = vec![
+            (frame(vec![transparent, transparent, transparent, grey(7), grey(9), transparent]), 3, 2),
+            (frame(vec![grey(1), grey(2)]), 2, 1),
+            (frame(vec![transparent, transparent, transparent, grey(7), grey(9), transparent]), 3, 2),
+        ];
+
+        let frames = frames_from_buffers(buffers, &palette, &CompressionType::Normal)?;
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].x_offset, frames[0].y_offset, frames[0].width, frames[0].height), (0, 1, 2, 1));
+        assert_eq!(frames[0].image_data.converted_pixels, vec![7, 9]);
+        assert_eq!(frames[1].image_data.converted_pixels, vec![1, 2]);
+        assert_eq!(frames[2].image_data_offset, frames[0].image_data_offset, "Identical frames should share data");
+
+        let wrong_size = vec![(vec![0u8; 7], 1, 2)];
+        assert!(frames_from_buffers(wrong_size, &palette, &CompressionType::Normal).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn maps_colours_to_the_palette_given_for_each_call() -> Result<()> {
+        let mut palette_a = vec![[255, 255, 255]; 16];
+        let mut palette_b = palette_a.clone();
+        palette_a[5] = [10, 10, 10];
+        palette_b[9] = [10, 10, 10];
+        let buffers = || vec![(vec![10, 10, 10, 255], 1, 1)];
+
+        let frames_a = frames_from_buffers(buffers(), &palette_a, &CompressionType::Normal)?;
+        let frames_b = frames_from_buffers(buffers(), &palette_b, &CompressionType::Normal)?;
+
+        assert_eq!(frames_a[0].image_data.converted_pixels, vec![5]);
+        assert_eq!(frames_b[0].image_data.converted_pixels, vec![9]);
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_to_write_image_data_at_other_offset_than_declared() -> Result<()> {
+        let palette = greyscale_palette()?;
+        let buffers = vec![
+            (vec![7, 7, 7, 255, 9, 9, 9, 255], 2, 1),
+            (vec![1, 1, 1, 255, 2, 2, 2, 255], 2, 1),
+        ];
+        let frames = frames_from_buffers(buffers, &palette, &CompressionType::Uncompressed)?;
+        let mut grp = GrpFile { header: create_grp_header(&frames, 2, 1), frames, grp_type: GrpType::Uncompressed };
+
+        assert!(grp.write(&mut Vec::new(), &CompressionType::Uncompressed).is_ok());
+
+        grp.frames[1].image_data_offset += 1;
+        let result = grp.write(&mut Vec::new(), &CompressionType::Uncompressed);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn omitting_trailing_transparent_runs_decodes_identically() {
+        let rows = vec![
+            vec![5, 5, 5, 5, 0, 0, 0],
+            vec![0, 0, 3, 4, 0, 0],
+            vec![1, 2, 3],
+            vec![0, 0, 0, 0],
+            vec![7; 200].into_iter().chain(vec![0; 200]).collect(),
+        ];
+        for row in rows {
+            let encoded = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+            let mut omitted = encoded.clone();
+            omit_trailing_transparent_run(&mut omitted);
+
+            assert!(!omitted.is_empty());
+            assert_eq!(decode_grp_rle_row(&omitted, row.len() as u16, 0).0, decode_grp_rle_row(&encoded, row.len() as u16, 0).0);
+            assert_eq!(decode_grp_rle_row(&omitted, row.len() as u16, 0).0, row);
+        }
+
+        let mut encoded = encode_grp_rle_row(&[5, 5, 5, 5, 0, 0, 0], &CompressionType::Normal, 0);
+        omit_trailing_transparent_run(&mut encoded);
+        assert_eq!(encoded, vec![0x44, 0x05]);
+    }
+
     fn perform_row_tests(test_cases: Vec<Vec<u8>>) {
         for row in test_cases {
-            let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal);
-            let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised);
-            let (decoded_normal, encoded_normal_length) = decode_grp_rle_row(&encoded_normal, row.len() as u16);
-            let (decoded_optim , encoded_optim_length)  = decode_grp_rle_row(&encoded_optim,  row.len() as u16);
+            let encoded_normal = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+            let encoded_optim  = encode_grp_rle_row(&row, &CompressionType::Optimised, 0);
+            let (decoded_normal, encoded_normal_length) = decode_grp_rle_row(&encoded_normal, row.len() as u16, 0);
+            let (decoded_optim , encoded_optim_length)  = decode_grp_rle_row(&encoded_optim,  row.len() as u16, 0);
 
             assert_eq!(decoded_normal, row);
             assert_eq!(decoded_optim,  row);
@@ -1429,6 +3909,27 @@ mod tests {
     }
 
 
+    // Property-based test: decoding arbitrary bytes must give an error rather than panic,
+    // both for random data and for data starting with a plausible header.
+    proptest! {
+        #[test]
+        fn prop_decode_grp_checked_never_panics(bytes in proptest::collection::vec(0u8..=255, 0..256)) {
+            let _ = decode_grp_checked(&bytes);
+        }
+
+        #[test]
+        fn prop_decode_grp_checked_never_panics_with_header(
+            frame_count in 1u8..4,
+            max_width  in 1u8..32,
+            max_height in 1u8..32,
+            body in proptest::collection::vec(0u8..=255, 0..256),
+        ) {
+            let mut bytes = vec![frame_count, 0, max_width, 0, max_height, 0];
+            bytes.extend(body);
+            let _ = decode_grp_checked(&bytes);
+        }
+    }
+
     // Property-based test: for any randomly generated row of pixel values (between 0 and 255),
     // the function encodes the row with GRP RLE compression, then decodes it back again.
     // The output must exactly match the original input.
@@ -1440,13 +3941,18 @@ mod tests {
         #[test]
         fn prop_encode_decode_roundtrip(row in proptest::collection::vec(0u8..=255, 0..128)) {
             let width = row.len();
-            let encoded = encode_grp_rle_row(&row, &CompressionType::Normal);
-            let (decoded, encoded_length) = decode_grp_rle_row(&encoded, width as u16);
+            let encoded = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+            let (decoded, encoded_length) = decode_grp_rle_row(&encoded, width as u16, 0);
             prop_assert_eq!(decoded, row);
             prop_assert_eq!(encoded_length, encoded.len());
         }
+
+        #[test]
+        fn prop_omitting_trailing_transparent_decodes_identically(row in proptest::collection::vec(0u8..=3, 0..128)) {
+            let mut encoded = encode_grp_rle_row(&row, &CompressionType::Normal, 0);
+            omit_trailing_transparent_run(&mut encoded);
+            let (decoded, _) = decode_grp_rle_row(&encoded, row.len() as u16, 0);
+            prop_assert_eq!(decoded, row);
+        }
     }
 }
-
-const EXTENDED_OFFSET_BIT: u32 = 0x8000_0000;
-pub const EXTENDED_IMAGE_WIDTH: u16 = 256;
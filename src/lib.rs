@@ -1,21 +1,26 @@
 use clap::{Parser, ValueEnum, ValueHint};
+use crate::grp::GrpType;
+use crate::palpng::ColourMetric;
 use clap_complete::Shell;
 use simplelog::LevelFilter;
 use std::fmt;
 use std::fs;
 use std::io::{Error, ErrorKind};
-use std::sync::OnceLock;
 
 pub mod analyse;
 pub mod grp;
+pub mod palette;
+pub mod palpng;
 pub mod png;
+pub mod zip;
 
-pub static LOG_LEVEL: OnceLock<LogLevel> = OnceLock::new();
-
-#[derive(Parser)]
+#[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Path to the GRP file, or directory containing PNG files
+    /// Path to the GRP file, or directory containing PNG files.
+    /// For the 'analyse-shared-data' mode, a directory containing GRP files.
+    /// For the 'grp-to-png' mode, a directory containing GRP files
+    /// converts each of them, to a directory named after the GRP.
     #[arg(long, short='i', value_hint = ValueHint::AnyPath)]
     pub input_path: Option<String>,
 
@@ -23,8 +28,25 @@ pub struct Args {
     #[arg(long, short='p', value_hint = ValueHint::FilePath)]
     pub pal_path: Option<String>,
 
-    /// Output directory if input is a GRP file,
-    /// or output file if input is a directory
+    /// Fail if the palette file does not contain
+    /// all 256 palette entries.
+    #[arg(long)]
+    pub require_full_palette: bool,
+
+    /// Only applicable when using the 'grp-to-png' or 'analyse-grp' modes.
+    /// Read the palette from the last 768 bytes of the input GRP
+    /// file, instead of from a separate palette file.
+    #[arg(long)]
+    pub embedded_palette: bool,
+
+    /// Only applicable for the 'cross-diff' mode.
+    /// Path to the GRP file to compare the input GRP to
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub other_path: Option<String>,
+
+    /// Output directory if input is a GRP file, or a ZIP
+    /// archive to bundle the output in if it ends with
+    /// '.zip'. Output file if input is a directory
     #[arg(long, short='o', value_hint = ValueHint::AnyPath)]
     pub output_path: Option<String>,
 
@@ -32,14 +54,141 @@ pub struct Args {
     #[arg(long, short='m', value_enum)]
     pub mode: Option<OperationMode>,
 
+    /// Pad the created GRP file with zero bytes, until its
+    /// size is a multiple of the given number of bytes.
+    /// Only RLE compressed GRPs can be padded.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub pad_to: Option<u32>,
+
+    /// Write a manifest next to the created GRP file,
+    /// listing the names of the PNGs it was created from.
+    /// When converting the GRP to PNGs, the frames will
+    /// get those names back if the manifest is present.
+    #[arg(long)]
+    pub write_source_manifest: bool,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// After writing the GRP, decode it again and check
+    /// that every frame has the palette indices of its PNG.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Don't trim away rows of transparent pixels at the
+    /// top of the PNGs when creating GRP files.
+    #[arg(long)]
+    pub no_trim_top: bool,
+
+    /// Don't trim away rows of transparent pixels at the
+    /// bottom of the PNGs when creating GRP files. Useful
+    /// for sprites that are anchored at the bottom.
+    #[arg(long)]
+    pub no_trim_bottom: bool,
+
+    /// Don't trim away columns of transparent pixels to
+    /// the left of the PNGs when creating GRP files.
+    #[arg(long)]
+    pub no_trim_left: bool,
+
+    /// Don't trim away columns of transparent pixels to
+    /// the right of the PNGs when creating GRP files.
+    #[arg(long)]
+    pub no_trim_right: bool,
+
+    /// Don't trim away transparent pixels at any edge of
+    /// the PNGs when creating GRP files, so that every
+    /// frame has the size of its PNG and the offsets 0, 0.
+    #[arg(long)]
+    pub no_trim: bool,
+
     /// Compression type to use when creating GRP files.
     /// If omitted or set to 'auto', it will use 'normal'
     /// compression, unless any of the input PNG file names
     /// contains the string "uncompressed" or "war1".
     /// If so, it will use the corresponding compression.
+    /// Must be given when using the 'transcode-grp' mode.
     #[arg(long, value_enum, default_value_t = CompressionType::Auto)]
     pub compression_type: CompressionType,
 
+    /// Only applicable when creating Uncompressed or War1
+    /// GRPs. Frames with identical pixels share image data
+    /// even if their offsets differ, since the offsets are
+    /// stored in the frame headers. By default, the offsets
+    /// must also be identical for the data to be shared.
+    #[arg(long)]
+    pub share_data_across_offsets: bool,
+
+    /// Only applicable when using the 'fix-row-offsets' mode.
+    /// Re-encode the frames from their pixels in a canonical form,
+    /// merging transparent runs and splitting runs consistently.
+    #[arg(long)]
+    pub normalize_transparent_runs: bool,
+
+    /// Only applicable when creating Normal or Optimised
+    /// GRPs. Don't encode transparent runs at the end of
+    /// rows; the rows end early instead. Decodes the same,
+    /// but some tools produce GRPs encoded this way.
+    #[arg(long)]
+    pub omit_trailing_transparent: bool,
+
+    /// Only applicable when creating Normal or Optimised
+    /// GRPs. If any frame is too wide for those, i.e.
+    /// wider than 255 pixels, the GRP will be created as
+    /// Uncompressed instead of giving an error.
+    #[arg(long)]
+    pub auto_uncompress_wide: bool,
+
+    /// Skip the conversion if it has already been done
+    /// with the same input files and arguments, according
+    /// to a manifest in the output directory. Completed
+    /// conversions are recorded in the manifest, so that
+    /// an interrupted batch of conversions can be resumed.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// Use this palette index for all colours that have
+    /// no exact match in the palette, instead of the
+    /// nearest colour, so that mistakes are easy to spot.
+    #[arg(long)]
+    pub inexact_fallback_index: Option<u8>,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// Read greyscale PNGs (with or without alpha) as
+    /// maps of palette indices, where the luminance of
+    /// each pixel is its index, instead of matching the
+    /// colours against the palette. Pixels with an alpha
+    /// of 0 are transparent.
+    #[arg(long)]
+    pub luminance_as_index: bool,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// Use the palette indices of indexed PNGs as they
+    /// are, if their palette matches the given palette.
+    /// Other PNGs have their colours matched as usual.
+    #[arg(long)]
+    pub preserve_indices: bool,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// Map colours to the palette with Floyd–Steinberg
+    /// dithering, to avoid banding in gradients. Off by
+    /// default, so that exact colour matches are kept.
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// How to measure the distance between colours when
+    /// finding the nearest palette colour. 'lab' compares
+    /// them perceptually, which suits saturated colours.
+    #[arg(long, value_enum, default_value_t = ColourMetric::Rgb)]
+    pub color_metric: ColourMetric,
+
+    /// Only applicable when using the 'png-to-grp' mode.
+    /// Treat all PNGs as having the same canvas size as
+    /// the first PNG. PNGs with smaller canvases (e.g.,
+    /// cropped by an image editor) are centred on it.
+    #[arg(long)]
+    pub canvas_from_first: bool,
+
     /// Output all frames in one image. GRPs cannot be
     /// created back from tiled images.
     #[arg(long)]
@@ -52,32 +201,306 @@ pub struct Args {
     #[arg(long)]
     pub max_width: Option<u32>,
 
+    /// Only applicable when using the 'tiled' argument.
+    /// Number of columns of the output tiled image, instead
+    /// of the square root of the number of frames. With the
+    /// 'max-width' argument, there may be fewer columns, so
+    /// that the image fits, but never fewer than 1.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub columns: Option<u32>,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// Maximum height in pixels of the output tiled image.
+    /// Gives an error if the rows of frames would make the
+    /// image higher than this.
+    #[arg(long)]
+    pub max_height: Option<u32>,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// Width in pixels of the gap between the tiles of the
+    /// output tiled image. The gaps are transparent with
+    /// the 'use-transparency' argument.
+    #[arg(long, default_value_t = 0)]
+    pub tile_gap: u32,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// Draws the frame number in the top left corner of
+    /// each tile, in the colour of the palette that
+    /// contrasts the most with the pixels beneath it.
+    #[arg(long)]
+    pub label_frames: bool,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// Also writes a JSON file next to the tiled image,
+    /// describing where each frame is, in the given format.
+    #[arg(long, value_enum)]
+    pub atlas_format: Option<AtlasFormat>,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// Only draws one tile per unique frame, and writes
+    /// a JSON file next to the tiled image, describing
+    /// which frame numbers each tile represents.
+    #[arg(long)]
+    pub tiled_unique_only: bool,
+
+    /// Only applicable when using the 'tiled' argument.
+    /// The number of angles (directions) that each animation
+    /// frame is drawn in, e.g., 17 for StarCraft units. Each
+    /// row of the tiled image will then be one animation
+    /// frame, with the angles progressing across the columns.
+    /// A JSON file describing the animation frame and angle
+    /// of each tile is written next to the tiled image.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub angles: Option<u32>,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Also writes the palette that the images are drawn
+    /// with to this path, as a raw RGB palette of 768 bytes.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub dump_palette: Option<String>,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Also save a swatch of the palette as an image in
+    /// the output directory, with one square per entry.
+    #[arg(long)]
+    pub with_palette_swatch: bool,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Save 8-bit indexed PNGs with the palette, where
+    /// every pixel keeps its palette index, and the
+    /// transparent index is transparent.
+    #[arg(long)]
+    pub indexed_output: bool,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Also save the frames as an animated GIF to this
+    /// path, drawn at their offsets with the palette of
+    /// the GRP, and with the transparent index
+    /// transparent.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub gif: Option<String>,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Also save the frames as an animated PNG to this
+    /// path, drawn at their offsets like the other
+    /// output images, so that 'use-transparency' gives
+    /// a clean alpha channel.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub apng: Option<String>,
+
+    /// Only applicable when using the 'gif' or 'apng'
+    /// arguments. The number of milliseconds that each
+    /// frame of the animation is shown. GIFs store delays
+    /// in hundredths of a second, so it is rounded to the
+    /// nearest of those.
+    #[arg(long, default_value_t = 100)]
+    pub frame_delay: u32,
+
+    /// Only applicable when using the 'gif' or 'apng'
+    /// arguments. A file with the number of milliseconds
+    /// that individual frames are shown, with the other
+    /// frames shown for the frame delay. Either JSON that
+    /// maps frame numbers to durations, e.g. {"0": 500},
+    /// or text with a frame number and a duration on
+    /// each line, e.g. "0 500".
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub frame_durations: Option<String>,
+
+    /// Only applicable when using the 'gif' or 'apng'
+    /// arguments. The number of times to play the
+    /// animation, where 0 plays it forever.
+    #[arg(long, default_value_t = 0)]
+    pub loop_count: u32,
+
+    /// Only applicable when using the 'grp-to-png' or
+    /// 'png-to-grp' modes. A JSON file describing the
+    /// offsets, dimensions and shared image data of every
+    /// frame. It is written when converting a GRP, and
+    /// read when converting PNGs, where the frames are
+    /// cut out at the described offsets and dimensions
+    /// instead of trimming the PNGs.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub metadata: Option<String>,
+
+    /// Only applicable when using the 'grp-to-png' or
+    /// 'png-to-grp' modes. Name the PNGs of the frames
+    /// after their offsets, like 'frame_003_x12_y8.png',
+    /// and read the offsets of the frames from the names
+    /// of the PNGs instead of trimming them at the top
+    /// and left.
+    #[arg(long)]
+    pub offsets_in_filenames: bool,
+
+    /// Only applicable when using the 'grp-to-png' mode
+    /// without the 'tiled' argument. Skip frames that
+    /// fail to be read or saved, and convert the rest,
+    /// failing at the end if any frame was skipped.
+    #[arg(long)]
+    pub continue_on_error: bool,
+
+    /// Only applicable when using the 'grp-to-png' mode.
+    /// Instead of converting, reads a sample of the frames
+    /// and checks that the palette has entries for all
+    /// palette indices that they use. Gives an error if
+    /// not, since the palette probably is the wrong one.
+    #[arg(long)]
+    pub check: bool,
+
+    /// When converting a GRP to separate PNGs, only save one
+    /// image per unique frame, named after the lowest frame
+    /// number with that content, and write a JSON file
+    /// describing which frame numbers each image represents.
+    #[arg(long)]
+    pub export_unique_only: bool,
+
+    /// When converting a GRP to separate PNGs, also report
+    /// frames that have identical content but don't share
+    /// image data in the GRP, as well as frames with
+    /// identical content that are drawn at different
+    /// positions. This requires hashing every frame,
+    /// which makes the conversion slower.
+    #[arg(long)]
+    pub report_content_duplicates: bool,
+
+    /// When converting a GRP to separate images, also
+    /// write a horizontally mirrored version of each
+    /// frame, with the suffix '_mirror', the way that
+    /// the game mirrors frames to draw more directions.
+    #[arg(long)]
+    pub export_mirrored: bool,
+
     /// Only outputs or analyses the given frame number.
     #[arg(long)]
     pub frame_number: Option<u16>,
 
+    /// Only outputs or analyses the frames in the given
+    /// range, like '17..24', where both ends are included.
+    #[arg(long)]
+    pub frame_range: Option<FrameRange>,
+
     /// Output the data of the given row number for the given frame.
     #[arg(long)]
     pub analyse_row_number: Option<u8>,
 
+    /// Only applicable for the 'analyse-grp' mode. With
+    /// 'svg', a diagram of the file layout is also
+    /// written to the output path. With 'json', a report
+    /// of the findings is printed as JSON instead of text.
+    #[arg(long, value_enum, default_value_t = AnalyseFormat::Text)]
+    pub analyse_format: AnalyseFormat,
+
+    /// Only applicable when using the 'analyse-row-number'
+    /// argument. Writes the raw bytes of the analysed row
+    /// to the given file.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub dump_row_to: Option<String>,
+
+    /// Only applicable when using the 'frame-number'
+    /// argument in the 'analyse-grp' mode. Prints the
+    /// palette indices of all pixels of the frame.
+    /// Only frames of at most 64x64 pixels are printed.
+    #[arg(long)]
+    pub print_pixels: bool,
+
+    /// Only applicable when using the 'analyse-grp' mode.
+    /// Reports how many pixels of each frame differ from
+    /// the first frame and from the previous frame, when
+    /// drawn on the canvas.
+    #[arg(long)]
+    pub delta_report: bool,
+
+    /// Draw each pixel with a colour derived from its
+    /// palette index instead of using the palette, going
+    /// from red at low indices to magenta at high ones.
+    /// Useful for seeing where palette ramps are used.
+    #[arg(long)]
+    pub index_heatmap: bool,
+
+    /// Image format of the output files when converting
+    /// a GRP. WebP images are lossless, and typically
+    /// much smaller than PNGs for large tiled images.
+    #[arg(long, value_enum, default_value_t = ImageFormat::Png)]
+    pub output_format: ImageFormat,
+
     /// Enable transparency in the PNG images. Default
     /// behavior is to use index 0 in the palette.
     #[arg(long)]
     pub use_transparency: bool,
 
+    /// Not applicable when using the 'use-transparency'
+    /// argument. The palette index whose colour the parts
+    /// of the images outside of the frames are filled with.
+    /// Default is black.
+    #[arg(long)]
+    pub background_index: Option<u8>,
+
+    /// Only applicable when using the 'use-transparency'
+    /// argument. The alpha value to give all pixels that
+    /// are not transparent. Lower values give translucent
+    /// images, which can be useful for previews.
+    #[arg(long, default_value_t = u8::MAX)]
+    pub opaque_alpha: u8,
+
+    /// The palette index that is transparent. It is
+    /// written as transparent runs in Normal GRPs, and
+    /// drawn transparent with 'use-transparency'.
+    #[arg(long, default_value_t = 0)]
+    pub transparent_index: u8,
+
+    /// Number of entries to assume that the row offset
+    /// tables of a Normal GRP have, for GRPs created by
+    /// tools that leave out the last rows of the tables.
+    /// The remaining rows of each frame are assumed to
+    /// follow directly after each other.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub assume_table_rows: Option<u16>,
+
+    /// Only applicable for the 'grp-to-png' and
+    /// 'analyse-grp' modes. Read the GRP as this type,
+    /// instead of detecting the type. Useful when the
+    /// detection is wrong for an unusual GRP.
+    #[arg(long, value_enum)]
+    pub force_grp_type: Option<GrpType>,
+
+    /// Maximum number of frames that a GRP may have.
+    /// GRPs claiming more frames are rejected before
+    /// they are read. Useful for untrusted input.
+    #[arg(long)]
+    pub max_frames: Option<u16>,
+
+    /// Maximum number of pixels (bytes) that any single
+    /// frame of a GRP may have. GRPs with larger frames
+    /// are rejected before they are read. Useful for
+    /// untrusted input.
+    #[arg(long)]
+    pub max_frame_bytes: Option<usize>,
+
     /// Logging level
     #[arg(long, value_enum, default_value_t = LogLevel::Info)]
     pub log_level: LogLevel,
 
     #[arg(long = "generate-shell-completions", value_enum, help = "Generate shell completions")]
     pub generator: Option<Shell>,
+
+    /// Only applicable when using the 'generate-shell-completions'
+    /// argument. Writes the completions to this file
+    /// instead of printing them.
+    #[arg(long)]
+    pub completions_out: Option<String>,
 }
 
-#[derive(Clone, ValueEnum, PartialEq)]
+#[derive(Clone, ValueEnum, PartialEq, Debug)]
 pub enum OperationMode {
     GrpToPng,
     PngToGrp,
     AnalyseGrp,
+    AnalyseSharedData,
+    ListFrames,
+    FixRowOffsets,
+    ReoriginGrp,
+    TranscodeGrp,
+    CrossDiff,
+    GeneratePalette,
 }
 
 #[derive(Clone, ValueEnum, PartialEq, Debug)]
@@ -89,6 +512,34 @@ pub enum CompressionType {
     Auto,
 }
 
+#[derive(Clone, Copy, ValueEnum, PartialEq, Debug)]
+pub enum AnalyseFormat {
+    Text,
+    Svg,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum, PartialEq, Debug)]
+pub enum AtlasFormat {
+    Aseprite,
+}
+
+#[derive(Clone, Copy, ValueEnum, PartialEq, Debug)]
+pub enum ImageFormat {
+    Png,
+    Webp,
+}
+
+impl ImageFormat {
+    /// The file extension used for images of this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png  => "png",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
 #[derive(Clone, ValueEnum, Debug)]
 pub enum LogLevel {
     Trace,
@@ -98,6 +549,45 @@ pub enum LogLevel {
     Error,
 }
 
+/// A range of frame numbers, where both the start and the end are included
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameRange {
+    pub start: u16,
+    pub end:   u16,
+}
+
+impl FrameRange {
+    pub fn contains(&self, frame_number: usize) -> bool {
+        (self.start as usize..=self.end as usize).contains(&frame_number)
+    }
+
+    /// Gives an error if the range ends after the last of the given number of frames
+    pub fn check_in_bounds(&self, frame_count: usize) -> std::io::Result<()> {
+        if self.end as usize >= frame_count {
+            return Err(Error::new(ErrorKind::InvalidInput, format!(
+                "Frame range {}..{} is out of range (0-{})", self.start, self.end, frame_count as isize - 1,
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..")
+            .ok_or_else(|| format!("'{}' is not a range like '17..24'", s))?;
+        let parse = |number: &str| number.trim().parse::<u16>()
+            .map_err(|e| format!("'{}' is not a frame number: {}", number, e));
+        let (start, end) = (parse(start)?, parse(end)?);
+        if end < start {
+            return Err(format!("The range ends at {}, before it starts at {}", end, start));
+        }
+        Ok(FrameRange { start, end })
+    }
+}
+
 impl fmt::Display for LogLevel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -121,12 +611,35 @@ impl From<LogLevel> for LevelFilter {
     }
 }
 
+/// Whether the given output path is a ZIP archive rather than a directory
+pub fn is_zip_path(path: &str) -> bool {
+    std::path::Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
 /// Returns all PNG files in the given directory.
 pub fn list_png_files(dir: &str) -> std::io::Result<Vec<String>> {
+    let entries = list_files_with_extension(dir, "png")?;
+
+    if entries.len() > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Too many PNGs found in directory! Found {} PNGs, but cannot handle more than {}",
+            entries.len(), u16::MAX)))
+    }
+    Ok(entries)
+}
+
+/// Returns all GRP files in the given directory.
+pub fn list_grp_files(dir: &str) -> std::io::Result<Vec<String>> {
+    list_files_with_extension(dir, "grp")
+}
+
+/// Returns all files in the given directory with the given extension (case-insensitive), in
+/// natural order, so that e.g. frame_2.png comes before frame_10.png.
+fn list_files_with_extension(dir: &str, extension: &str) -> std::io::Result<Vec<String>> {
     let mut entries: Vec<_> = fs::read_dir(dir)?
         .filter_map(|entry| {
             let path = entry.ok()?.path();
-            if path.extension()?.to_str()?.eq_ignore_ascii_case("png") {
+            if path.extension()?.to_str()?.eq_ignore_ascii_case(extension) {
                 path.to_str().map(|s| s.to_string())
             } else {
                 None
@@ -134,14 +647,70 @@ pub fn list_png_files(dir: &str) -> std::io::Result<Vec<String>> {
         })
         .collect();
 
-    if entries.len() > u16::MAX as usize {
-        return Err(Error::new(ErrorKind::InvalidInput, format!(
-            "Too many PNGs found in directory! Found {} PNGs, but cannot handle more than {}",
-            entries.len(), u16::MAX)))
-    }
-    entries.sort();
+    entries.sort_by(|a, b| natural_cmp(a, b));
     Ok(entries)
 }
 
+/// Compares the strings with runs of digits compared by their numeric value, and everything
+/// else compared character by character. Strings that only differ in leading zeros are ordered
+/// as plain strings.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let (Some(&a_char), Some(&b_char)) = (a_chars.peek(), b_chars.peek()) else {
+            return a_chars.peek().is_some().cmp(&b_chars.peek().is_some()).then_with(|| a.cmp(b));
+        };
+        let ordering = if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let a_digits = take_digits(&mut a_chars);
+            let b_digits = take_digits(&mut b_chars);
+            let a_number = a_digits.trim_start_matches('0');
+            let b_number = b_digits.trim_start_matches('0');
+            a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number))
+        } else {
+            a_chars.next();
+            b_chars.next();
+            a_char.cmp(&b_char)
+        };
+        if ordering != std::cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect()
+}
+
 const UNCOMPRESSED_FILENAME: &str = "uncompressed";
 const WAR1_FILENAME: &str = "war1";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_png_files_in_natural_order() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join("irongrp_test_natural_order");
+        fs::create_dir_all(&dir)?;
+        for i in (1..=12).rev() {
+            fs::write(dir.join(format!("frame_{}.png", i)), [])?;
+        }
+        fs::write(dir.join("frame_1.txt"), [])?;
+        let files = list_png_files(dir.to_str().unwrap());
+        fs::remove_dir_all(&dir)?;
+
+        let names: Vec<String> = files?.iter()
+            .map(|file| std::path::Path::new(file).file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let expected: Vec<String> = (1..=12).map(|i| format!("frame_{}.png", i)).collect();
+        assert_eq!(names, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn compares_digit_runs_numerically() {
+        let mut names = vec!["b", "a10b", "a2b", "a02b", "a", "a2a", "a1", "ab"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["a", "a1", "a2a", "a02b", "a2b", "a10b", "ab", "b"]);
+    }
+}
@@ -1,44 +1,106 @@
 use clap::{Command, CommandFactory, Parser};
 use clap_complete::{generate, Generator};
-use irongrp::analyse::analyse_grp;
-use irongrp::grp::{grp_to_png, png_to_grp};
-use irongrp::{Args, OperationMode};
+use irongrp::analyse::{analyse_grp, analyse_shared_data, cross_diff, list_frames};
+use irongrp::grp::{check_palette_compatibility, fix_row_offsets, grp_to_png, png_to_grp, reorigin_grp, transcode_grp};
+use irongrp::palette::generate_palette;
+use irongrp::palpng::ColourMetric;
+use irongrp::{is_zip_path, AnalyseFormat, Args, ImageFormat, OperationMode};
 use log::{error, info};
 use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode};
-use std::io::stdout;
+use std::fs::File;
+use std::io::{stdout, Write};
 use std::path::Path;
+use std::process::ExitCode;
 use std::time::{Duration, SystemTime};
 
-fn main() -> std::io::Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
     CombinedLogger::init(
         vec![
             TermLogger::new(args.log_level.clone().into(), Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
         ]
     ).unwrap();
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run(args: Args) -> std::io::Result<()> {
     let start_time = SystemTime::now();
 
+    if args.generator.is_none() && args.completions_out.is_some() {
+        error!("The 'completions-out' argument is only applicable when using the 'generate-shell-completions' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
     if let Some(generator) = args.generator {
         let mut cmd = Args::command();
         info!("Generating completion file for {generator:?}...");
-        print_completions(generator, &mut cmd);
+        match &args.completions_out {
+            Some(path) => {
+                print_completions(generator, &mut cmd, &mut File::create(path)?);
+                info!("Wrote completions to {}", path);
+            },
+            None => print_completions(generator, &mut cmd, &mut stdout()),
+        }
         return Ok(());
     }
 
-    if args.mode.is_none() {
-        error!("Mode of operation must be specified!");
-        std::process::exit(1);
-    }
-    if args.input_path.is_none() {
-        error!("Input path must be specified!");
-        std::process::exit(1);
-    }
-    let input_path = &args.input_path.clone().unwrap();
+    let Some(mode) = args.mode.clone() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Mode of operation must be specified!"));
+    };
+    let Some(input_path) = &args.input_path.clone() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Input path must be specified!"));
+    };
 
     if !args.tiled && args.max_width.is_some() {
         error!("The 'max-width' argument is only applicable when using the 'tiled' argument.");
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
     }
+    if !args.tiled && args.tiled_unique_only {
+        error!("The 'tiled-unique-only' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.max_height.is_some() {
+        error!("The 'max-height' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.tile_gap != 0 {
+        error!("The 'tile-gap' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.label_frames {
+        error!("The 'label-frames' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.columns.is_some() {
+        error!("The 'columns' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.angles.is_some() && args.columns.is_some() {
+        error!("The 'angles' argument cannot be combined with the 'columns' argument, since the angles are the columns.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.angles.is_some() {
+        error!("The 'angles' argument is only applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.angles.is_some() && (args.max_width.is_some() || args.tiled_unique_only) {
+        error!("The 'angles' argument cannot be combined with the 'max-width' or 'tiled-unique-only' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.use_transparency && args.opaque_alpha != u8::MAX {
+        error!("The 'opaque-alpha' argument is only applicable when using the 'use-transparency' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.use_transparency || args.indexed_output) && args.background_index.is_some() {
+        error!("The 'background-index' argument is not applicable when using the 'use-transparency' or 'indexed-output' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
     if args.tiled && args.frame_number.is_some() {
         error!("The 'frame-number' argument is not applicable when using the 'tiled' argument.");
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
@@ -47,26 +109,247 @@ fn main() -> std::io::Result<()> {
         error!("The 'frame-number' argument is not applicable when using the 'png-to-grp' mode.");
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
     }
+    if args.tiled && args.frame_range.is_some() {
+        error!("The 'frame-range' argument is not applicable when using the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::GrpToPng | OperationMode::AnalyseGrp)) && args.frame_range.is_some() {
+        error!("The 'frame-range' argument is only applicable when using the 'grp-to-png' or 'analyse-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.frame_number.is_some() && args.frame_range.is_some() {
+        error!("The 'frame-number' and 'frame-range' arguments cannot be combined.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (!matches!(args.mode, Some(OperationMode::GrpToPng | OperationMode::PngToGrp)) || args.check) && args.metadata.is_some() {
+        error!("The 'metadata' argument is only applicable when using the 'grp-to-png' or 'png-to-grp' modes, without the 'check' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::GrpToPng | OperationMode::PngToGrp)) && args.offsets_in_filenames {
+        error!("The 'offsets-in-filenames' argument is only applicable when using the 'grp-to-png' or 'png-to-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode == Some(OperationMode::PngToGrp) && args.offsets_in_filenames && (args.canvas_from_first || args.metadata.is_some()) {
+        error!("The 'offsets-in-filenames' argument cannot be combined with the 'canvas-from-first' or 'metadata' arguments when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.canvas_from_first && args.metadata.is_some() {
+        error!("The 'canvas-from-first' argument cannot be combined with the 'metadata' argument, which gives the offsets of the frames.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.canvas_from_first {
+        error!("The 'canvas-from-first' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) &&
+        (args.no_trim || args.no_trim_top || args.no_trim_bottom || args.no_trim_left || args.no_trim_right) {
+        error!("The 'no-trim' and 'no-trim-*' arguments are only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.no_trim && args.offsets_in_filenames {
+        error!("The 'no-trim' argument cannot be combined with the 'offsets-in-filenames' argument, which gives the offsets of the frames.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !args.tiled && args.atlas_format.is_some() {
+        error!("The 'atlas-format' argument is only applicable when used together with the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::GrpToPng) && args.report_content_duplicates {
+        error!("The 'report-content-duplicates' argument is only applicable when using the 'grp-to-png' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::GrpToPng) && args.mode != Some(OperationMode::AnalyseGrp) && args.embedded_palette {
+        error!("The 'embedded-palette' argument is only applicable when using the 'grp-to-png' or 'analyse-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.embedded_palette && (args.pal_path.is_some() || args.index_heatmap) {
+        error!("The 'embedded-palette' argument cannot be combined with the 'pal-path' or 'index-heatmap' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.check) && args.dump_palette.is_some() {
+        error!("The 'dump-palette' argument is only applicable when using the 'grp-to-png' mode without the 'check' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.output_format != ImageFormat::Png) && args.indexed_output {
+        error!("The 'indexed-output' argument is only applicable when using the 'grp-to-png' mode with the 'png' output format.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.check) && args.gif.is_some() {
+        error!("The 'gif' argument is only applicable when using the 'grp-to-png' mode without the 'check' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.check) && args.apng.is_some() {
+        error!("The 'apng' argument is only applicable when using the 'grp-to-png' mode without the 'check' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.gif.is_none() && args.apng.is_none() && args.frame_delay != 100 {
+        error!("The 'frame-delay' argument is only applicable when using the 'gif' or 'apng' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.gif.is_none() && args.apng.is_none() && args.frame_durations.is_some() {
+        error!("The 'frame-durations' argument is only applicable when using the 'gif' or 'apng' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.gif.is_none() && args.apng.is_none() && args.loop_count != 0 {
+        error!("The 'loop-count' argument is only applicable when using the 'gif' or 'apng' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.check) && args.with_palette_swatch {
+        error!("The 'with-palette-swatch' argument is only applicable when using the 'grp-to-png' mode without the 'check' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::GrpToPng) && args.check {
+        error!("The 'check' argument is only applicable when using the 'grp-to-png' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.tiled || args.check) && args.continue_on_error {
+        error!("The 'continue-on-error' argument is only applicable when using the 'grp-to-png' mode without the 'tiled' or 'check' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.tiled) && args.export_unique_only {
+        error!("The 'export-unique-only' argument is only applicable when using the 'grp-to-png' mode without the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if (args.mode != Some(OperationMode::GrpToPng) || args.tiled) && args.export_mirrored {
+        error!("The 'export-mirrored' argument is only applicable when using the 'grp-to-png' mode without the 'tiled' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::GrpToPng) && args.index_heatmap {
+        error!("The 'index-heatmap' argument is only applicable when using the 'grp-to-png' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::PngToGrp | OperationMode::TranscodeGrp)) && args.pad_to.is_some() {
+        error!("The 'pad-to' argument is only applicable when using the 'png-to-grp' or 'transcode-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.write_source_manifest {
+        error!("The 'write-source-manifest' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.verify {
+        error!("The 'verify' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.luminance_as_index {
+        error!("The 'luminance-as-index' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.preserve_indices {
+        error!("The 'preserve-indices' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.transparent_index != 0 && !matches!(
+        args.mode,
+        Some(OperationMode::GrpToPng | OperationMode::PngToGrp | OperationMode::AnalyseGrp | OperationMode::FixRowOffsets | OperationMode::TranscodeGrp),
+    ) {
+        error!("The 'transparent-index' argument is only applicable when using the 'grp-to-png', 'png-to-grp', 'analyse-grp', 'fix-row-offsets' or 'transcode-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.inexact_fallback_index.is_some() {
+        error!("The 'inexact-fallback-index' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.dither {
+        error!("The 'dither' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.color_metric != ColourMetric::Rgb {
+        error!("The 'color-metric' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.dither && args.inexact_fallback_index.is_some() {
+        error!("The 'dither' and 'inexact-fallback-index' arguments cannot be combined.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::FixRowOffsets) && args.normalize_transparent_runs {
+        error!("The 'normalize-transparent-runs' argument is only applicable when using the 'fix-row-offsets' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::PngToGrp | OperationMode::TranscodeGrp)) && args.share_data_across_offsets {
+        error!("The 'share-data-across-offsets' argument is only applicable when using the 'png-to-grp' or 'transcode-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::PngToGrp | OperationMode::TranscodeGrp)) && args.omit_trailing_transparent {
+        error!("The 'omit-trailing-transparent' argument is only applicable when using the 'png-to-grp' or 'transcode-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::PngToGrp) && args.auto_uncompress_wide {
+        error!("The 'auto-uncompress-wide' argument is only applicable when using the 'png-to-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::GrpToPng) && args.mode != Some(OperationMode::PngToGrp) && args.resume {
+        error!("The 'resume' argument is only applicable for the 'grp-to-png' and 'png-to-grp' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if !matches!(args.mode, Some(OperationMode::GrpToPng | OperationMode::AnalyseGrp | OperationMode::ListFrames)) && args.force_grp_type.is_some() {
+        error!("The 'force-grp-type' argument is only applicable for the 'grp-to-png', 'analyse-grp' and 'list-frames' modes.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::AnalyseGrp) && args.analyse_format != AnalyseFormat::Text {
+        error!("The 'analyse-format' argument is only applicable for the 'analyse-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.analyse_format == AnalyseFormat::Svg && (args.output_path.is_none() || args.frame_number.is_some() || args.frame_range.is_some()) {
+        error!("The 'analyse-format svg' argument requires the 'output-path' argument, and cannot be combined with the 'frame-number' or 'frame-range' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.analyse_format == AnalyseFormat::Json && (args.frame_number.is_some() || args.frame_range.is_some() || args.delta_report) {
+        error!("The 'analyse-format json' argument cannot be combined with the 'frame-number', 'frame-range' or 'delta-report' arguments.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.mode != Some(OperationMode::CrossDiff) && args.other_path.is_some() {
+        error!("The 'other-path' argument is only applicable when using the 'cross-diff' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
     if args.mode != Some(OperationMode::AnalyseGrp) && args.analyse_row_number.is_some() {
         error!("The 'analyse-row-number' argument is only applicable when using the 'analyse-grp' mode.");
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
     }
+    if args.mode != Some(OperationMode::AnalyseGrp) && args.delta_report {
+        error!("The 'delta-report' argument is only applicable when using the 'analyse-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.print_pixels && (args.mode != Some(OperationMode::AnalyseGrp) || (args.frame_number.is_none() && args.frame_range.is_none())) {
+        error!("The 'print-pixels' argument is only applicable when used together with the 'frame-number' or 'frame-range' arguments in the 'analyse-grp' mode.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
+    if args.analyse_row_number.is_none() && args.dump_row_to.is_some() {
+        error!("The 'dump-row-to' argument is only applicable when used together with the 'analyse-row-number' argument.");
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+    }
     if args.frame_number.is_none() && args.analyse_row_number.is_some() {
         error!("The 'analyse-row-number' argument is only applicable when used together with the 'frame-number' argument.");
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
     }
 
-    match args.mode.clone().unwrap() {
+    match mode {
+        OperationMode::GrpToPng if args.check => {
+            if !Path::new(input_path).is_file() {
+                error!("Invalid input path, please provide a file path to a GRP file.");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            check_palette_compatibility(&args)?;
+            info!("Check complete in {} ms", time_elapsed(start_time));
+        },
+
         OperationMode::GrpToPng => {
             let output_path = &args.output_path
                 .as_ref()
                 .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --output-path argument"))?;
             let p = Path::new(input_path);
-            if !p.exists() || p.is_dir() {
-                error!("Invalid input path, please provide a file path to a GRP file.");
+            if !p.exists() {
+                error!("Invalid input path, please provide a file path to a GRP file, or a directory containing GRP files.");
                 return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
             }
-            std::fs::create_dir_all(output_path)?;
+            if p.is_dir() && (is_zip_path(output_path) || args.gif.is_some() || args.apng.is_some() ||
+                args.metadata.is_some() || args.dump_palette.is_some()) {
+                error!("When the input path is a directory, the output path must be a directory, and the 'gif', 'apng', 'metadata' and 'dump-palette' arguments cannot be used.");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+            if !is_zip_path(output_path) {
+                std::fs::create_dir_all(output_path)?;
+            }
 
             grp_to_png(&args)?;
             info!("Conversion complete in {} ms", time_elapsed(start_time));
@@ -95,8 +378,99 @@ fn main() -> std::io::Result<()> {
             }
 
             analyse_grp(&args)?;
+            if args.analyse_format != AnalyseFormat::Json {
+                info!("Analysis complete in {} ms", time_elapsed(start_time));
+            }
+        },
+
+        OperationMode::AnalyseSharedData => {
+            let p = Path::new(input_path);
+            if !p.is_dir() {
+                error!("Invalid input path, please provide a path to a directory containing GRP files");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            analyse_shared_data(&args)?;
             info!("Analysis complete in {} ms", time_elapsed(start_time));
         },
+
+        OperationMode::ListFrames => {
+            if !Path::new(input_path).is_file() {
+                error!("Invalid input path, please provide a file path to a GRP file");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            list_frames(&args)?;
+            info!("Listing complete in {} ms", time_elapsed(start_time));
+        },
+
+        OperationMode::FixRowOffsets => {
+            let output_path = &args.output_path
+                .as_ref()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --output-path argument"))?;
+            let p = Path::new(input_path);
+            if !p.exists() || p.is_dir() {
+                error!("Invalid input path, please provide a file path to a GRP file");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            fix_row_offsets(&args)?;
+            info!("Wrote GRP with fixed row offsets in {} ms to {}", time_elapsed(start_time), output_path);
+        },
+
+        OperationMode::ReoriginGrp => {
+            let output_path = &args.output_path
+                .as_ref()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --output-path argument"))?;
+            let p = Path::new(input_path);
+            if !p.exists() || p.is_dir() {
+                error!("Invalid input path, please provide a file path to a GRP file");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            reorigin_grp(&args)?;
+            info!("Wrote re-origined GRP in {} ms to {}", time_elapsed(start_time), output_path);
+        },
+
+        OperationMode::TranscodeGrp => {
+            let output_path = &args.output_path
+                .as_ref()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --output-path argument"))?;
+            let p = Path::new(input_path);
+            if !p.exists() || p.is_dir() {
+                error!("Invalid input path, please provide a file path to a GRP file");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            transcode_grp(&args)?;
+            info!("Wrote transcoded GRP in {} ms to {}", time_elapsed(start_time), output_path);
+        },
+
+        OperationMode::CrossDiff => {
+            let other_path = &args.other_path
+                .as_ref()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --other-path argument"))?;
+            if [input_path, other_path].iter().any(|path| !Path::new(path).is_file()) {
+                error!("Invalid input path or other path, please provide file paths to two GRP files");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            cross_diff(&args)?;
+            info!("Comparison complete in {} ms", time_elapsed(start_time));
+        },
+
+        OperationMode::GeneratePalette => {
+            let output_path = &args.output_path
+                .as_ref()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing --output-path argument"))?;
+            if !Path::new(input_path).is_dir() {
+                error!("Invalid input path, please provide a path to a directory containing PNG files");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid arguments"));
+            }
+
+            generate_palette(&args)?;
+            info!("Wrote palette in {} ms to {}", time_elapsed(start_time), output_path);
+        },
     }
     Ok(())
 }
@@ -105,11 +479,37 @@ fn time_elapsed(start_time: SystemTime) -> u128 {
     start_time.elapsed().unwrap_or_else(|_| Duration::new(0, 0)).as_millis()
 }
 
-fn print_completions<G: Generator>(generator: G, cmd: &mut Command) {
+fn print_completions<G: Generator>(generator: G, cmd: &mut Command, out: &mut dyn Write) {
     generate(
         generator,
         cmd,
         cmd.get_name().to_string(),
-        &mut stdout(),
+        out,
     );
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_arguments_not_applicable_to_the_mode_or_other_arguments() {
+        let rejected = [
+            vec!["--mode", "grp-to-png", "--max-width", "64"],
+            vec!["--mode", "grp-to-png", "--tiled-unique-only"],
+            vec!["--mode", "grp-to-png", "--frame-durations", "durations.txt"],
+            vec!["--mode", "png-to-grp", "--frame-number", "1"],
+            vec!["--mode", "grp-to-png", "--frame-number", "1", "--frame-range", "1..2"],
+        ];
+        for arguments in rejected {
+            let args = Args::parse_from(
+                ["irongrp", "--input-path", "test_rejected_arguments.grp", "--output-path", "."].into_iter().chain(arguments.clone()),
+            );
+
+            let result = run(args);
+
+            assert_eq!(result.map_err(|e| e.kind()), Err(std::io::ErrorKind::InvalidInput), "Arguments: {:?}", arguments);
+        }
+    }
+}
@@ -0,0 +1,766 @@
+use crate::grp::GrpFrame;
+use crate::{list_png_files, Args};
+use log::{debug, info, warn};
+use crate::palpng::{greyscale_palette, read_rgb_palette, write_rgb_palette, PaletteTree};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+/// Number of entries in a full palette
+pub const FULL_PALETTE_LEN: usize = 256;
+/// Size of a palette of 256 RGB entries appended to the end of a GRP file
+pub const EMBEDDED_PALETTE_SIZE: usize = FULL_PALETTE_LEN * 3;
+const BMP_CORE_HEADER_SIZE: usize = 12;
+const PCX_MANUFACTURER: u8 = 0x0A;
+const PCX_PALETTE_MARKER: u8 = 0x0C;
+/// Number of palette entries on each row of a palette swatch
+const SWATCH_COLUMNS: usize = 16;
+/// Width and height in pixels of the square drawn for each palette entry in a palette swatch
+const SWATCH_CELL_SIZE: usize = 16;
+
+/// How a single palette entry is used by the frames of a GRP
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaletteEntryUsage {
+    /// The colour of the palette entry
+    pub rgb: [u8; 3],
+    /// Whether any pixel in any frame uses this palette entry
+    pub used: bool,
+    /// Number of pixels, across all frames, that use this palette entry
+    pub count: usize,
+}
+
+/// Reads the palette given in the arguments, or falls back to a greyscale palette if none was given.
+pub fn get_palette(args: &Args) -> Result<Vec<[u8; 3]>> {
+    if args.embedded_palette {
+        read_embedded_palette(args.input_path.as_deref().unwrap())
+    } else if let Some(path) = &args.pal_path {
+        read_palette_file(path, args.require_full_palette)
+    } else {
+        warn!("No palette given - defaulting to greyscale palette");
+        greyscale_palette()
+    }
+}
+
+/// Reads the palette of 256 RGB entries stored in the last bytes of a self-contained GRP file.
+pub fn read_embedded_palette(grp_path: &str) -> Result<Vec<[u8; 3]>> {
+    let mut file = fs::File::open(grp_path)?;
+    if file.metadata()?.len() < EMBEDDED_PALETTE_SIZE as u64 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is too small to end with an embedded palette", grp_path),
+        ));
+    }
+    let mut data = vec![0u8; EMBEDDED_PALETTE_SIZE];
+    file.seek(SeekFrom::End(-(EMBEDDED_PALETTE_SIZE as i64)))?;
+    file.read_exact(&mut data)?;
+    debug!("Read the palette embedded at the end of {}", grp_path);
+    Ok(data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+/// Returns a palette where each index gets a colour from a rainbow, going from red at
+/// low indices through green and blue to magenta at index 255. Index 0 is black. Drawing
+/// frames with this palette reveals which palette indices they use, regardless of colour.
+pub fn index_heatmap_palette() -> Vec<[u8; 3]> {
+    (0..FULL_PALETTE_LEN)
+        .map(|index| if index == 0 {
+            [0, 0, 0]
+        } else {
+            hue_to_rgb(300.0 * (index - 1) as f64 / (FULL_PALETTE_LEN - 2) as f64)
+        })
+        .collect()
+}
+
+/// Converts a hue in degrees (0-360) to a fully saturated and bright RGB colour
+fn hue_to_rgb(hue: f64) -> [u8; 3] {
+    let sector = hue / 60.0;
+    let rising  = (255.0 * sector.fract()).round() as u8;
+    let falling = 255 - rising;
+    match sector as u32 % 6 {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+/// Draws the palette as a swatch of squares, one for each palette entry, going from left to right
+/// and then top to bottom, 16 entries per row. Returns the RGB pixels, and the width and height.
+pub fn palette_swatch(palette: &[[u8; 3]]) -> (Vec<u8>, u32, u32) {
+    let width  = SWATCH_COLUMNS * SWATCH_CELL_SIZE;
+    let height = palette.len().div_ceil(SWATCH_COLUMNS) * SWATCH_CELL_SIZE;
+    let mut pixels = vec![0u8; width * height * 3];
+    for (index, colour) in palette.iter().enumerate() {
+        let left = (index % SWATCH_COLUMNS) * SWATCH_CELL_SIZE;
+        let top  = (index / SWATCH_COLUMNS) * SWATCH_CELL_SIZE;
+        for y in top..top + SWATCH_CELL_SIZE {
+            for x in left..left + SWATCH_CELL_SIZE {
+                pixels[(y * width + x) * 3..][..3].copy_from_slice(colour);
+            }
+        }
+    }
+    (pixels, width as u32, height as u32)
+}
+
+/// Reads a palette file. The format is determined by the file extension: Palettes embedded
+/// in .bmp and .pcx files are extracted from them. Other files are read as Microsoft RIFF
+/// palettes if they start like one, and otherwise as raw RGB palettes. Palettes with fewer
+/// than 256 entries are padded with black, unless require_full_palette is true, in which case
+/// an error is returned.
+pub fn read_palette_file(path: &str, require_full_palette: bool) -> Result<Vec<[u8; 3]>> {
+    let mut palette = read_palette_entries(path)?;
+    debug!("Read {} palette entries from {}", palette.len(), path);
+
+    if palette.len() < FULL_PALETTE_LEN {
+        if require_full_palette {
+            verify_full_palette(path, palette.len())?;
+        }
+        warn!(
+            "The palette {} only has {} entries. Padding it with black up to {} entries",
+            path, palette.len(), FULL_PALETTE_LEN,
+        );
+        palette.resize(FULL_PALETTE_LEN, [0, 0, 0]);
+    }
+    Ok(palette)
+}
+
+/// Reads the entries that are stored in a palette file, without padding the palette up to 256
+/// entries. The format is determined as for read_palette_file.
+pub fn read_palette_entries(path: &str) -> Result<Vec<[u8; 3]>> {
+    if is_raw_rgb_palette(path)? {
+        return read_rgb_palette(path);
+    }
+    let data = fs::read(path)?;
+    if has_extension(path, "bmp") {
+        read_bmp_palette(&data)
+    } else if has_extension(path, "pcx") {
+        read_pcx_palette(&data)
+    } else {
+        read_riff_palette(&data)
+    }
+}
+
+/// Whether the palette at the given path is raw RGB, rather than embedded in an image file
+/// or a RIFF palette
+fn is_raw_rgb_palette(path: &str) -> Result<bool> {
+    if has_extension(path, "bmp") || has_extension(path, "pcx") {
+        return Ok(false);
+    }
+    let mut start = [0u8; 12];
+    let read = fs::File::open(path)?.read(&mut start)?;
+    Ok(!is_riff_palette(&start[..read]))
+}
+
+fn is_riff_palette(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"PAL "
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// Returns an error unless the palette at the given path holds all 256 palette entries.
+fn verify_full_palette(path: &str, entries: usize) -> Result<()> {
+    if entries < FULL_PALETTE_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The palette {} only has {} entries, but a full palette of {} entries is required",
+            path, entries, FULL_PALETTE_LEN,
+        )));
+    }
+    Ok(())
+}
+
+/// Extracts the colour table of a palettized BMP file.
+fn read_bmp_palette(data: &[u8]) -> Result<Vec<[u8; 3]>> {
+    if data.len() < 30 || &data[0..2] != b"BM" {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a BMP file"));
+    }
+    let dib_header_size = u32::from_le_bytes([data[14], data[15], data[16], data[17]]) as usize;
+    let bits_per_pixel  = if dib_header_size == BMP_CORE_HEADER_SIZE {
+        u16::from_le_bytes([data[24], data[25]])
+    } else {
+        u16::from_le_bytes([data[28], data[29]])
+    };
+    if bits_per_pixel > 8 {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The BMP has {} bits per pixel, and thus no palette", bits_per_pixel,
+        )));
+    }
+
+    // The old OS/2 style header has 3 bytes per colour, and can't specify the number of colours.
+    // The later headers have 4 bytes per colour, and the number of colours may be given.
+    let (entry_size, colours_used) = if dib_header_size == BMP_CORE_HEADER_SIZE {
+        (3, 0)
+    } else if data.len() >= 50 {
+        (4, u32::from_le_bytes([data[46], data[47], data[48], data[49]]) as usize)
+    } else {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "The BMP header is truncated"));
+    };
+    let entries = if colours_used == 0 { 1 << bits_per_pixel } else { colours_used };
+
+    let table_start = 14 + dib_header_size;
+    let table_end   = table_start + entries * entry_size;
+    if entries > FULL_PALETTE_LEN || table_end > data.len() {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The BMP colour table of {} entries does not fit in the file", entries,
+        )));
+    }
+    // Colours are stored as BGR
+    Ok(data[table_start..table_end]
+        .chunks(entry_size)
+        .map(|c| [c[2], c[1], c[0]])
+        .collect())
+}
+
+/// Extracts the palette of a Microsoft RIFF palette file. Its 'data' chunk holds a LOGPALETTE:
+/// a version, the number of entries, and the entries as red, green, blue and flags.
+fn read_riff_palette(data: &[u8]) -> Result<Vec<[u8; 3]>> {
+    if !is_riff_palette(data) {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a RIFF palette file"));
+    }
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let chunk_id   = &data[pos..pos + 4];
+        let chunk_size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let chunk = data.get(pos + 8 .. pos + 8 + chunk_size).ok_or_else(|| Error::new(
+            ErrorKind::UnexpectedEof, format!("The RIFF chunk '{}' is truncated", String::from_utf8_lossy(chunk_id)),
+        ))?;
+        if chunk_id != b"data" {
+            pos += 8 + chunk_size + chunk_size % 2; // Chunks are padded to an even size
+            continue;
+        }
+
+        if chunk.len() < 4 {
+            return Err(Error::new(ErrorKind::InvalidData, "The RIFF palette data chunk is truncated"));
+        }
+        let entries = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+        if chunk.len() != 4 + entries * 4 || entries > FULL_PALETTE_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "The RIFF palette claims to have {} entries, but its data chunk has room for {}",
+                entries, (chunk.len() - 4) / 4,
+            )));
+        }
+        return Ok(chunk[4..]
+            .chunks(4)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect());
+    }
+    Err(Error::new(ErrorKind::InvalidData, "The RIFF palette has no data chunk"))
+}
+
+/// Extracts the 256 colour palette at the end of a PCX file.
+fn read_pcx_palette(data: &[u8]) -> Result<Vec<[u8; 3]>> {
+    if data.is_empty() || data[0] != PCX_MANUFACTURER {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a PCX file"));
+    }
+    let palette_len = FULL_PALETTE_LEN * 3;
+    if data.len() < 128 + 1 + palette_len || data[data.len() - palette_len - 1] != PCX_PALETTE_MARKER {
+        return Err(Error::new(ErrorKind::InvalidData, "The PCX file does not end with a 256 colour palette"));
+    }
+    Ok(data[data.len() - palette_len..]
+        .chunks(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect())
+}
+
+/// Counts how many pixels use each palette index, across all the given frames.
+/// Frames that share image data are counted once per frame.
+pub fn palette_histogram(frames: &[GrpFrame]) -> [usize; FULL_PALETTE_LEN] {
+    let mut histogram = [0usize; FULL_PALETTE_LEN];
+    for frame in frames {
+        for &pixel in &frame.image_data.converted_pixels {
+            histogram[pixel as usize] += 1;
+        }
+    }
+    histogram
+}
+
+/// Combines the palette with the usage histogram of the given frames, returning
+/// one entry per palette index. Palettes with fewer than 256 entries will only
+/// get as many entries as the palette has.
+pub fn palette_usage(frames: &[GrpFrame], palette: &[[u8; 3]]) -> Vec<PaletteEntryUsage> {
+    let histogram = palette_histogram(frames);
+    palette.iter()
+        .zip(histogram.iter())
+        .map(|(&rgb, &count)| PaletteEntryUsage {
+            rgb,
+            used: count > 0,
+            count,
+        })
+        .collect()
+}
+
+/// A colour table with only the palette entries that are used by a set of frames, for image
+/// formats where a smaller colour table gives a smaller file, such as GIF. The first entry is
+/// the transparent palette index, which is always included.
+#[derive(Debug, PartialEq)]
+pub struct ReducedPalette {
+    pub colours: Vec<[u8; 3]>,
+    /// The index in colours of each palette index. Unused palette indices get index 0, which is
+    /// the transparent palette index.
+    pub indices: [u8; FULL_PALETTE_LEN],
+}
+
+impl ReducedPalette {
+    /// Maps the palette indices of the given pixels to indices in the reduced colour table
+    pub fn remap(&self, pixels: &[u8]) -> Vec<u8> {
+        pixels.iter().map(|&pixel| self.indices[pixel as usize]).collect()
+    }
+}
+
+/// Creates a colour table from the palette, with only the palette entries that are used by
+/// the given frames, according to their usage histogram. The entry of transparent_index comes
+/// first, whether it is used or not.
+pub fn reduced_palette(frames: &[GrpFrame], palette: &[[u8; 3]], transparent_index: u8) -> ReducedPalette {
+    let histogram = palette_histogram(frames);
+    let mut colours = vec![palette.get(transparent_index as usize).copied().unwrap_or_default()];
+    let mut indices = [0u8; FULL_PALETTE_LEN];
+    for (index, &count) in histogram.iter().enumerate() {
+        if count > 0 && index != transparent_index as usize {
+            indices[index] = colours.len() as u8;
+            colours.push(palette.get(index).copied().unwrap_or_default());
+        }
+    }
+    debug!("Reduced the palette to the {} entries that are used", colours.len());
+    ReducedPalette { colours, indices }
+}
+
+/// Colours that index 0 of generated palettes can have, in order of preference. The corners of
+/// the RGB cube are the colours farthest from most others.
+const TRANSPARENT_KEY_COLOURS: [[u8; 3]; 8] = [
+    [255, 0, 255], [0, 255, 255], [255, 255, 0], [0, 255, 0], [0, 0, 255], [255, 0, 0], [255, 255, 255], [0, 0, 0],
+];
+
+/// Generates a palette for the PNGs in the input directory and writes it to the output path as a
+/// raw RGB palette. Index 0 is reserved for transparency, and the colours of the non-transparent
+/// pixels are reduced to the remaining 255 entries with median cut. Index 0 gets a key colour
+/// that no opaque pixel is nearer to than to its own palette entry, so that no opaque pixel
+/// becomes transparent, and the unused entries repeat the last colour rather than index 0.
+/// Generation is deterministic: the colours are sorted and ties are broken the same way every
+/// time, and nothing is random, so the same PNGs always give a byte-identical palette.
+pub fn generate_palette(args: &Args) -> Result<()> {
+    let png_files = list_png_files(args.input_path.as_deref().unwrap())?;
+    let mut colour_counts: HashMap<[u8; 3], usize> = HashMap::new();
+    for png_file in &png_files {
+        let img = image::open(png_file)
+            .map_err(|e| Error::other(e.to_string()))?
+            .to_rgba8();
+        for pixel in img.pixels().filter(|pixel| pixel[3] != 0) {
+            *colour_counts.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+        }
+    }
+    info!("Found {} unique colours in {} PNGs", colour_counts.len(), png_files.len());
+
+    let colours: Vec<[u8; 3]> = colour_counts.keys().copied().collect();
+    let reduced = median_cut(colour_counts.into_iter().collect(), FULL_PALETTE_LEN - 1);
+    let mut palette = vec![transparent_key_colour(&colours, &reduced)];
+    palette.extend(&reduced);
+    palette.resize(FULL_PALETTE_LEN, reduced.last().copied().unwrap_or_default());
+    write_rgb_palette(args.output_path.as_deref().unwrap(), &palette)
+}
+
+/// Returns the first of the key colours that every one of the given colours is farther from than
+/// from the nearest of the reduced colours, so that pixels of those colours are never matched to
+/// it. If there is no such key colour, the one that the fewest colours are nearer to is used.
+fn transparent_key_colour(colours: &[[u8; 3]], reduced: &[[u8; 3]]) -> [u8; 3] {
+    let squared_distance = |a: [u8; 3], b: [u8; 3]| (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32).sum::<u32>();
+    let palette_tree = PaletteTree::new(reduced);
+    let nearest_distances: Vec<u32> = colours.iter()
+        .map(|&colour| palette_tree.nearest(colour).map_or(u32::MAX, |(_, distance)| distance))
+        .collect();
+    let (matched, key_colour) = TRANSPARENT_KEY_COLOURS.iter()
+        .map(|&key_colour| {
+            let matched = colours.iter()
+                .zip(&nearest_distances)
+                .filter(|&(&colour, &distance)| squared_distance(colour, key_colour) <= distance)
+                .count();
+            (matched, key_colour)
+        })
+        .min_by_key(|&(matched, _)| matched)
+        .unwrap();
+    if matched > 0 {
+        warn!(
+            "{} colours are as near to the transparent colour {:?} as to their palette entries, and may become transparent",
+            matched, key_colour,
+        );
+    }
+    debug!("Using {:?} as the colour of the transparent index 0", key_colour);
+    key_colour
+}
+
+/// Reduces the given colours, each given with the number of pixels having it, to at most
+/// max_colours colours. The colours start out in one box, and the box whose colours span the
+/// widest range in any channel is repeatedly split in two along that channel, at the median
+/// pixel. Each box then becomes the average of its colours, weighted by their pixel counts.
+/// If there are no more colours than max_colours, they are all kept exactly. The result is sorted.
+fn median_cut(mut colours: Vec<([u8; 3], usize)>, max_colours: usize) -> Vec<[u8; 3]> {
+    if colours.is_empty() || max_colours == 0 {
+        return vec![];
+    }
+    colours.sort();
+    let mut boxes = vec![colours];
+
+    while boxes.len() < max_colours {
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, colours)| colours.len() > 1)
+            .map(|(i, colours)| {
+                let (channel, range) = widest_channel(colours);
+                (i, channel, range)
+            })
+            .max_by_key(|&(i, _, range)| (range, Reverse(i)));
+        let Some((i, channel, _)) = widest else {
+            break; // Every box has a single colour
+        };
+
+        let mut lower = boxes.swap_remove(i);
+        lower.sort_by_key(|(colour, _)| colour[channel]);
+        let total: usize = lower.iter().map(|(_, count)| count).sum();
+        let mut pixels = 0;
+        let median = lower.iter()
+            .position(|(_, count)| {
+                pixels += count;
+                pixels * 2 >= total
+            })
+            .unwrap_or(0);
+        let upper = lower.split_off((median + 1).clamp(1, lower.len() - 1));
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let mut palette: Vec<[u8; 3]> = boxes.iter().map(|colours| average_colour(colours)).collect();
+    palette.sort();
+    debug!("Reduced the colours to {} palette entries", palette.len());
+    palette
+}
+
+/// Returns the channel in which the given colours span the widest range, and that range
+fn widest_channel(colours: &[([u8; 3], usize)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colours.iter().map(|(colour, _)| colour[channel]).min().unwrap_or(0);
+            let max = colours.iter().map(|(colour, _)| colour[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(channel, range)| (range, Reverse(channel)))
+        .unwrap()
+}
+
+/// Returns the average of the given colours, weighted by their pixel counts
+fn average_colour(colours: &[([u8; 3], usize)]) -> [u8; 3] {
+    let total: usize = colours.iter().map(|(_, count)| count).sum::<usize>().max(1);
+    let mut average = [0u8; 3];
+    for (channel, value) in average.iter_mut().enumerate() {
+        let sum: usize = colours.iter().map(|(colour, count)| colour[channel] as usize * count).sum();
+        *value = ((sum + total / 2) / total) as u8;
+    }
+    average
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grp::{GrpType, ImageData};
+    use crate::palpng::greyscale_palette;
+
+    fn frame_with_pixels(pixels: Vec<u8>) -> GrpFrame {
+        GrpFrame {
+            x_offset: 0,
+            y_offset: 0,
+            width:    pixels.len() as u8,
+            height:   1,
+            image_data_offset: 0,
+            image_data: ImageData {
+                row_offsets:  vec![],
+                raw_row_data: vec![pixels.clone()],
+                converted_pixels: pixels,
+                grp_type: GrpType::Uncompressed,
+            },
+        }
+    }
+
+    #[test]
+    fn reduces_palette_to_used_entries() {
+        let palette = greyscale_palette().unwrap();
+        let frames = vec![
+            frame_with_pixels(vec![9, 7, 7]),
+            frame_with_pixels(vec![200]),
+        ];
+
+        let reduced = reduced_palette(&frames, &palette, 0);
+        assert_eq!(reduced.colours, vec![[0, 0, 0], [7, 7, 7], [9, 9, 9], [200, 200, 200]]);
+        assert_eq!(reduced.remap(&[0, 9, 7, 200]), vec![0, 2, 1, 3]);
+
+        let reduced = reduced_palette(&frames, &palette, 9);
+        assert_eq!(reduced.colours, vec![[9, 9, 9], [7, 7, 7], [200, 200, 200]]);
+        assert_eq!(reduced.remap(&[0, 9, 7, 200]), vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn flags_used_and_unused_palette_entries() {
+        let palette = greyscale_palette().unwrap();
+        let frames = vec![
+            frame_with_pixels(vec![0, 7, 7, 9]),
+            frame_with_pixels(vec![7, 0]),
+        ];
+
+        let usage = palette_usage(&frames, &palette);
+
+        assert_eq!(usage.len(), 256);
+        assert_eq!(usage[0], PaletteEntryUsage { rgb: [0, 0, 0], used: true, count: 2 });
+        assert_eq!(usage[7], PaletteEntryUsage { rgb: [7, 7, 7], used: true, count: 3 });
+        assert_eq!(usage[9].count, 1);
+        assert!(!usage[8].used);
+        assert_eq!(usage.iter().filter(|entry| entry.used).count(), 3);
+    }
+
+    #[test]
+    fn heatmap_palette_goes_from_red_to_magenta() {
+        let palette = index_heatmap_palette();
+
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0],   [0, 0, 0]);
+        assert_eq!(palette[1],   [255, 0, 0]);
+        assert_eq!(palette[255], [255, 0, 255]);
+        let unique: std::collections::HashSet<_> = palette.iter().collect();
+        assert_eq!(unique.len(), 256, "Every index should get its own colour");
+    }
+
+    #[test]
+    fn rejects_palettes_with_fewer_than_256_entries() -> Result<()> {
+        let short_path = "test_short_palette.pal";
+        let full_path  = "test_full_palette.pal";
+        fs::write(short_path, vec![0u8; 255 * 3])?;
+        fs::write(full_path,  vec![0u8; 256 * 3])?;
+
+        assert!(read_palette_file(short_path, true).is_err());
+        assert!(read_palette_file(full_path,  true).is_ok());
+
+        fs::remove_file(short_path)?;
+        fs::remove_file(full_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn pads_short_raw_palettes_unless_a_full_palette_is_required() -> Result<()> {
+        let path = "test_short_raw_palette.pal";
+        fs::write(path, vec![7u8; 255 * 3])?;
+
+        let padded   = read_palette_file(path, false);
+        let required = read_palette_file(path, true);
+        let entries  = read_palette_entries(path);
+        fs::remove_file(path)?;
+
+        let padded = padded?;
+        assert_eq!(padded.len(), 256);
+        assert_eq!(padded[254], [7, 7, 7]);
+        assert_eq!(padded[255], [0, 0, 0]);
+        assert_eq!(required.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(entries?.len(), 255);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_raw_palettes_with_partial_entries() -> Result<()> {
+        let path = "test_partial_raw_palette.pal";
+        fs::write(path, vec![0u8; 255 * 3 + 1])?;
+
+        let result = read_palette_file(path, false);
+        fs::remove_file(path)?;
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_bmp_colour_table() -> Result<()> {
+        let mut data = vec![0u8; 14 + 40];
+        data[0..2].copy_from_slice(b"BM");
+        data[14] = 40;  // BITMAPINFOHEADER
+        data[28] = 8;   // 8 bits per pixel
+        data[46] = 2;   // 2 colours used
+        data.extend([30, 20, 10, 0, 3, 2, 1, 0]); // BGRA entries
+
+        let palette = read_bmp_palette(&data)?;
+
+        assert_eq!(palette, vec![[10, 20, 30], [1, 2, 3]]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bmp_without_palette() {
+        let mut data = vec![0u8; 14 + 40];
+        data[0..2].copy_from_slice(b"BM");
+        data[14] = 40;
+        data[28] = 24; // True colour BMP
+
+        assert!(read_bmp_palette(&data).is_err());
+    }
+
+    fn riff_palette(declared_entries: u16, colours: &[[u8; 3]]) -> Vec<u8> {
+        let mut chunk = vec![0x00, 0x03];
+        chunk.extend(declared_entries.to_le_bytes());
+        for colour in colours {
+            chunk.extend(colour);
+            chunk.push(0); // Flags
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend((4 + 8 + chunk.len() as u32).to_le_bytes());
+        data.extend(b"PAL data");
+        data.extend((chunk.len() as u32).to_le_bytes());
+        data.extend(chunk);
+        data
+    }
+
+    #[test]
+    fn reads_riff_palette() -> Result<()> {
+        let path = "test_riff_palette.pal";
+        fs::write(path, riff_palette(2, &[[1, 2, 3], [4, 5, 6]]))?;
+
+        assert_eq!(read_palette_entries(path)?, vec![[1, 2, 3], [4, 5, 6]]);
+        let palette = read_palette_file(path, false)?;
+        assert_eq!(palette.len(), FULL_PALETTE_LEN);
+        assert_eq!(palette[1], [4, 5, 6]);
+        assert!(read_palette_file(path, true).is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn draws_one_square_per_palette_entry() {
+        let palette = index_heatmap_palette();
+        let (pixels, width, height) = palette_swatch(&palette);
+        assert_eq!((width, height), (256, 256));
+        assert_eq!(pixels.len(), 256 * 256 * 3);
+
+        let pixel = |x: usize, y: usize| &pixels[(y * 256 + x) * 3..][..3];
+        assert_eq!(pixel(0, 0), &palette[0]);
+        assert_eq!(pixel(15, 15), &palette[0]);
+        assert_eq!(pixel(16, 0), &palette[1]);
+        assert_eq!(pixel(0, 16), &palette[16]);
+        assert_eq!(pixel(255, 255), &palette[255]);
+    }
+
+    #[test]
+    fn generates_identical_palettes_for_the_same_pngs() -> Result<()> {
+        use clap::Parser;
+        let input_dir = "test_generate_identical_palettes_input";
+        let output_paths = ["test_generate_identical_palettes_1.pal", "test_generate_identical_palettes_2.pal"];
+        fs::create_dir_all(input_dir)?;
+        // More colours than fit in the palette, so that they are reduced with median cut
+        let image = image::RgbaImage::from_fn(32, 32, |x, y| image::Rgba([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8, 255]));
+        image.save(format!("{}/frame.png", input_dir)).map_err(Error::other)?;
+
+        let results: Vec<Result<Vec<u8>>> = output_paths.iter()
+            .map(|output_path| {
+                generate_palette(&Args::parse_from([
+                    "irongrp", "--mode", "generate-palette", "--input-path", input_dir, "--output-path", output_path,
+                ]))?;
+                fs::read(output_path)
+            })
+            .collect();
+        fs::remove_dir_all(input_dir)?;
+        for output_path in output_paths {
+            let _ = fs::remove_file(output_path);
+        }
+
+        let palettes = results.into_iter().collect::<Result<Vec<_>>>()?;
+        assert_eq!(palettes[0].len(), FULL_PALETTE_LEN * 3);
+        assert_eq!(palettes[0], palettes[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_opaque_black_out_of_the_transparent_index() -> Result<()> {
+        use crate::palpng::{read_png, ColourMapping, PalettizedImageWithMetadata, TrimEdges};
+        use clap::Parser;
+        let (input_dir, output_path) = ("test_generate_palette_input", "test_generate_palette.pal");
+        fs::create_dir_all(input_dir)?;
+        let png_path = format!("{}/frame.png", input_dir);
+        let pixels = [[0, 0, 0, 255], [255, 0, 255, 255], [0, 0, 0, 0], [40, 80, 120, 255]];
+        let image = image::RgbaImage::from_fn(4, 1, |x, _| image::Rgba(pixels[x as usize]));
+        image.save(&png_path).map_err(Error::other)?;
+
+        let result = generate_palette(&Args::parse_from([
+            "irongrp", "--mode", "generate-palette", "--input-path", input_dir, "--output-path", output_path,
+        ]));
+        let palette = result.and_then(|_| read_rgb_palette(output_path));
+        let image: Option<Result<PalettizedImageWithMetadata<u8, u16>>> = palette.as_ref().ok()
+            .map(|palette| read_png(&png_path, palette, TrimEdges::NONE, ColourMapping::default()));
+        fs::remove_dir_all(input_dir)?;
+        let _ = fs::remove_file(output_path);
+
+        let palette = palette?;
+        // Magenta is used by the pixels, so the next key colour is used for transparency
+        assert_eq!(palette[0], [0, 255, 255]);
+        assert!(!palette[1..].contains(&palette[0]), "Only the transparent index has the key colour");
+        assert_eq!(&palette[1..4], &[[0, 0, 0], [40, 80, 120], [255, 0, 255]]);
+        assert_eq!(palette[255], [255, 0, 255]);
+        assert_eq!(image.unwrap()?.palettized_image, vec![1, 3, 0, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_colours_exactly_when_there_are_few_enough() {
+        let colours = vec![([9, 9, 9], 1), ([1, 2, 3], 5), ([200, 0, 0], 2)];
+        assert_eq!(median_cut(colours, 255), vec![[1, 2, 3], [9, 9, 9], [200, 0, 0]]);
+    }
+
+    #[test]
+    fn splits_colours_at_the_median_of_the_widest_channel() {
+        // Red spans the widest range, and most pixels are dark red
+        let colours = vec![([0, 0, 0], 10), ([10, 0, 0], 10), ([200, 0, 0], 1), ([250, 20, 0], 1)];
+        let palette = median_cut(colours, 2);
+        assert_eq!(palette, vec![[5, 0, 0], [225, 10, 0]]);
+
+        let many: Vec<([u8; 3], usize)> = (0..=255).map(|i| ([i as u8, 255 - i as u8, 0], 1)).collect();
+        assert_eq!(median_cut(many, FULL_PALETTE_LEN - 1).len(), FULL_PALETTE_LEN - 1);
+    }
+
+    #[test]
+    fn reads_palette_embedded_at_end_of_grp() -> Result<()> {
+        let path = "test_embedded_palette.grp";
+        let mut data = vec![1, 0, 4, 0, 4, 0];
+        data.extend((0..EMBEDDED_PALETTE_SIZE).map(|i| (i / 3) as u8));
+        fs::write(path, &data)?;
+
+        let palette = read_embedded_palette(path)?;
+        assert_eq!(palette.len(), FULL_PALETTE_LEN);
+        assert_eq!(palette[0], [0, 0, 0]);
+        assert_eq!(palette[255], [255, 255, 255]);
+
+        fs::write(path, &data[..EMBEDDED_PALETTE_SIZE - 1])?;
+        assert_eq!(read_embedded_palette(path).unwrap_err().kind(), ErrorKind::InvalidData);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_riff_palette_with_wrong_entry_count() {
+        let data = riff_palette(3, &[[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(read_riff_palette(&data).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_pcx_palette_at_end_of_file() -> Result<()> {
+        let mut data = vec![0u8; 128 + 10]; // header and some image data
+        data[0] = PCX_MANUFACTURER;
+        data.push(PCX_PALETTE_MARKER);
+        data.extend((0..256).flat_map(|i| [i as u8, 255 - i as u8, 7]));
+
+        let palette = read_pcx_palette(&data)?;
+
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0],   [0, 255, 7]);
+        assert_eq!(palette[255], [255, 0, 7]);
+
+        let last = data.len() - 769;
+        data[last] = 0;
+        assert!(read_pcx_palette(&data).is_err(), "Missing palette marker should be an error");
+        Ok(())
+    }
+}
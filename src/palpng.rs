@@ -0,0 +1,1288 @@
+//! Conversion between palettized images and PNGs. This started out as the separate
+//! palpngrs crate, and was brought into IronGRP to be developed together with it.
+
+use image::{ColorType, DynamicImage, ImageBuffer};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Read};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// The palette is identified by the hash of its colours, see [`PaletteTree::palette_hash`]
+type CacheKey = (u64, [u8; 3], Option<u8>, ColourMapping);
+const COLOUR_INDEX_CACHE_SHARDS: usize = 32;
+
+/// Palette indices of the colours that have already been mapped. The colours are spread over
+/// shards with a lock each, so that threads mapping different colours rarely wait for each other.
+struct ColourIndexCache {
+    hasher: RandomState,
+    shards: [Mutex<HashMap<CacheKey, u8>>; COLOUR_INDEX_CACHE_SHARDS],
+}
+/// The palette tree of the palette last used, so that it is only built once per run
+static PALETTE_TREE: Mutex<Option<Arc<PaletteTree>>> = Mutex::new(None);
+static COLOUR_INDEX_CACHE: LazyLock<ColourIndexCache> = LazyLock::new(|| ColourIndexCache {
+    hasher: RandomState::new(),
+    shards: std::array::from_fn(|_| Mutex::new(HashMap::new())),
+});
+
+/// Which edges of an image that rows and columns of transparent pixels are trimmed away from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrimEdges {
+    pub top:    bool,
+    pub bottom: bool,
+    pub left:   bool,
+    pub right:  bool,
+}
+
+impl TrimEdges {
+    /// Trim all four edges
+    pub const ALL:  TrimEdges = TrimEdges { top: true,  bottom: true,  left: true,  right: true  };
+    /// Don't trim anything
+    pub const NONE: TrimEdges = TrimEdges { top: false, bottom: false, left: false, right: false };
+}
+
+/// How colours are mapped to palette indices, when there is no exact match in the palette
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ColourMapping {
+    /// Palette index to use for colours without an exact match, instead of the nearest colour
+    pub inexact_fallback_index: Option<u8>,
+    /// Read greyscale images as maps of palette indices, where the luminance of each pixel is its
+    /// palette index, instead of matching their colours against the palette. Pixels with an
+    /// alpha of 0 are transparent.
+    pub luminance_as_index: bool,
+    /// Read indexed PNGs whose palette matches the given palette as maps of palette indices,
+    /// instead of matching their colours against the palette.
+    pub preserve_indices: bool,
+    /// Palette index that transparent pixels get
+    pub transparent_index: u8,
+    /// Diffuse the difference between each colour and its palette colour to the neighbouring
+    /// pixels (Floyd–Steinberg dithering), instead of mapping every pixel on its own
+    pub dither: bool,
+    /// How the distance between colours is measured when finding the nearest palette colour
+    pub metric: ColourMetric,
+}
+
+/// How the distance between two colours is measured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum ColourMetric {
+    /// Squared distance between the red, green and blue values
+    #[default]
+    Rgb,
+    /// Perceptual distance (ΔE, CIE76) between the colours in the CIELAB colour space
+    Lab,
+}
+
+#[derive(Clone, Debug)]
+pub struct PalettizedImageWithMetadata<O, S>
+where
+    O: TryFrom<u32>, // Offset type
+    S: TryFrom<u32>, // Image size type
+{
+    /// x-offset to where the image data starts
+    pub x_offset: O,
+    /// y-offset to where the image data starts
+    pub y_offset: O,
+    /// width  of the image data
+    pub width:    S,
+    /// height of the image data
+    pub height:   S,
+    /// original width  of the image, before any trimming or offsetting was done
+    pub original_width:  S,
+    /// original height of the image, before any trimming or offsetting was done
+    pub original_height: S,
+    /// Palettized image, i.e. every element is an index to an external palette.
+    /// This is thus not an RGB pixel.
+    pub palettized_image: Vec<u8>,
+}
+
+/// Given a palettized image and a palette path, this function
+/// will create a PNG RGB image in the specified output_path.
+pub fn palettized_image_to_png<T>(
+    palettized_image: Vec<u8>,
+    output_path: &str,
+    palette: &[[u8; 3]],
+    use_transparency: bool,
+    width:  T,
+    height: T,
+) -> Result<(), Error>
+where
+    T: Clone + TryFrom<u32> + TryInto<u32>, <T as TryInto<u32>>::Error: Debug,
+{
+    let image: PalettizedImageWithMetadata<u8, T> = PalettizedImageWithMetadata {
+        x_offset: 0,
+        y_offset: 0,
+        width:  width.clone(),
+        height: height.clone(),
+        original_width:  width.clone(),
+        original_height: height.clone(),
+        palettized_image,
+    };
+
+    let rgb_pixels = draw_image_to_pixel_buffer(image, palette, use_transparency, 0, None)?;
+    save_rgb_pixels_to_image_file(
+        rgb_pixels,
+        output_path,
+        use_transparency,
+        width .try_into().unwrap(),
+        height.try_into().unwrap(),
+    )
+}
+
+
+/// Reads a Palette file. Only the entries that are in the file are returned, so palettes with
+/// fewer than 256 entries give fewer entries. Anything after the first 256 entries is ignored.
+pub fn read_rgb_palette(pal_path: &str) -> std::io::Result<Vec<[u8; 3]>> {
+    let file = File::open(pal_path)?;
+    let mut buffer = Vec::with_capacity(768);
+    // RGB PAL files contain at most 256 RGB entries (256 * 3 bytes = 768)
+    file.take(768).read_to_end(&mut buffer)?;
+    if buffer.len() % 3 != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, format!(
+            "The palette {} has {} bytes, which is not a whole number of RGB entries", pal_path, buffer.len(),
+        )));
+    }
+
+    Ok(buffer.chunks(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+/// Writes the palette as a raw RGB palette file, which can be read back with read_rgb_palette
+pub fn write_rgb_palette(pal_path: &str, palette: &[[u8; 3]]) -> std::io::Result<()> {
+    std::fs::write(pal_path, palette.concat())
+}
+
+/// Returns greyscale palette with 256 entries
+pub fn greyscale_palette() -> std::io::Result<Vec<[u8; 3]>> {
+    let mut palette = [[0u8; 3]; 256];
+    for (i, rgb) in palette.iter_mut().enumerate() {
+        rgb[0] = i as u8;
+        rgb[1] = i as u8;
+        rgb[2] = i as u8;
+    }
+    Ok(Vec::from(palette))
+}
+
+
+/// Saves the given RGB pixel buffer to the given output path.
+pub fn save_rgb_pixels_to_image_file(
+    rgb_pixels: Vec<u8>,
+    output_path: &str,
+    use_transparency: bool,
+    width:  u32,
+    height: u32,
+) -> Result<(), Error> {
+    let wrong_size = || Error::new(ErrorKind::InvalidInput, format!(
+        "The pixel buffer does not have the size of an image of {}x{} pixels", width, height,
+    ));
+    let image = if use_transparency {
+        DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, rgb_pixels).ok_or_else(wrong_size)?)
+    } else {
+        DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, rgb_pixels).ok_or_else(wrong_size)?)
+    };
+    image.save(output_path).map_err(|e| Error::other(e.to_string()))
+}
+
+/// Saves palette indices as an 8-bit indexed PNG, with the palette in its PLTE chunk, so that
+/// every pixel keeps its palette index. The tRNS chunk makes the transparent index transparent,
+/// and gives the other indices the alpha of opaque_alpha.
+pub fn save_indexed_pixels_to_png(
+    indices: &[u8],
+    output_path: &str,
+    palette: &[[u8; 3]],
+    opaque_alpha: u8,
+    transparent_index: u8,
+    width:  u32,
+    height: u32,
+) -> Result<(), Error> {
+    if indices.len() != width as usize * height as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "The pixel buffer does not have the size of an image of {}x{} pixels", width, height,
+        )));
+    }
+    let palette = &palette[..palette.len().min(256)];
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(output_path)?), width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.concat());
+    let mut transparency = vec![opaque_alpha; transparent_index as usize + 1];
+    transparency[transparent_index as usize] = 0;
+    if opaque_alpha != u8::MAX {
+        transparency.resize(palette.len().max(transparency.len()), opaque_alpha);
+    }
+    encoder.set_trns(transparency);
+
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(indices).map_err(Error::other)?;
+    writer.finish().map_err(Error::other)
+}
+
+/// Draws a palettized image into an RGB pixel buffer (Vec<u8>).
+/// Uses the given palette for colour lookups. With use_transparency, pixels with the
+/// transparent palette index are drawn fully transparent. Without it, the parts of the
+/// canvas outside of the image are drawn with the colour at background_index, if given,
+/// and black otherwise.
+pub fn draw_image_to_pixel_buffer<O, S>(
+    image: PalettizedImageWithMetadata<O, S>,
+    palette: &[[u8; 3]],
+    use_transparency: bool,
+    transparent_index: u8,
+    background_index: Option<u8>,
+) -> std::io::Result<Vec<u8>>
+where
+    O: TryFrom<u32> + TryInto<u32>, <O as TryInto<u32>>::Error: Debug,
+    S: TryFrom<u32> + TryInto<u32>, <S as TryInto<u32>>::Error: Debug,
+{
+    let height     = image.height  .try_into().unwrap();
+    let width      = image.width   .try_into().unwrap();
+    let x_offset   = image.x_offset.try_into().unwrap();
+    let y_offset   = image.y_offset.try_into().unwrap();
+    let max_width  = image.original_width .try_into().unwrap();
+    let max_height = image.original_height.try_into().unwrap();
+
+    let mut buffer = match background_index {
+        Some(index) if !use_transparency => {
+            let colour = *palette.get(index as usize).ok_or_else(|| Error::new(
+                ErrorKind::InvalidInput,
+                format!("The background index {} is outside of the palette, which has {} colours", index, palette.len()),
+            ))?;
+            colour.repeat((max_width * max_height) as usize)
+        }
+        _ => vec![0u8; (max_width * max_height * if use_transparency { 4 } else { 3 }) as usize],
+    };
+
+    if x_offset + width > max_width || y_offset + height > max_height {
+        warn!(
+            "Image of size {}x{} at offset ({}, {}) does not fit on the canvas of size {}x{}. \
+            Pixels outside the canvas will not be drawn",
+            width, height, x_offset, y_offset, max_width, max_height,
+        );
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let out_x = x + x_offset;
+            let out_y = y + y_offset;
+            if out_x >= max_width || out_y >= max_height {
+                continue;
+            }
+
+            let idx = (y * width + x) as usize;
+            let palette_index = *image.palettized_image.get(idx).ok_or_else(|| Error::new(
+                ErrorKind::InvalidInput,
+                format!("The image of size {}x{} only has {} pixels", width, height, image.palettized_image.len()),
+            ))? as usize;
+            let colour = *palette.get(palette_index).ok_or_else(|| Error::new(
+                ErrorKind::InvalidInput,
+                format!("Palette index {} is outside of the palette, which has {} colours", palette_index, palette.len()),
+            ))?;
+
+            let pixel_index = (out_y * max_width + out_x) as usize;
+
+            if use_transparency {
+                let base = pixel_index * 4;
+                let intensity = if palette_index == transparent_index as usize {
+                    0
+                } else {
+                    255
+                };
+                buffer[base..base + 4].copy_from_slice(&[colour[0], colour[1], colour[2], intensity]);
+            } else {
+                let base = pixel_index * 3;
+                buffer[base..base + 3].copy_from_slice(&[colour[0], colour[1], colour[2]]);
+            }
+        }
+    }
+
+    Ok(buffer)
+}
+
+/// Reads a PNG file and creates an PalettizedImageWithMetadata by doing colour
+/// lookups using the given palette. Any rows or columns where all pixels are
+/// transparent will be trimmed away from the edges given by trim, so that only
+/// the non-transparent parts of the image remains.
+pub fn read_png<O, S>(
+    png_file_name: &str,
+    palette: &[[u8; 3]],
+    trim: TrimEdges,
+    mapping: ColourMapping,
+) -> std::io::Result<PalettizedImageWithMetadata<O, S>>
+where
+    O: TryFrom<u32>,
+    S: TryFrom<u32>,
+{
+    if mapping.preserve_indices {
+        if let Some((pixels, width, height)) = read_png_indices(png_file_name, palette, mapping.transparent_index)? {
+            info!(
+                "Reading indexed image {} with its palette indices. Dimensions: 0x{:0>2X} * 0x{:0>2X} ({} * {})",
+                png_file_name, width, height, width, height,
+            );
+            return palettized_image_from_indices(pixels, width, height, trim, mapping.transparent_index);
+        }
+    }
+    let img = image::open(png_file_name)
+        .map_err(|e| Error::other(e.to_string()))?;
+    // Indexed images with a tRNS chunk are decoded as RGBA, so their transparent
+    // palette entries become transparent pixels, which map to the transparent index.
+    let has_alpha = matches!(
+        img.color(),
+        ColorType::Rgba8 | ColorType::La8 | ColorType::Rgba16 | ColorType::La16,
+    );
+    if mapping.luminance_as_index && matches!(img.color(), ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16) {
+        let img_data = img.to_luma_alpha8();
+        let (width, height) = img_data.dimensions();
+        info!(
+            "Reading greyscale image {} as palette indices. Dimensions: 0x{:0>2X} * 0x{:0>2X} ({} * {})",
+            png_file_name, width, height, width, height,
+        );
+        let pixels = img_data.pixels()
+            .map(|pixel| if pixel[1] == 0 { mapping.transparent_index } else { pixel[0] })
+            .collect();
+        return palettized_image_from_indices(pixels, width, height, trim, mapping.transparent_index);
+    }
+    let img_data = img.to_rgba8();
+
+    let (width, height) = img_data.dimensions();
+    info!(
+        "Reading image {}. Has alpha channel: {}. Dimensions: 0x{:0>2X} * 0x{:0>2X} ({} * {})",
+        png_file_name, has_alpha, width, height, width, height,
+    );
+
+    palettize_rgba_pixels(img_data.as_raw(), width, height, has_alpha, palette, trim, mapping)
+}
+
+/// Reads the palette indices of an 8-bit indexed PNG as they are, if every entry in its PLTE
+/// chunk is the same as the entry with the same index in the given palette. Pixels with an index
+/// that the tRNS chunk makes fully transparent get the transparent index. Returns None if the PNG
+/// is not indexed with 8 bits per pixel, or if its palette differs, so that its colours are mapped
+/// to the palette instead.
+fn read_png_indices(
+    png_file_name: &str,
+    palette: &[[u8; 3]],
+    transparent_index: u8,
+) -> std::io::Result<Option<(Vec<u8>, u32, u32)>> {
+    let mut decoder = png::Decoder::new(File::open(png_file_name)?);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(Error::other)?;
+
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed || info.bit_depth != png::BitDepth::Eight {
+        debug!("{} is not an 8-bit indexed PNG, so its colours will be mapped to the palette", png_file_name);
+        return Ok(None);
+    }
+    let png_palette = info.palette.as_deref().unwrap_or_default();
+    let palettes_match = png_palette
+        .chunks_exact(3)
+        .enumerate()
+        .all(|(index, entry)| palette.get(index).is_some_and(|colour| colour[..] == *entry));
+    if !palettes_match {
+        warn!(
+            "The palette of {} differs from the given palette. Will map its colours to the palette instead of keeping its indices",
+            png_file_name,
+        );
+        return Ok(None);
+    }
+    let transparent_indices: Vec<u8> = info.trns.as_deref().unwrap_or_default()
+        .iter()
+        .enumerate()
+        .filter(|(_, &alpha)| alpha == 0)
+        .map(|(index, _)| index as u8)
+        .collect();
+    let (width, height) = (info.width, info.height);
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels).map_err(Error::other)?;
+    pixels.truncate(frame.buffer_size());
+    for pixel in pixels.iter_mut().filter(|pixel| transparent_indices.contains(pixel)) {
+        *pixel = transparent_index;
+    }
+    Ok(Some((pixels, width, height)))
+}
+
+/// Creates a PalettizedImageWithMetadata from a buffer of RGBA pixels (4 bytes per pixel, row by
+/// row), by doing colour lookups using the given palette. If has_alpha is false, the alpha channel
+/// is ignored. Any rows or columns where all pixels are transparent will be trimmed away from the
+/// edges given by trim, so that only the non-transparent parts of the image remains.
+pub fn palettize_rgba_pixels<O, S>(
+    rgba_pixels: &[u8],
+    width:  u32,
+    height: u32,
+    has_alpha: bool,
+    palette: &[[u8; 3]],
+    trim: TrimEdges,
+    mapping: ColourMapping,
+) -> std::io::Result<PalettizedImageWithMetadata<O, S>>
+where
+    O: TryFrom<u32>,
+    S: TryFrom<u32>,
+{
+    if rgba_pixels.len() != width as usize * height as usize * 4 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Expected {} bytes of RGBA pixels for an image of size {}x{}, but got {}",
+            width as usize * height as usize * 4, width, height, rgba_pixels.len(),
+        )));
+    }
+
+    let palette_tree = PaletteTree::shared(palette);
+    let palette_tree = palette_tree.as_ref();
+    if mapping.dither {
+        let pixels = dither_rgba_pixels(rgba_pixels, width, height, has_alpha, palette, palette_tree, mapping);
+        return palettized_image_from_indices(pixels, width, height, trim, mapping.transparent_index);
+    }
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for pixel in rgba_pixels.chunks_exact(4) {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let alpha = if has_alpha {
+            Some(pixel[3])
+        } else {
+            None
+        };
+        pixels.push(cached_map_colour_to_palette_index(rgb, alpha, palette_tree, mapping));
+    }
+    palettized_image_from_indices(pixels, width, height, trim, mapping.transparent_index)
+}
+
+/// Maps RGBA pixels to palette indices with Floyd–Steinberg dithering: the difference between
+/// the colour of each pixel and the palette colour it is mapped to is spread to the pixels to the
+/// right of and below it, before they are mapped. Transparent pixels neither spread nor receive
+/// any difference.
+fn dither_rgba_pixels(
+    rgba_pixels: &[u8],
+    width:  u32,
+    height: u32,
+    has_alpha: bool,
+    palette: &[[u8; 3]],
+    palette_tree: &PaletteTree,
+    mapping: ColourMapping,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let is_transparent = |x: usize, y: usize| has_alpha && rgba_pixels[(y * width + x) * 4 + 3] == 0;
+    // Differences spread to each pixel, in sixteenths
+    let mut spread = vec![[0i32; 3]; width * height];
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = &rgba_pixels[(y * width + x) * 4..][..4];
+            if is_transparent(x, y) {
+                pixels.push(mapping.transparent_index);
+                continue;
+            }
+            let wanted: [i32; 3] = std::array::from_fn(|c| pixel[c] as i32 + spread[y * width + x][c] / 16);
+            let colour = wanted.map(|channel| channel.clamp(0, 255) as u8);
+            let alpha = if has_alpha { Some(pixel[3]) } else { None };
+            let index = cached_map_colour_to_palette_index(colour, alpha, palette_tree, mapping);
+            pixels.push(index);
+
+            let mapped = palette.get(index as usize).copied().unwrap_or(colour);
+            let difference: [i32; 3] = std::array::from_fn(|c| wanted[c] - mapped[c] as i32);
+            for (dx, dy, sixteenths) in [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)] {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx < 0 || nx as usize >= width || ny >= height || is_transparent(nx as usize, ny) {
+                    continue;
+                }
+                let neighbour = &mut spread[ny * width + nx as usize];
+                for c in 0..3 {
+                    neighbour[c] += difference[c] * sixteenths;
+                }
+            }
+        }
+    }
+    pixels
+}
+
+/// Creates a PalettizedImageWithMetadata from palette indices (one byte per pixel, row by row),
+/// trimming away rows and columns of the transparent index from the edges given by trim.
+fn palettized_image_from_indices<O, S>(
+    pixels: Vec<u8>,
+    width:  u32,
+    height: u32,
+    trim: TrimEdges,
+    transparent_index: u8,
+) -> std::io::Result<PalettizedImageWithMetadata<O, S>>
+where
+    O: TryFrom<u32>,
+    S: TryFrom<u32>,
+{
+    let (pixels, new_width, new_height, trim_left, trim_top) = if trim != TrimEdges::NONE {
+        trim_indices_at_edges(&pixels, width, height, transparent_index, trim)?
+    } else {
+        (pixels, width, height, 0, 0)
+    };
+
+    Ok(PalettizedImageWithMetadata {
+        x_offset: cast::<O>(trim_left,  "x_offset")?,
+        y_offset: cast::<O>(trim_top,   "y_offset")?,
+        width:    cast::<S>(new_width,  "width")?,
+        height:   cast::<S>(new_height, "height")?,
+        original_width:  cast::<S>(width,  "original_width")?,
+        original_height: cast::<S>(height, "original_height")?,
+        palettized_image: pixels,
+    })
+}
+
+fn cached_map_colour_to_palette_index(
+    colour: [u8; 3],
+    alpha: Option<u8>,
+    palette_tree: &PaletteTree,
+    mapping: ColourMapping,
+) -> u8 {
+    let key = (palette_tree.palette_hash, colour, alpha, mapping);
+    let shard = COLOUR_INDEX_CACHE.hasher.hash_one(key) as usize % COLOUR_INDEX_CACHE_SHARDS;
+    // The shard stays locked while mapping an uncached colour, so that it is only locked once
+    *COLOUR_INDEX_CACHE.shards[shard].lock().unwrap()
+        .entry(key)
+        .or_insert_with(|| map_colour_to_palette_index(colour, alpha, palette_tree, mapping))
+}
+
+fn map_colour_to_palette_index(colour: [u8; 3], alpha: Option<u8>, palette_tree: &PaletteTree, mapping: ColourMapping) -> u8 {
+    if alpha == Some(0) {
+        return mapping.transparent_index;
+    }
+    if let Some(alpha) = alpha.filter(|&alpha| alpha != 255) {
+        warn!(
+            "Pixel [{}, {}, {}, {}] is neither fully transparent nor fully opaque. Will drop the alpha channel.",
+            colour[0], colour[1], colour[2], alpha,
+        );
+    }
+    let nearest = match mapping.metric {
+        ColourMetric::Rgb => palette_tree.nearest(colour),
+        ColourMetric::Lab => palette_tree.nearest_lab(colour),
+    };
+    let (best_index, best_distance) = nearest.unwrap_or((0, u32::MAX));
+
+    if let Some(fallback_index) = mapping.inexact_fallback_index.filter(|_| best_distance != 0) {
+        warn!(
+            "Non-exact colour match for pixel [{}, {}, {}] — using fallback palette index {} instead of the nearest, {}",
+            colour[0], colour[1], colour[2], fallback_index, best_index,
+        );
+        return fallback_index;
+    }
+    if best_distance != 0 {
+        warn!(
+            "Non-exact colour match for pixel [{}, {}, {}] — using palette index {} (distance = {})",
+            colour[0], colour[1], colour[2], best_index, best_distance,
+        );
+    }
+
+    best_index
+}
+
+fn squared_distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    let dr = a[0] as i32 - b[0] as i32;
+    let dg = a[1] as i32 - b[1] as i32;
+    let db = a[2] as i32 - b[2] as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+/// Converts an sRGB colour to the CIELAB colour space, with the D65 white point of the
+/// conversion matrix, so that white has no colour
+fn srgb_to_lab(colour: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = colour.map(|channel| {
+        let channel = channel as f32 / 255.0;
+        if channel <= 0.04045 { channel / 12.92 } else { ((channel + 0.055) / 1.055).powf(2.4) }
+    });
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+    let y =  0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.0890;
+    let f = |t: f32| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// A kd-tree of the colours of a palette, for finding the nearest palette colour of a colour
+/// without comparing it with every colour in the palette.
+pub struct PaletteTree {
+    /// The colours of the palette with their palette indices. The middle entry of every range
+    /// of the tree splits the rest of the range in two, along one of the red, green and blue
+    /// axes: the first half has no greater values on that axis, and the second half no smaller.
+    /// The axis is red for the whole tree, and goes to the next axis for each level below.
+    entries: Vec<([u8; 3], u8)>,
+    /// The colours of the palette in palette index order, with their CIELAB values
+    lab_colours: Vec<([u8; 3], [f32; 3])>,
+    /// Hash of the colours of the palette, so that mapped colours can be cached per palette
+    palette_hash: u64,
+}
+
+impl PaletteTree {
+    pub fn new(palette: &[[u8; 3]]) -> Self {
+        let palette = &palette[..palette.len().min(256)];
+        let mut entries: Vec<([u8; 3], u8)> = palette.iter()
+            .enumerate()
+            .map(|(index, &colour)| (colour, index as u8))
+            .collect();
+        Self::build(&mut entries, 0);
+        let lab_colours = palette.iter().map(|&colour| (colour, srgb_to_lab(colour))).collect();
+        let mut hasher = DefaultHasher::new();
+        palette.hash(&mut hasher);
+        PaletteTree { entries, lab_colours, palette_hash: hasher.finish() }
+    }
+
+    /// Returns the tree of the given palette, which is only built again if the palette differs
+    /// from the one last given
+    pub fn shared(palette: &[[u8; 3]]) -> Arc<PaletteTree> {
+        let mut last = PALETTE_TREE.lock().unwrap();
+        match last.as_ref() {
+            Some(tree) if tree.lab_colours.iter().map(|(colour, _)| colour).eq(palette.iter().take(256)) => tree.clone(),
+            _ => last.insert(Arc::new(PaletteTree::new(palette))).clone(),
+        }
+    }
+
+    fn build(entries: &mut [([u8; 3], u8)], axis: usize) {
+        if entries.len() <= 1 {
+            return;
+        }
+        let middle = entries.len() / 2;
+        entries.select_nth_unstable_by_key(middle, |(colour, _)| colour[axis]);
+        let (before, after) = entries.split_at_mut(middle);
+        Self::build(before,         (axis + 1) % 3);
+        Self::build(&mut after[1..], (axis + 1) % 3);
+    }
+
+    /// Returns the palette index of the colour nearest to the given colour, and the squared
+    /// distance between them. If several colours are equally near, the lowest palette index is
+    /// returned. Returns None if the palette is empty.
+    pub fn nearest(&self, colour: [u8; 3]) -> Option<(u8, u32)> {
+        let mut best: Option<(u32, u8)> = None;
+        Self::search(&self.entries, 0, colour, &mut best);
+        best.map(|(distance, index)| (index, distance))
+    }
+
+    /// Like nearest, but finds the palette colour that is nearest in the CIELAB colour space.
+    /// The returned distance is still the squared distance between the red, green and blue
+    /// values, so that it is 0 for exact matches.
+    pub fn nearest_lab(&self, colour: [u8; 3]) -> Option<(u8, u32)> {
+        let lab = srgb_to_lab(colour);
+        let mut best: Option<(f32, usize)> = None;
+        for (index, (_, pal_lab)) in self.lab_colours.iter().enumerate() {
+            let distance = (0..3).map(|c| (lab[c] - pal_lab[c]).powi(2)).sum::<f32>();
+            if best.is_none_or(|(best_distance, _)| distance < best_distance) {
+                best = Some((distance, index));
+            }
+        }
+        best.map(|(_, index)| (index as u8, squared_distance(colour, self.lab_colours[index].0)))
+    }
+
+    fn search(entries: &[([u8; 3], u8)], axis: usize, colour: [u8; 3], best: &mut Option<(u32, u8)>) {
+        if entries.is_empty() {
+            return;
+        }
+        let middle = entries.len() / 2;
+        let (pal_colour, index) = entries[middle];
+        let candidate = (squared_distance(colour, pal_colour), index);
+        if best.is_none_or(|best| candidate < best) {
+            *best = Some(candidate);
+        }
+
+        let axis_distance = colour[axis] as i32 - pal_colour[axis] as i32;
+        let (near, far) = if axis_distance < 0 {
+            (&entries[..middle], &entries[middle + 1..])
+        } else {
+            (&entries[middle + 1..], &entries[..middle])
+        };
+        Self::search(near, (axis + 1) % 3, colour, best);
+        // Colours on the far side are at least the axis distance away. Those exactly as far away
+        // as the best colour are searched too, since they may have a lower palette index.
+        if best.is_none_or(|(distance, _)| (axis_distance * axis_distance) as u32 <= distance) {
+            Self::search(far, (axis + 1) % 3, colour, best);
+        }
+    }
+}
+
+/// Trims away all rows and columns at the edges of a palettized image where every pixel has the
+/// given transparent palette index. The pixels are given row by row, as a flat slice.
+/// Returns the trimmed pixels, their width and height, and the number of columns and rows
+/// that were trimmed from the left and top, i.e. the x- and y-offsets of the trimmed pixels.
+/// Fails with `InvalidInput` if the number of pixels does not match the width and height.
+pub fn trim_indices(
+    pixels: &[u8],
+    width:  u32,
+    height: u32,
+    transparent_index: u8,
+) -> Result<(Vec<u8>, u32, u32, u32, u32), Error> {
+    trim_indices_at_edges(pixels, width, height, transparent_index, TrimEdges::ALL)
+}
+
+/// Like trim_indices, but only trims the given edges of the image.
+pub fn trim_indices_at_edges(
+    pixels: &[u8],
+    width:  u32,
+    height: u32,
+    transparent_index: u8,
+    edges: TrimEdges,
+) -> Result<(Vec<u8>, u32, u32, u32, u32), Error> {
+    if pixels.len() as u64 != width as u64 * height as u64 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Expected {} pixels for an image of {}x{}, but got {}",
+            width as u64 * height as u64, width, height, pixels.len(),
+        )));
+    }
+    let (w, h) = (width as usize, height as usize);
+    let row_is_transparent    = |y: usize| pixels[y * w..(y + 1) * w].iter().all(|&p| p == transparent_index);
+    let column_is_transparent = |x: usize| (0..h).all(|y| pixels[y * w + x] == transparent_index);
+
+    // Determine how many rows/columns to trim from each edge
+    let trim_top    = if edges.top    { (0..h).take_while(|&y| row_is_transparent(y)).count() } else { 0 } as u32;
+    let trim_bottom = if edges.bottom { (0..h).rev().take_while(|&y| row_is_transparent(y)).count() } else { 0 } as u32;
+    let trim_left   = if edges.left   { (0..w).take_while(|&x| column_is_transparent(x)).count() } else { 0 } as u32;
+    let trim_right  = if edges.right  { (0..w).rev().take_while(|&x| column_is_transparent(x)).count() } else { 0 } as u32;
+    debug!(
+        "Trimming 0x{:0>2X} ({}) rows from top, 0x{:0>2X} ({}) from bottom, \
+        0x{:0>2X} ({}) from left, 0x{:0>2X} ({}) from right",
+        trim_top, trim_top, trim_bottom, trim_bottom, trim_left, trim_left, trim_right, trim_right,
+    );
+
+
+    // Clamp dimensions
+    let new_width = if width > trim_left + trim_right {
+        width - trim_left - trim_right
+    } else {
+        error!("Image is too small to trim. Setting width to 0");
+        0
+    };
+    let new_height = if height > trim_top + trim_bottom {
+        height - trim_top - trim_bottom
+    } else {
+        error!("Image is too small to trim. Setting height to 0");
+        0
+    };
+
+    debug!(
+        "width:  0x{:0>2X} ({}),  new_width: 0x{:0>2X} ({}), x_offset: 0x{:0>2X} ({})",
+        width, width, new_width, new_width,
+        (width - new_width) / 2, (width - new_width) / 2,
+    );
+    debug!(
+        "height: 0x{:0>2X} ({}), new_height: 0x{:0>2X} ({}), y_offset: 0x{:0>2X} ({})",
+        height, height, new_height, new_height,
+        (height - new_height) / 2, (height - new_height) / 2,
+    );
+
+    let mut trimmed = Vec::with_capacity((new_width * new_height) as usize);
+    for y in (trim_top..trim_top + new_height).map(|y| y as usize) {
+        let start = y * w + trim_left as usize;
+        trimmed.extend(&pixels[start..start + new_width as usize]);
+    }
+
+    Ok((trimmed, new_width, new_height, trim_left, trim_top))
+}
+
+fn cast<T: TryFrom<u32>>(value: u32, name: &str) -> Result<T, Error> {
+    T::try_from(value).map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} out of range", name)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use proptest::prelude::*;
+    use std::fs;
+
+    fn save_test_png_rgb(path: &str, colour: [u8; 3], width: u32, height: u32) {
+        let mut img = RgbImage::new(width, height);
+        for pixel in img.pixels_mut() {
+            *pixel = Rgb(colour);
+        }
+        let _ = fs::remove_file(path); // Remove if it already exists
+        img.save(path).unwrap();
+    }
+
+    fn save_test_png_rgba(path: &str, colour: [u8; 4], width: u32, height: u32) {
+        let mut img = RgbaImage::new(width, height);
+        for pixel in img.pixels_mut() {
+            *pixel = Rgba(colour);
+        }
+        let _ = fs::remove_file(path); // Remove if it already exists
+        img.save(path).unwrap();
+    }
+
+
+    #[test]
+    fn cached_mapping_matches_uncached_mapping_on_every_thread() {
+        // Two palettes with the same colours in different orders, so that every colour is mapped
+        // to different indices depending on the palette
+        let palette: Vec<[u8; 3]> = (0..=255u8).map(|i| [i, i.wrapping_mul(7), 255 - i]).collect();
+        let reversed: Vec<[u8; 3]> = palette.iter().rev().copied().collect();
+        let palette_trees = [PaletteTree::new(&palette), PaletteTree::new(&reversed)];
+        let fallback = ColourMapping { inexact_fallback_index: Some(3), ..ColourMapping::default() };
+        let lookups: Vec<([u8; 3], Option<u8>, ColourMapping)> = (0..2000u32)
+            .map(|i| [(i * 31 % 256) as u8, (i * 17 % 256) as u8, (i * 101 % 256) as u8])
+            .flat_map(|colour| [None, Some(0), Some(255)].map(|alpha| (colour, alpha)))
+            .flat_map(|(colour, alpha)| [ColourMapping::default(), fallback].map(|mapping| (colour, alpha, mapping)))
+            .collect();
+        let expected: Vec<Vec<u8>> = palette_trees.iter()
+            .map(|palette_tree| lookups.iter()
+                .map(|&(colour, alpha, mapping)| map_colour_to_palette_index(colour, alpha, palette_tree, mapping))
+                .collect())
+            .collect();
+        assert_ne!(expected[0], expected[1]);
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let (palette_trees, lookups, expected) = (&palette_trees, &lookups, &expected);
+                scope.spawn(move || {
+                    // Look every colour up twice with both palettes, in different orders on different
+                    // threads, so that later lookups are answered by the cache
+                    for round in 0..4 {
+                        let tree = (thread + round) % 2;
+                        let actual: Vec<u8> = lookups.iter()
+                            .map(|&(colour, alpha, mapping)| cached_map_colour_to_palette_index(colour, alpha, &palette_trees[tree], mapping))
+                            .collect();
+                        assert_eq!(actual, expected[tree]);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn detects_alpha_correctly() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path_rgb = "test_rgb.png";
+        save_test_png_rgb(path_rgb, [100, 100, 100], 8, 8);
+
+        let result_rgb: PalettizedImageWithMetadata<u8, u16> = read_png(path_rgb, &palette, TrimEdges::ALL, ColourMapping::default())?;
+        for i in 0..result_rgb.palettized_image.len() {
+            assert_eq!(result_rgb.palettized_image[i], 100);
+        }
+        fs::remove_file(path_rgb)?;
+
+
+        let path_rgba = "test_rgba.png";
+        save_test_png_rgba(path_rgba, [100, 100, 100, 255], 8, 8);
+
+        let result_rgba: PalettizedImageWithMetadata<u8, u16> = read_png(path_rgba, &palette, TrimEdges::ALL, ColourMapping::default())?;
+        for i in 0..result_rgba.palettized_image.len() {
+            assert_eq!(result_rgba.palettized_image[i], 100);
+        }
+        fs::remove_file(path_rgba)?;
+        Ok(())
+    }
+
+    #[test]
+    fn drops_alpha_channel_if_not_0() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path_rgba = "test_rgba_alpha.png";
+        save_test_png_rgba(path_rgba, [100, 100, 100, 71], 8, 8);
+
+        let trimmed_image: PalettizedImageWithMetadata<u8, u8> = read_png(path_rgba, &palette, TrimEdges::ALL, ColourMapping::default())?;
+        for i in 0..trimmed_image.palettized_image.len() {
+            assert_eq!(trimmed_image.palettized_image[i], 100);
+        }
+        fs::remove_file(path_rgba)?;
+        Ok(())
+    }
+
+    #[test]
+    fn trims_transparent_rows_and_columns() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_trim.png";
+        let mut img = RgbaImage::new(3, 3);
+
+        // Center is visible, borders are fully transparent
+        for y in 0..3 {
+            for x in 0..3 {
+                let alpha = if x == 1 && y == 1 { 255 } else { 0 };
+                img.put_pixel(x, y, Rgba([100, 100, 100, alpha]));
+            }
+        }
+        img.save(path).unwrap();
+
+        let trimmed_image: PalettizedImageWithMetadata<u8, u8> = read_png(path, &palette, TrimEdges::ALL, ColourMapping::default())?;
+        assert_eq!(trimmed_image.width,    1);
+        assert_eq!(trimmed_image.height,   1);
+        assert_eq!(trimmed_image.x_offset, 1);
+        assert_eq!(trimmed_image.y_offset, 1);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn trims_index_buffer_with_given_transparent_index() -> Result<(), Error> {
+        let pixels = vec![
+            9, 9, 9, 9,
+            9, 1, 2, 9,
+            9, 9, 3, 9,
+            9, 9, 9, 9,
+            9, 9, 9, 9,
+        ];
+
+        let (trimmed, width, height, x_offset, y_offset) = trim_indices(&pixels, 4, 5, 9)?;
+
+        assert_eq!(trimmed, vec![1, 2, 9, 3]);
+        assert_eq!((width, height), (2, 2));
+        assert_eq!((x_offset, y_offset), (1, 1));
+
+        let (untouched, width, height, x_offset, y_offset) = trim_indices(&pixels, 4, 5, 0)?;
+        assert_eq!(untouched, pixels);
+        assert_eq!((width, height, x_offset, y_offset), (4, 5, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn only_trims_given_edges() -> Result<(), Error> {
+        let pixels = vec![
+            0, 0, 0,
+            0, 5, 0,
+            0, 0, 0,
+        ];
+        let edges = TrimEdges { bottom: false, right: false, ..TrimEdges::ALL };
+
+        let (trimmed, width, height, x_offset, y_offset) = trim_indices_at_edges(&pixels, 3, 3, 0, edges)?;
+
+        assert_eq!(trimmed, vec![5, 0, 0, 0]);
+        assert_eq!((width, height), (2, 2));
+        assert_eq!((x_offset, y_offset), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn trims_fully_transparent_index_buffer_to_nothing() -> Result<(), Error> {
+        let (trimmed, width, height, _, _) = trim_indices(&[0; 6], 3, 2, 0)?;
+
+        assert!(trimmed.is_empty());
+        assert_eq!((width, height), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_to_trim_index_buffer_not_matching_its_size() {
+        let result = trim_indices(&[0; 5], 3, 2, 0);
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn fills_canvas_with_background_colour() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let image = |palettized_image| PalettizedImageWithMetadata::<u8, u8> {
+            x_offset: 1,
+            y_offset: 0,
+            width:    1,
+            height:   1,
+            original_width:  3,
+            original_height: 1,
+            palettized_image,
+        };
+
+        let buffer = draw_image_to_pixel_buffer(image(vec![9]), &palette, false, 0, Some(7))?;
+        assert_eq!(buffer, vec![7, 7, 7, 9, 9, 9, 7, 7, 7]);
+
+        // The background is transparent with use_transparency
+        let buffer = draw_image_to_pixel_buffer(image(vec![9]), &palette, true, 0, Some(7))?;
+        assert_eq!(buffer, vec![0, 0, 0, 0, 9, 9, 9, 255, 0, 0, 0, 0]);
+
+        assert!(draw_image_to_pixel_buffer(image(vec![9]), &palette[..5], false, 0, Some(7)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn clamps_drawing_to_canvas() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let image: PalettizedImageWithMetadata<u8, u8> = PalettizedImageWithMetadata {
+            x_offset: 2,
+            y_offset: 1,
+            width:    3,
+            height:   3,
+            original_width:  4,
+            original_height: 3,
+            palettized_image: vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+        };
+
+        let buffer = draw_image_to_pixel_buffer(image, &palette, false, 0, None)?;
+
+        let red_channel: Vec<u8> = buffer.chunks(3).map(|pixel| pixel[0]).collect();
+        assert_eq!(red_channel, vec![
+            0, 0, 0, 0,
+            0, 0, 1, 2,
+            0, 0, 4, 5,
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn dithers_gradients_between_palette_colours() -> Result<(), Error> {
+        let palette = vec![[0, 0, 0], [255, 255, 255], [255, 0, 255]];
+        // The cache of mapped colours is shared by all tests, so these mappings must not be used with other palettes
+        let plain  = ColourMapping { transparent_index: 2, ..ColourMapping::default() };
+        let dither = ColourMapping { dither: true, ..plain };
+        let grey: Vec<u8> = [128, 128, 128, 255].repeat(8 * 8);
+
+        let banded: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(&grey, 8, 8, true, &palette, TrimEdges::NONE, plain)?;
+        assert!(banded.palettized_image.iter().all(|&index| index == 1));
+
+        let dithered: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(&grey, 8, 8, true, &palette, TrimEdges::NONE, dither)?;
+        let white = dithered.palettized_image.iter().filter(|&&index| index == 1).count();
+        let black = dithered.palettized_image.iter().filter(|&&index| index == 0).count();
+        assert_eq!(white + black, 64);
+        assert!((28..=36).contains(&white), "{} of 64 pixels are white", white);
+
+        // Exact colours stay as they are
+        let exact: Vec<u8> = (0..64).flat_map(|i| if i % 3 == 0 { [255, 255, 255, 255] } else { [0, 0, 0, 255] }).collect();
+        let result: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(&exact, 8, 8, true, &palette, TrimEdges::NONE, dither)?;
+        assert_eq!(result.palettized_image, (0..64).map(|i| if i % 3 == 0 { 1 } else { 0 }).collect::<Vec<u8>>());
+        Ok(())
+    }
+
+    #[test]
+    fn dithering_skips_transparent_pixels() -> Result<(), Error> {
+        let palette = vec![[0, 0, 0], [255, 255, 255], [255, 0, 255]];
+        let dither = ColourMapping { dither: true, transparent_index: 2, ..ColourMapping::default() };
+        // The light grey pixel would become black if it got the difference between the
+        // transparent pixel and the magenta of the transparent index
+        let pixels = [0, 0, 0, 255, 120, 120, 120, 0, 130, 130, 130, 255];
+        let result: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(&pixels, 3, 1, true, &palette, TrimEdges::NONE, dither)?;
+        assert_eq!(result.palettized_image, vec![0, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn converts_colours_to_lab() {
+        let close = |a: [f32; 3], b: [f32; 3]| (0..3).all(|c| (a[c] - b[c]).abs() < 0.01);
+        assert!(close(srgb_to_lab([0, 0, 0]), [0.0, 0.0, 0.0]));
+        assert!(close(srgb_to_lab([255, 255, 255]), [100.0, 0.0, 0.0]));
+        assert!(close(srgb_to_lab([255, 0, 0]), [53.23, 80.11, 67.22]));
+    }
+
+    #[test]
+    fn maps_saturated_colours_to_the_perceptually_nearest_colour_with_lab() {
+        let palette = [[255, 90, 90], [200, 0, 80], [255, 90, 90]];
+        let palette_tree = PaletteTree::new(&palette);
+        let lab = ColourMapping { metric: ColourMetric::Lab, ..ColourMapping::default() };
+
+        assert_eq!(map_colour_to_palette_index([255, 0, 0], None, &palette_tree, ColourMapping::default()), 1);
+        assert_eq!(map_colour_to_palette_index([255, 0, 0], None, &palette_tree, lab), 0);
+        assert_eq!(palette_tree.nearest_lab([255, 0, 0]), Some((0, 90 * 90 * 2)));
+        assert_eq!(palette_tree.nearest_lab([200, 0, 80]), Some((1, 0)));
+        assert_eq!(PaletteTree::new(&[]).nearest_lab([1, 2, 3]), None);
+    }
+
+    #[test]
+    fn maps_non_exact_colours() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_colour.png";
+        save_test_png_rgb(path, [100, 100, 101], 1, 1);
+
+        let result: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, ColourMapping::default())?;
+
+        assert_eq!(result.palettized_image[0], 100); // Closest match
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reads_greyscale_images_as_palette_indices() -> Result<(), Error> {
+        let path = "test_luminance_as_index.png";
+        let img = ImageBuffer::from_fn(3, 1, |x, _| image::LumaA([[0u8, 17, 200][x as usize], [255u8, 255, 0][x as usize]]));
+        img.save(path).unwrap();
+        // A palette where the nearest colours would give other indices than the luminance
+        let palette = vec![[0, 0, 0], [255, 0, 0], [17, 17, 17]];
+        let mapping = ColourMapping { luminance_as_index: true, ..ColourMapping::default() };
+
+        let result: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, mapping)?;
+        assert_eq!(result.palettized_image, vec![0, 17, 0]); // The last pixel is transparent
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn honours_transparency_of_indexed_images() -> Result<(), Error> {
+        let path = "test_indexed_trns.png";
+        let mut encoder = png::Encoder::new(fs::File::create(path)?, 4, 1);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![10, 10, 10, 20, 20, 20, 30, 30, 30]);
+        encoder.set_trns(vec![255, 0]); // Index 1 is transparent, and index 2 is opaque since it is not listed
+        let mut writer = encoder.write_header().map_err(Error::other)?;
+        writer.write_image_data(&[0, 1, 2, 1]).map_err(Error::other)?;
+        writer.finish().map_err(Error::other)?;
+
+        let palette = greyscale_palette()?;
+        let result: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, ColourMapping::default())?;
+        assert_eq!(result.palettized_image, vec![10, 0, 30, 0]);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn preserves_indices_of_indexed_images_with_matching_palette() -> Result<(), Error> {
+        let write_png = |path: &str, png_palette: Vec<u8>| -> Result<(), Error> {
+            let mut encoder = png::Encoder::new(fs::File::create(path)?, 4, 1);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(png_palette);
+            encoder.set_trns(vec![255, 255, 255, 0]); // Index 3 is transparent
+            let mut writer = encoder.write_header().map_err(Error::other)?;
+            writer.write_image_data(&[1, 2, 3, 2]).map_err(Error::other)?;
+            writer.finish().map_err(Error::other)
+        };
+        // Index 2 has the same colour as index 1, so looking up its colour would give index 1
+        let palette = vec![[0, 0, 0], [50, 50, 50], [50, 50, 50], [90, 90, 90]];
+        let mapping = ColourMapping { preserve_indices: true, ..ColourMapping::default() };
+
+        let path = "test_preserve_indices.png";
+        write_png(path, palette.concat())?;
+        let preserved: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, mapping)?;
+        let remapped:  PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, ColourMapping::default())?;
+        assert_eq!(preserved.palettized_image, vec![1, 2, 0, 2]);
+        assert_eq!(remapped.palettized_image,  vec![1, 1, 0, 1]);
+
+        // With a different palette, the colours are mapped to the given palette
+        write_png(path, vec![0, 0, 0, 90, 90, 90, 50, 50, 50, 90, 90, 90])?;
+        let differing: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, mapping)?;
+        assert_eq!(differing.palettized_image, vec![3, 1, 0, 1]);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn saves_indexed_pngs_that_keep_their_indices() -> Result<(), Error> {
+        let path = "test_save_indexed.png";
+        // Index 2 has the same colour as index 1, which an RGB image could not tell apart
+        let palette = vec![[0, 0, 0], [50, 50, 50], [50, 50, 50], [90, 90, 90]];
+        save_indexed_pixels_to_png(&[0, 1, 2, 3], path, &palette, u8::MAX, 0, 2, 2)?;
+
+        let mapping = ColourMapping { preserve_indices: true, ..ColourMapping::default() };
+        let result: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, mapping)?;
+        assert_eq!(result.palettized_image, vec![0, 1, 2, 3]);
+
+        let decoder = png::Decoder::new(fs::File::open(path)?);
+        let reader = decoder.read_info().map_err(Error::other)?;
+        assert_eq!(reader.info().trns.as_deref(), Some(&[0u8][..]));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn written_palettes_read_back_identically() -> Result<(), Error> {
+        let path = "test_write_rgb_palette.pal";
+        let palette: Vec<[u8; 3]> = (0..=255u8).map(|i| [i, 255 - i, i / 2]).collect();
+        write_rgb_palette(path, &palette)?;
+        assert_eq!(fs::metadata(path)?.len(), 768);
+        assert_eq!(read_rgb_palette(path)?, palette);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn maps_non_exact_colours_to_fallback_index() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let mapping = ColourMapping { inexact_fallback_index: Some(254), ..ColourMapping::default() };
+        let pixels = [100, 100, 101, 255, 100, 100, 100, 255];
+
+        let result: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(&pixels, 2, 1, true, &palette, TrimEdges::NONE, mapping)?;
+
+        assert_eq!(result.palettized_image, vec![254, 100]); // Only the exact match is kept
+        Ok(())
+    }
+
+    #[test]
+    fn whole_image_is_transparent_and_trimmed_away() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_transparency.png";
+        save_test_png_rgba(path, [0, 0, 0, 0], 1, 1); // Fully transparent
+
+        let trimmed_image: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::ALL, ColourMapping::default())?;
+
+        assert_eq!(trimmed_image.palettized_image.len(), 0);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn whole_image_is_transparent_but_not_trimmed_away() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_transparency_without_trimming.png";
+        save_test_png_rgba(path, [0, 0, 0, 0], 1, 1); // Fully transparent
+
+        let trimmed_image: PalettizedImageWithMetadata<u8, u16> = read_png(path, &palette, TrimEdges::NONE, ColourMapping::default())?;
+
+        assert_eq!(trimmed_image.palettized_image.len(), 1);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn image_exactly_255x255() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_image_exactly_255x255.png";
+        let mut img = RgbaImage::new(255, 255);
+        for pixel in img.pixels_mut() {
+            *pixel = Rgba([100, 100, 100, 255]);
+        }
+        img.save(path).unwrap();
+
+        let result: PalettizedImageWithMetadata<u8, u8> = read_png(path, &palette, TrimEdges::ALL, ColourMapping::default())?;
+        assert_eq!(result.width  + result.x_offset, 255);
+        assert_eq!(result.height + result.y_offset, 255);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn image_just_above_255x255() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_image_just_above_255x255.png";
+        let mut img = RgbaImage::new(256, 256);
+        for pixel in img.pixels_mut() {
+            *pixel = Rgba([100, 100, 100, 255]);
+        }
+        img.save(path).unwrap();
+
+        let result: Result<PalettizedImageWithMetadata<u8, u8>, Error> = read_png(path, &palette, TrimEdges::NONE, ColourMapping::default());
+        assert!(result.is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn image_too_many_transparent_pixes() -> Result<(), Error> {
+        let palette = greyscale_palette()?;
+        let path = "test_image_too_many_transparent_pixels.png";
+        let mut img = RgbaImage::new(300, 300);
+
+        // 260 pixels transparent on the top and left
+        for y in 0..3 {
+            for x in 0..3 {
+                let alpha = if x > 260 && y > 260 { 255 } else { 0 };
+                img.put_pixel(x, y, Rgba([100, 100, 100, alpha]));
+            }
+        }
+        img.save(path).unwrap();
+
+        let result: Result<PalettizedImageWithMetadata<u8, u16>, Error> = read_png(path, &palette, TrimEdges::ALL, ColourMapping::default());
+        assert!(result.is_err());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+
+    /// The nearest palette colour, found by comparing the colour with every colour in the palette
+    fn nearest_by_exhaustive_search(colour: [u8; 3], palette: &[[u8; 3]]) -> Option<(u8, u32)> {
+        palette.iter()
+            .enumerate()
+            .map(|(index, &pal_colour)| (squared_distance(colour, pal_colour), index as u8))
+            .min()
+            .map(|(distance, index)| (index, distance))
+    }
+
+    // Property-based test: the kd-tree finds the same palette index as comparing the colour with
+    // every colour in the palette, including picking the lowest index among equally near colours.
+    // The colour components are drawn from a small range, so that palettes with duplicated colours
+    // are common.
+    proptest! {
+        #[test]
+        fn prop_palette_tree_finds_the_nearest_colour_with_the_lowest_index(
+            palette in proptest::collection::vec(proptest::array::uniform3(0u8..8), 0..=256),
+            colours in proptest::collection::vec(proptest::array::uniform3(0u8..16), 1..32),
+        ) {
+            let palette: Vec<[u8; 3]> = palette.iter().map(|colour| colour.map(|c| c * 32)).collect();
+            let tree = PaletteTree::new(&palette);
+            // Half of the colours are halfway between palette colours, so that they are equally near both
+            for colour in colours.iter().map(|colour| colour.map(|c| c * 16)) {
+                prop_assert_eq!(tree.nearest(colour), nearest_by_exhaustive_search(colour, &palette));
+            }
+        }
+    }
+}
@@ -1,60 +1,406 @@
-use crate::grp::{GrpFrame, GrpType, EXTENDED_IMAGE_WIDTH};
-use crate::{Args, UNCOMPRESSED_FILENAME, WAR1_FILENAME};
-use log::{debug, info};
-use palpngrs::{draw_image_to_pixel_buffer, read_png, save_rgb_pixels_to_image_file, PalettizedImageWithMetadata};
+use crate::grp::{GrpFrame, GrpType};
+use crate::palette::reduced_palette;
+use crate::{AtlasFormat, Args, UNCOMPRESSED_FILENAME, WAR1_FILENAME};
+use log::{debug, error, info, warn};
+use crate::palpng::{draw_image_to_pixel_buffer, ColourMapping, palettize_rgba_pixels, read_png, save_indexed_pixels_to_png, save_rgb_pixels_to_image_file, PalettizedImageWithMetadata, TrimEdges};
+use image::{RgbImage, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
+/// Describes the layout of a tiled image, when only unique frames were drawn
+/// or when the frames were laid out by angle
+#[derive(Serialize)]
+struct TileMap {
+    columns:     u32,
+    tile_width:  u32,
+    tile_height: u32,
+    /// Pixels between adjacent tiles
+    tile_gap:    u32,
+    tiles: Vec<TileEntry>,
+}
+
+#[derive(Serialize)]
+struct TileEntry {
+    /// Index of the tile in the tiled image, counted row by row
+    tile: usize,
+    /// All frame numbers whose image content is drawn in this tile
+    frames: Vec<usize>,
+    /// Animation frame of the tile, if the number of angles was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    animation: Option<u32>,
+    /// Angle of the tile, if the number of angles was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    angle: Option<u32>,
+}
+
+/// A rendered frame that is to be saved to its own file
+struct FrameToSave {
+    index: usize,
+    file_name: String,
+    buffer: Vec<u8>,
+    /// Index in the unique images that the file is, when exporting unique frames only
+    unique_image: Option<usize>,
+}
+
+/// Describes which frames each image represents, when only unique frames were exported
+#[derive(Serialize)]
+struct UniqueFrameMap {
+    images: Vec<UniqueFrameEntry>,
+}
+
+#[derive(Serialize)]
+struct UniqueFrameEntry {
+    /// File name of the image, named after the lowest frame number it represents
+    file: String,
+    /// All frame numbers whose image content is in this file
+    frames: Vec<usize>,
+}
+
+/// GRPs have no timing information, so all frames get this duration in animation atlases
+const ATLAS_FRAME_DURATION_MS: u32 = 100;
+
+/// Number of frames that are rendered and saved in parallel before the next ones are rendered
+const RENDER_CHUNK_SIZE: usize = 64;
+
+/// A sprite sheet description in the JSON format of Aseprite, using the array layout
+#[derive(Serialize)]
+struct AsepriteAtlas {
+    frames: Vec<AsepriteFrame>,
+    meta: AsepriteMeta,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteFrame {
+    filename: String,
+    /// Where the pixels of the frame are in the sheet
+    frame: AtlasRect,
+    rotated: bool,
+    trimmed: bool,
+    /// Where the pixels of the frame are on the canvas of the frame
+    sprite_source_size: AtlasRect,
+    /// The size of the canvas of the frame
+    source_size: AtlasSize,
+    duration: u32,
+}
+
+#[derive(Serialize)]
+struct AsepriteMeta {
+    app: String,
+    version: String,
+    image: String,
+    format: String,
+    size: AtlasSize,
+    scale: String,
+}
+
+#[derive(Serialize)]
+struct AtlasRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct AtlasSize {
+    w: u32,
+    h: u32,
+}
+
+/// Lists the file names of the PNGs that a GRP was created from, one per frame. It is written
+/// next to the GRP, so that the frames can get their original names back when converted to PNGs.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SourceManifest {
+    pub source_files: Vec<String>,
+}
+
+/// The path of the source manifest belonging to the GRP at the given path
+pub fn source_manifest_path(grp_path: &str) -> String {
+    format!("{}.manifest.json", grp_path)
+}
+
+/// Writes a source manifest next to the GRP at the given path, listing the file names
+/// (without directories) of the given PNG files.
+pub fn write_source_manifest(grp_path: &str, png_files: &[String]) -> std::io::Result<()> {
+    let manifest = SourceManifest {
+        source_files: png_files.iter()
+            .map(|png_file| Path::new(png_file).file_name().map_or(png_file.clone(), |n| n.to_string_lossy().into_owned()))
+            .collect(),
+    };
+    let path = source_manifest_path(grp_path);
+    write_json_file(&path, &manifest)?;
+    info!("Saved the names of the source PNGs to {}", path);
+    Ok(())
+}
+
+/// Reads the source manifest next to the GRP at the given path, if there is one.
+pub fn read_source_manifest(grp_path: &str) -> std::io::Result<Option<SourceManifest>> {
+    let path = source_manifest_path(grp_path);
+    if !Path::new(&path).is_file() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(&path)?;
+    let manifest = serde_json::from_str(&json).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    debug!("Read source manifest {}", path);
+    Ok(Some(manifest))
+}
+
+/// Adds the offsets to the end of the given file name, like `frame_003_x12_y8.png`, replacing
+/// any offsets that it already ends with.
+fn file_name_with_offsets(file_name: &str, x_offset: u8, y_offset: u8) -> String {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = match offsets_from_file_name(file_name) {
+        Some(_) => stem.rsplit_once("_x").map_or(stem.as_ref(), |(stem, _)| stem),
+        None => stem.as_ref(),
+    };
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    format!("{}_x{}_y{}{}", stem, x_offset, y_offset, extension)
+}
+
+/// Reads the offsets at the end of a file name like `frame_003_x12_y8.png`, if it has any
+pub fn offsets_from_file_name(file_name: &str) -> Option<(u8, u8)> {
+    let stem = Path::new(file_name).file_stem()?.to_str()?;
+    let (rest, y_offset) = stem.rsplit_once("_y")?;
+    let (_, x_offset) = rest.rsplit_once("_x")?;
+    let parse = |number: &str| number.bytes().all(|b| b.is_ascii_digit()).then(|| number.parse().ok()).flatten();
+    Some((parse(x_offset)?, parse(y_offset)?))
+}
+
+/// Describes where a frame of a GRP is on the canvas, so that a GRP can be recreated from PNGs
+/// with exactly the same frames. A list of these is written as the frame metadata file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrameRecord {
+    pub index:    usize,
+    pub x_offset: u8,
+    pub y_offset: u8,
+    pub width:    u16,
+    pub height:   u8,
+    pub image_data_offset: u32,
+    /// The first frame whose image data this frame shares, if any
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reuses_frame: Option<usize>,
+}
+
+/// Writes the offsets, dimensions and shared image data of the frames to the given path
+pub fn write_frame_metadata(path: &str, frames: &[GrpFrame]) -> std::io::Result<()> {
+    let mut first_frame_with_offset: HashMap<u32, usize> = HashMap::new();
+    let records: Vec<FrameRecord> = frames.iter().enumerate()
+        .map(|(index, frame)| {
+            let first = *first_frame_with_offset.entry(frame.image_data_offset).or_insert(index);
+            FrameRecord {
+                index,
+                x_offset: frame.x_offset,
+                y_offset: frame.y_offset,
+                width:    frame.actual_width(),
+                height:   frame.height,
+                image_data_offset: frame.image_data_offset,
+                reuses_frame: Some(first).filter(|&first| first != index),
+            }
+        })
+        .collect();
+    write_json_file(path, &records)?;
+    info!("Saved the metadata of {} frames to {}", records.len(), path);
+    Ok(())
+}
+
+/// Reads the frame metadata file at the given path, and checks that it describes the frames
+/// 0 to frame_count - 1, in order.
+pub fn read_frame_metadata(path: &str, frame_count: usize) -> std::io::Result<Vec<FrameRecord>> {
+    let json = std::fs::read_to_string(path)?;
+    let records: Vec<FrameRecord> = serde_json::from_str(&json).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    if records.len() != frame_count {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, format!(
+            "{} describes {} frames, but there are {} PNGs", path, records.len(), frame_count,
+        )));
+    }
+    if let Some((position, record)) = records.iter().enumerate().find(|(position, record)| record.index != *position) {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, format!(
+            "Entry {} of {} describes frame {}, but the frames must be described in order", position, path, record.index,
+        )));
+    }
+    debug!("Read the metadata of {} frames from {}", records.len(), path);
+    Ok(records)
+}
+
+/// Lists the conversions that have been completed, so that they can be skipped when
+/// resuming an interrupted conversion. It is written to the output directory.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ResumeManifest {
+    pub completed: Vec<CompletedConversion>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CompletedConversion {
+    pub input_path:  String,
+    pub output_path: String,
+    /// CRC32 of the names and contents of all input files, and of the options of the conversion
+    pub input_hash:  String,
+}
+
+/// The path of the resume manifest in the given output directory
+pub fn resume_manifest_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("irongrp-resume.json")
+}
+
+impl ResumeManifest {
+    /// Reads the resume manifest at the given path, or gives an empty one if there is none.
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let json = std::fs::read_to_string(path)?;
+        let manifest = serde_json::from_str(&json).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        debug!("Read resume manifest {}", path.display());
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        write_json_file(&path.to_string_lossy(), self)
+    }
+
+    /// True if the given input was converted to the given output, the output still exists,
+    /// and the input has not changed since.
+    pub fn is_completed(&self, input_path: &str, output_path: &str, input_hash: &str) -> bool {
+        Path::new(output_path).exists() && self.completed.iter().any(|c|
+            c.input_path == input_path && c.output_path == output_path && c.input_hash == input_hash
+        )
+    }
+
+    /// Records the given conversion as completed, replacing any earlier record of the input.
+    pub fn mark_completed(&mut self, input_path: &str, output_path: &str, input_hash: &str) {
+        self.completed.retain(|c| c.input_path != input_path);
+        self.completed.push(CompletedConversion {
+            input_path:  input_path .to_string(),
+            output_path: output_path.to_string(),
+            input_hash:  input_hash .to_string(),
+        });
+    }
+}
+
+/// Calculates a CRC32 of the given options, and of the names and contents of the given files, in
+/// the given order.
+pub fn hash_input_files(files: &[String], options: &str) -> std::io::Result<String> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(options.as_bytes());
+    for file in files {
+        hasher.update(file.as_bytes());
+        hasher.update(&std::fs::read(file)?);
+    }
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+impl From<&Args> for ColourMapping {
+    fn from(args: &Args) -> Self {
+        ColourMapping {
+            inexact_fallback_index: args.inexact_fallback_index,
+            luminance_as_index: args.luminance_as_index,
+            preserve_indices:   args.preserve_indices,
+            transparent_index:  args.transparent_index,
+            dither: args.dither,
+            metric: args.color_metric,
+        }
+    }
+}
+
+impl From<&Args> for TrimEdges {
+    fn from(args: &Args) -> Self {
+        if args.no_trim {
+            return TrimEdges::NONE;
+        }
+        TrimEdges {
+            top:    !args.no_trim_top,
+            bottom: !args.no_trim_bottom,
+            left:   !args.no_trim_left,
+            right:  !args.no_trim_right,
+        }
+    }
+}
+
+/// Renders the frames and saves them as images. Unless tiled, each frame is saved to its own
+/// file, named after the GRP type and frame number, or after frame_names if given. Frames whose
+/// numbers are in failed_frames are skipped. With `--continue-on-error`, frames that can't be
+/// saved are added to failed_frames, instead of stopping the others from being saved.
 pub fn render_and_save_frames_to_png(
     frames: &[GrpFrame],
-    palette: &Vec<[u8; 3]>,
+    palette: &[[u8; 3]],
     max_frame_width:  u32,
     max_frame_height: u32,
+    frame_names: Option<&[String]>,
+    failed_frames: &mut Vec<usize>,
     args: &Args,
 ) -> std::io::Result<()> {
     if args.tiled && args.frame_number.is_none() {
         // Tiled mode, so we need to draw all frames into one image.
-        // Attempt to set the number of columns to sqrt(number of frames), so e.g., if there
-        // are 25 frames, we will attempt to create a 5x5 image.
-        // If the user has requested a max_width, then scale down to try to accommodate for that.
-        // So, if there are 25 frames, but the user has requested a max_width that only fits
-        // 3 frames, then the resulting image would be 3x9
-        let mut cols = (frames.len() as f64).sqrt().floor() as u32;
+        // Frames with identical image content are only drawn once if the user has asked for
+        // unique frames only. Each tile keeps track of which frame numbers it represents.
+        let mut tiles: Vec<Vec<usize>> = Vec::with_capacity(frames.len());
+        let mut tile_buffers: Vec<Vec<u8>> = Vec::with_capacity(frames.len());
+        let mut hash_to_tiles: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let buffer = render_for_output(frame, palette, max_frame_width, max_frame_height, args)?;
+            if args.tiled_unique_only {
+                let image_hash = hash_buffer(&buffer);
+                // Tiles with the same hash are compared byte for byte, in case of hash collisions
+                let identical_tile = hash_to_tiles.get(&image_hash)
+                    .and_then(|candidates| candidates.iter().copied().find(|&tile| tile_buffers[tile] == buffer));
+                if let Some(tile) = identical_tile {
+                    debug!("Frame {} is identical to the frame in tile {} — not drawing it", i, tile);
+                    tiles[tile].push(i);
+                    continue;
+                }
+                hash_to_tiles.entry(image_hash).or_default().push(tiles.len());
+            }
+            tiles.push(vec![i]);
+            tile_buffers.push(buffer);
+        }
+
+        let cols = tile_columns(tiles.len(), max_frame_width, args);
         debug!(
             "Saving all frames as one PNG. Columns: {}, max-frame-size: {}x{}, requested max width: {}",
             cols, max_frame_width, max_frame_height, args.max_width.unwrap_or(0),
         );
 
-        // The user has requested a maximum width in pixels,
-        // so we might need to adjust the number of columns down.
-        if let Some(max_w) = args.max_width {
-            if max_w > max_frame_width && cols * max_frame_width > max_w {
-                cols = (max_w as f64 / max_frame_width as f64).floor() as u32;
-                debug!("Adjusted number of columns to: {}", cols);
-            } else if max_w < max_frame_width {
-                cols = 1;
-                debug!(
-                    "The requested max-width, {}, is smaller than one frame. The resulting image \
-                    will have 1 column and it will be {} pixels wide.",
-                    max_w, max_frame_width
+        if let Some(angles) = args.angles {
+            if !frames.len().is_multiple_of(angles as usize) {
+                warn!(
+                    "The number of frames ({}) is not a multiple of the number of angles ({}). \
+                    The last row of the tiled image will not be complete.",
+                    frames.len(), angles,
                 );
             }
         }
 
-        let canvas_width = cols * max_frame_width;
-        let canvas_height = (frames.len() as f64 / cols as f64).ceil() as u32 * max_frame_height;
+        let rows = tile_rows(tiles.len(), cols, max_frame_height, args)?;
+        let canvas_width  = tiled_length(cols, max_frame_width,  args.tile_gap);
+        let canvas_height = tiled_length(rows, max_frame_height, args.tile_gap);
 
-        let pixel_length: usize = if args.use_transparency { 4 } else { 3 }; // RGBA or RGB
-        let mut buffer = vec![0u8; pixel_length * (canvas_width * canvas_height) as usize];
+        let pixel_length = output_pixel_length(args);
+        let mut buffer = match args.background_index {
+            // Gaps and empty tiles are drawn with the background colour too
+            Some(index) if pixel_length == 3 => palette.get(index as usize)
+                .copied()
+                .unwrap_or_default()
+                .repeat((canvas_width * canvas_height) as usize),
+            _ => vec![0u8; pixel_length * (canvas_width * canvas_height) as usize],
+        };
 
-        for (i, frame) in frames.iter().enumerate() {
+        if args.label_frames {
+            for (tile, temp_img) in tile_buffers.iter_mut().enumerate() {
+                label_tile(temp_img, max_frame_width, max_frame_height, tiles[tile][0], palette, args);
+            }
+        }
+
+        for (i, temp_img) in tile_buffers.iter().enumerate() {
             let col = (i as u32) % cols;
             let row = (i as u32) / cols;
-            let base_x = col * max_frame_width;
-            let base_y = row * max_frame_height;
-
-            let temp_img = image_to_buffer(frame, &palette, max_frame_width, max_frame_height, args.use_transparency)?;
+            let base_x = col * (max_frame_width  + args.tile_gap);
+            let base_y = row * (max_frame_height + args.tile_gap);
 
             for y in 0..max_frame_height {
                 for x in 0..max_frame_width {
@@ -66,10 +412,41 @@ pub fn render_and_save_frames_to_png(
             }
         }
 
-        let output_path = format!("{}/all_frames.png", args.output_path.as_deref().unwrap());
-        save_rgb_pixels_to_image_file(buffer, &output_path, args.use_transparency, canvas_width, canvas_height)?;
+        let output_path = format!("{}/all_frames.{}", args.output_path.as_deref().unwrap(), args.output_format.extension());
+        save_rendered(buffer, &output_path, palette, canvas_width, canvas_height, args)?;
         info!("Saved all frames to {}", output_path);
 
+        if args.atlas_format == Some(AtlasFormat::Aseprite) {
+            let atlas = aseprite_atlas(
+                frames, &tiles, cols, max_frame_width, max_frame_height, args.tile_gap,
+                &output_path, canvas_width, canvas_height, pixel_length,
+            );
+            let atlas_path = format!("{}/all_frames.aseprite.json", args.output_path.as_deref().unwrap());
+            write_json_file(&atlas_path, &atlas)?;
+            info!("Saved Aseprite atlas to {}", atlas_path);
+        }
+
+        if args.tiled_unique_only || args.angles.is_some() {
+            let tile_map = TileMap {
+                columns:     cols,
+                tile_width:  max_frame_width,
+                tile_height: max_frame_height,
+                tile_gap:    args.tile_gap,
+                tiles: tiles.into_iter()
+                    .enumerate()
+                    .map(|(tile, frames)| TileEntry {
+                        tile,
+                        frames,
+                        animation: args.angles.map(|_| tile as u32 / cols),
+                        angle:     args.angles.map(|_| tile as u32 % cols),
+                    })
+                    .collect(),
+            };
+            let tile_map_path = format!("{}/all_frames.json", args.output_path.as_deref().unwrap());
+            write_json_file(&tile_map_path, &tile_map)?;
+            info!("Saved mapping of tiles to frames to {}", tile_map_path);
+        }
+
     } else {
         // Non-tiled mode - save each frame as a separate image.
 
@@ -78,36 +455,148 @@ pub fn render_and_save_frames_to_png(
         let mut offset_map: HashMap<u32, Vec<usize>> = HashMap::new();
         // Map: image hash -> list of frame indices
         let mut image_hash_map: HashMap<u64, Vec<usize>> = HashMap::new();
+        // Map: hash of the pixels of the frame itself, regardless of its offsets -> list of frame indices
+        let mut content_hash_map: HashMap<u64, Vec<usize>> = HashMap::new();
+        // Map: image hash -> indices in unique_images, when exporting unique frames only
+        let mut hash_to_unique_images: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut unique_images: Vec<UniqueFrameEntry> = Vec::new();
 
-        for (i, frame) in frames.iter().enumerate() {
-            if args.frame_number == Some(i as u16) {
-                continue;
+        // Render and save the frames in parallel, a chunk at a time, so that the rendered images of
+        // all frames are not kept in memory at once. The bookkeeping is done in frame order, so that
+        // the names of the files and the reported duplicates are the same as when rendering one
+        // frame at a time.
+        let selected_frames: Vec<usize> = (0..frames.len())
+            .filter(|i| frame_is_selected(*i, args) && !failed_frames.contains(i))
+            .collect();
+        let mut unsaved_unique_images: HashSet<usize> = HashSet::new();
+        for chunk in selected_frames.chunks(RENDER_CHUNK_SIZE) {
+            let rendered: Vec<(usize, std::io::Result<Vec<u8>>)> = chunk.par_iter()
+                .map(|&i| (i, render_for_output(&frames[i], palette, max_frame_width, max_frame_height, args)))
+                .collect();
+
+            let mut frames_to_save: Vec<FrameToSave> = Vec::with_capacity(rendered.len());
+            for (i, buffer) in rendered {
+                let frame = &frames[i];
+                let frames_with_offset = offset_map.entry(frame.image_data_offset).or_default();
+                frames_with_offset.push(i);
+                let shares_offset = frames_with_offset.len() > 1;
+
+                let Some(buffer) = skip_if_failed(buffer, i, failed_frames, args)? else {
+                    continue;
+                };
+
+                // Frames sharing image data with an earlier frame are already known to be duplicates,
+                // so there's no need to hash their content
+                if args.report_content_duplicates && !shares_offset {
+                    image_hash_map.entry(hash_buffer(&buffer))
+                        .or_default()
+                        .push(i);
+                }
+                if args.report_content_duplicates {
+                    content_hash_map.entry(hash_frame_content(frame))
+                        .or_default()
+                        .push(i);
+                }
+
+                let unique_hash = args.export_unique_only.then(|| hash_buffer(&buffer));
+                // Images with the same hash are compared byte for byte, by rendering the first frame of
+                // the unique image again, since its buffer may have been saved and dropped already
+                let identical_image = unique_hash
+                    .and_then(|image_hash| hash_to_unique_images.get(&image_hash))
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .find(|&image| {
+                        let first_frame = &frames[unique_images[image].frames[0]];
+                        render_for_output(first_frame, palette, max_frame_width, max_frame_height, args)
+                            .is_ok_and(|unique_buffer| unique_buffer == buffer)
+                    });
+                if let Some(image) = identical_image {
+                    debug!("Frame {} is identical to {} — not saving it", i, unique_images[image].file);
+                    unique_images[image].frames.push(i);
+                    continue;
+                }
+
+                let grp_type = if frame.image_data.grp_type == GrpType::Normal {
+                    ""
+                } else if frame.image_data.grp_type == GrpType::War1 {
+                    &format!("{}_", WAR1_FILENAME)
+                } else {
+                    &format!("{}_", UNCOMPRESSED_FILENAME)
+                };
+
+                let file_name = match frame_names {
+                    // Only the file name is used, so that the image ends up in the output directory
+                    Some(names) => Path::new(Path::new(&names[i]).file_name().unwrap_or_default())
+                        .with_extension(args.output_format.extension())
+                        .display()
+                        .to_string(),
+                    None => format!("{}frame_{:03}.{}", grp_type, i, args.output_format.extension()),
+                };
+                let file_name = if args.offsets_in_filenames {
+                    file_name_with_offsets(&file_name, frame.x_offset, frame.y_offset)
+                } else {
+                    file_name
+                };
+                let unique_image = unique_hash.map(|image_hash| {
+                    hash_to_unique_images.entry(image_hash).or_default().push(unique_images.len());
+                    unique_images.push(UniqueFrameEntry { file: file_name.clone(), frames: vec![i] });
+                    unique_images.len() - 1
+                });
+                frames_to_save.push(FrameToSave { index: i, file_name, buffer, unique_image });
             }
-            offset_map.entry(frame.image_data_offset)
-                .or_default()
-                .push(i);
-
-            let buffer = image_to_buffer(frame, &palette, max_frame_width, max_frame_height, args.use_transparency)?;
-
-            let mut hasher = DefaultHasher::new();
-            buffer.hash(&mut hasher); // Hash the raw RGB(A) buffer
-            let image_hash = hasher.finish();
-
-            image_hash_map.entry(image_hash)
-                .or_default()
-                .push(i);
-
-            let grp_type = if frame.image_data.grp_type == GrpType::Normal {
-                ""
-            } else if frame.image_data.grp_type == GrpType::War1 {
-                &format!("{}_", WAR1_FILENAME)
-            } else {
-                &format!("{}_", UNCOMPRESSED_FILENAME)
-            };
 
-            let output_path = format!("{}/{}frame_{:03}.png", args.output_path.as_deref().unwrap(), grp_type, i);
-            save_rgb_pixels_to_image_file(buffer, &output_path, args.use_transparency, max_frame_width, max_frame_height)?;
-            info!("Saved frame {:2} to {}", i, output_path);
+            // Encode and save the images in parallel, and report the results in frame order
+            let saved_frames: Vec<_> = frames_to_save.into_par_iter()
+                .map(|frame| {
+                    let output_path = format!("{}/{}", args.output_path.as_deref().unwrap(), frame.file_name);
+                    let mirrored = args.export_mirrored.then(|| {
+                        mirror_horizontally(&frame.buffer, max_frame_width, output_pixel_length(args))
+                    });
+                    let saved = save_rendered(frame.buffer, &output_path, palette, max_frame_width, max_frame_height, args);
+                    let saved_mirror = mirrored.filter(|_| saved.is_ok()).map(|mirrored| {
+                        let file_stem = Path::new(&frame.file_name).file_stem().unwrap_or_default().to_string_lossy();
+                        let output_path = format!(
+                            "{}/{}_mirror.{}", args.output_path.as_deref().unwrap(), file_stem, args.output_format.extension(),
+                        );
+                        let saved = save_rendered(mirrored, &output_path, palette, max_frame_width, max_frame_height, args);
+                        (output_path, saved)
+                    });
+                    (frame.index, frame.unique_image, output_path, saved, saved_mirror)
+                })
+                .collect();
+
+            for (i, unique_image, output_path, saved, saved_mirror) in saved_frames {
+                if skip_if_failed(saved, i, failed_frames, args)?.is_none() {
+                    if let Some(image) = unique_image {
+                        unsaved_unique_images.insert(image);
+                    }
+                    continue;
+                }
+                info!("Saved frame {:2} to {}", i, output_path);
+
+                if let Some((output_path, saved)) = saved_mirror {
+                    if skip_if_failed(saved, i, failed_frames, args)?.is_some() {
+                        info!("Saved mirrored frame {:2} to {}", i, output_path);
+                    }
+                }
+            }
+        }
+        // The identical frames were to be represented by the images that couldn't be saved
+        for &image in &unsaved_unique_images {
+            failed_frames.extend(&unique_images[image].frames[1..]);
+        }
+        let unique_images: Vec<UniqueFrameEntry> = unique_images.into_iter()
+            .enumerate()
+            .filter(|(image, _)| !unsaved_unique_images.contains(image))
+            .map(|(_, entry)| entry)
+            .collect();
+
+        if args.export_unique_only {
+            let unique_frames_path = format!("{}/unique_frames.json", args.output_path.as_deref().unwrap());
+            info!("Saved {} unique frames out of {}", unique_images.len(), frames.len());
+            write_json_file(&unique_frames_path, &UniqueFrameMap { images: unique_images })?;
+            info!("Saved mapping of images to frames to {}", unique_frames_path);
         }
 
         let mut offset_duplicates_vec: Vec<(&u32, &Vec<usize>)> = offset_map
@@ -123,7 +612,7 @@ pub fn render_and_save_frames_to_png(
             offset_duplicates.extend(indices);
         }
 
-        for (_, indices) in &image_hash_map {
+        for indices in image_hash_map.values() {
             if indices.len() > 1 {
                 let overlap = indices.iter().any(|idx| offset_duplicates.contains(idx));
                 if !overlap {
@@ -133,24 +622,539 @@ pub fn render_and_save_frames_to_png(
                 }
             }
         }
+
+        for indices in repositioned_duplicates(frames, content_hash_map) {
+            info!("Frames with identical content at different positions: {:?}", indices);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the frame is to be output, given `--frame-number` and `--frame-range`
+fn frame_is_selected(frame_number: usize, args: &Args) -> bool {
+    args.frame_number.is_none_or(|selected| selected as usize == frame_number) &&
+        args.frame_range.is_none_or(|range| range.contains(frame_number))
+}
+
+/// The number of columns of a tiled image with the given number of tiles
+fn tile_columns(tile_count: usize, max_frame_width: u32, args: &Args) -> u32 {
+    // Attempt to set the number of columns to sqrt(number of frames), so e.g., if there
+    // are 25 frames, we will attempt to create a 5x5 image, unless the user has given the
+    // number of columns.
+    // If the user has requested a max_width, then scale down to try to accommodate for that.
+    // So, if there are 25 frames, but the user has requested a max_width that only fits
+    // 3 frames, then the resulting image would be 3x9
+    // If the user has given the number of angles, each row will instead be one animation
+    // frame, with the angles progressing across the columns.
+    let mut cols = args.angles
+        .or(args.columns)
+        .unwrap_or((tile_count as f64).sqrt().floor() as u32);
+
+    // The user has requested a maximum width in pixels,
+    // so we might need to adjust the number of columns down.
+    if let Some(max_w) = args.max_width {
+        if max_w > max_frame_width && tiled_length(cols, max_frame_width, args.tile_gap) > max_w {
+            cols = (max_w + args.tile_gap) / (max_frame_width + args.tile_gap);
+            debug!("Adjusted number of columns to: {}", cols);
+        } else if max_w < max_frame_width {
+            cols = 1;
+            debug!(
+                "The requested max-width, {}, is smaller than one frame. The resulting image \
+                will have 1 column and it will be {} pixels wide.",
+                max_w, max_frame_width
+            );
+        }
+    }
+    cols
+}
+
+/// The number of rows of a tiled image with the given number of tiles and columns. Gives an error
+/// if the rows would make the image higher than the requested max_height.
+fn tile_rows(tile_count: usize, cols: u32, max_frame_height: u32, args: &Args) -> std::io::Result<u32> {
+    let rows = (tile_count as u32).div_ceil(cols.max(1));
+    let height = tiled_length(rows, max_frame_height, args.tile_gap);
+    if let Some(max_h) = args.max_height.filter(|&max_h| height > max_h) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!(
+            "The tiled image would have {} rows of {} pixels, i.e. be {} pixels high, which is more \
+            than the requested max-height of {}. Try giving more columns with '--columns'",
+            rows, max_frame_height, height, max_h,
+        )));
+    }
+    Ok(rows)
+}
+
+/// The width or height in pixels of the given number of tiles next to each other, with gaps of
+/// tile_gap pixels between them
+fn tiled_length(tiles: u32, tile_length: u32, tile_gap: u32) -> u32 {
+    tiles * tile_length + tiles.saturating_sub(1) * tile_gap
+}
+
+/// Glyphs of the digits 0-9 in a 3x5 pixel font. Each byte is one row, with the leftmost pixel in bit 2
+const DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const GLYPH_WIDTH:  u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+/// Pixels between the label and the edges of the tile, and between the digits of the label
+const LABEL_SPACING: u32 = 1;
+
+/// Draws the frame number in the top left corner of a tile rendered by render_for_output. The
+/// label is drawn in the palette colour whose luminance differs the most from the average of the
+/// visible pixels beneath it. Parts of the label outside of the tile are left out.
+fn label_tile(buffer: &mut [u8], tile_width: u32, tile_height: u32, frame_number: usize, palette: &[[u8; 3]], args: &Args) {
+    let pixel_length = output_pixel_length(args);
+    let label = frame_number.to_string();
+    let label_width  = (label.len() as u32 * (GLYPH_WIDTH + LABEL_SPACING)).min(tile_width);
+    let label_height = (GLYPH_HEIGHT + 2 * LABEL_SPACING).min(tile_height);
+
+    let visible_pixels: Vec<u32> = (0..label_height)
+        .flat_map(|y| (0..label_width).map(move |x| (y * tile_width + x) as usize * pixel_length))
+        .filter_map(|index| visible_colour(&buffer[index..index + pixel_length], palette, args))
+        .map(luminance)
+        .collect();
+    let background = visible_pixels.iter().sum::<u32>().checked_div(visible_pixels.len() as u32).unwrap_or(0);
+
+    let Some((index, colour)) = palette.iter()
+        .enumerate()
+        .filter(|(index, _)| !(*index == args.transparent_index as usize && (args.indexed_output || args.use_transparency)))
+        .max_by_key(|(index, colour)| (luminance(**colour).abs_diff(background), std::cmp::Reverse(*index)))
+    else {
+        return;
+    };
+    let pixel = if args.indexed_output {
+        vec![index as u8]
+    } else if args.use_transparency {
+        vec![colour[0], colour[1], colour[2], args.opaque_alpha]
+    } else {
+        colour.to_vec()
+    };
+
+    for (digit, glyph) in label.bytes().map(|digit| DIGIT_GLYPHS[(digit - b'0') as usize]).enumerate() {
+        let glyph_x = LABEL_SPACING + digit as u32 * (GLYPH_WIDTH + LABEL_SPACING);
+        for (row, bits) in glyph.iter().enumerate() {
+            let y = LABEL_SPACING + row as u32;
+            for column in 0..GLYPH_WIDTH {
+                let x = glyph_x + column;
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 && x < tile_width && y < tile_height {
+                    let index = (y * tile_width + x) as usize * pixel_length;
+                    buffer[index..index + pixel_length].copy_from_slice(&pixel);
+                }
+            }
+        }
+    }
+}
+
+/// The colour of a pixel rendered by render_for_output, unless it is transparent
+fn visible_colour(pixel: &[u8], palette: &[[u8; 3]], args: &Args) -> Option<[u8; 3]> {
+    if args.indexed_output {
+        (pixel[0] != args.transparent_index).then(|| palette.get(pixel[0] as usize).copied().unwrap_or_default())
+    } else if args.use_transparency {
+        (pixel[3] != 0).then(|| [pixel[0], pixel[1], pixel[2]])
+    } else {
+        Some([pixel[0], pixel[1], pixel[2]])
+    }
+}
+
+/// Perceived brightness of the colour, from 0 to 255
+fn luminance(colour: [u8; 3]) -> u32 {
+    (299 * colour[0] as u32 + 587 * colour[1] as u32 + 114 * colour[2] as u32) / 1000
+}
+
+/// Renders the frame on a canvas of the given size, as palette indices with `--indexed-output`,
+/// and as RGB or RGBA pixels otherwise.
+fn render_for_output(
+    frame: &GrpFrame,
+    palette: &[[u8; 3]],
+    max_frame_width:  u32,
+    max_frame_height: u32,
+    args: &Args,
+) -> std::io::Result<Vec<u8>> {
+    if args.indexed_output {
+        Ok(draw_on_canvas(frame, max_frame_width as u16, max_frame_height as u16, args.transparent_index))
+    } else {
+        image_to_buffer(frame, palette, max_frame_width, max_frame_height, args.use_transparency, args.opaque_alpha, args.transparent_index, args.background_index)
+    }
+}
+
+/// Number of bytes per pixel in the buffers given by render_for_output
+fn output_pixel_length(args: &Args) -> usize {
+    if args.indexed_output {
+        1 // Palette index
+    } else if args.use_transparency {
+        4 // RGBA
+    } else {
+        3 // RGB
+    }
+}
+
+/// Saves a buffer given by render_for_output as an image
+fn save_rendered(
+    buffer: Vec<u8>,
+    output_path: &str,
+    palette: &[[u8; 3]],
+    width:  u32,
+    height: u32,
+    args: &Args,
+) -> std::io::Result<()> {
+    if args.indexed_output {
+        save_indexed_pixels_to_png(&buffer, output_path, palette, args.opaque_alpha, args.transparent_index, width, height)
+    } else {
+        save_rgb_pixels_to_image_file(buffer, output_path, args.use_transparency, width, height)
+    }
+}
+
+/// Places the palette indices of the frame at its offsets on a canvas of the given size, filled
+/// with the transparent index. Pixels outside the canvas are left out.
+pub(crate) fn draw_on_canvas(frame: &GrpFrame, canvas_width: u16, canvas_height: u16, transparent_index: u8) -> Vec<u8> {
+    let mut canvas = vec![transparent_index; canvas_width as usize * canvas_height as usize];
+    let width = frame.actual_width() as usize;
+    if width == 0 {
+        return canvas;
+    }
+    for (y, row) in frame.image_data.converted_pixels.chunks(width).enumerate() {
+        let canvas_y = frame.y_offset as usize + y;
+        if canvas_y >= canvas_height as usize {
+            break;
+        }
+        for (x, &pixel) in row.iter().enumerate() {
+            let canvas_x = frame.x_offset as usize + x;
+            if canvas_x < canvas_width as usize {
+                canvas[canvas_y * canvas_width as usize + canvas_x] = pixel;
+            }
+        }
+    }
+    canvas
+}
+
+/// Returns the result of converting frame i. With `--continue-on-error`, a failure is instead
+/// logged and the frame number added to failed_frames, and None is returned so that the frame
+/// can be skipped.
+fn skip_if_failed<T>(
+    result: std::io::Result<T>,
+    i: usize,
+    failed_frames: &mut Vec<usize>,
+    args: &Args,
+) -> std::io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if args.continue_on_error => {
+            error!("Failed to convert frame {}: {}. Skipping it", i, e);
+            failed_frames.push(i);
+            Ok(None)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Flips an image buffer with the given width and number of bytes per pixel horizontally, the
+/// way that the game mirrors frames to draw the directions that are not stored in the GRP.
+fn mirror_horizontally(buffer: &[u8], width: u32, pixel_length: usize) -> Vec<u8> {
+    let row_length = width as usize * pixel_length;
+    if row_length == 0 {
+        return buffer.to_vec();
+    }
+    buffer
+        .chunks(row_length)
+        .flat_map(|row| row.chunks(pixel_length).rev().flatten().copied())
+        .collect()
+}
+
+/// Describes where each frame is in a tiled image, in the JSON format of Aseprite.
+/// Each frame is described by the rectangle of its pixels within its tile.
+#[allow(clippy::too_many_arguments)]
+fn aseprite_atlas(
+    frames: &[GrpFrame],
+    tiles: &[Vec<usize>],
+    columns: u32,
+    tile_width:  u32,
+    tile_height: u32,
+    tile_gap:    u32,
+    image_path: &str,
+    image_width:  u32,
+    image_height: u32,
+    pixel_length: usize,
+) -> AsepriteAtlas {
+
+    let mut frame_to_tile = vec![0; frames.len()];
+    for (tile, tile_frames) in tiles.iter().enumerate() {
+        for &frame in tile_frames {
+            frame_to_tile[frame] = tile as u32;
+        }
     }
 
+    let atlas_frames = frames.iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let tile = frame_to_tile[i];
+            // Parts of the frame outside of the tile are not drawn
+            let x = (frame.x_offset as u32).min(tile_width);
+            let y = (frame.y_offset as u32).min(tile_height);
+            let w = (frame.actual_width() as u32).min(tile_width  - x);
+            let h = (frame.height as u32).min(tile_height - y);
+            AsepriteFrame {
+                filename: format!("frame_{:03}", i),
+                frame: AtlasRect {
+                    x: tile % columns * (tile_width  + tile_gap) + x,
+                    y: tile / columns * (tile_height + tile_gap) + y,
+                    w,
+                    h,
+                },
+                rotated: false,
+                trimmed: w != tile_width || h != tile_height,
+                sprite_source_size: AtlasRect { x, y, w, h },
+                source_size: AtlasSize { w: tile_width, h: tile_height },
+                duration: ATLAS_FRAME_DURATION_MS,
+            }
+        })
+        .collect();
+
+    AsepriteAtlas {
+        frames: atlas_frames,
+        meta: AsepriteMeta {
+            app: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            image: std::path::Path::new(image_path).file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            format: match pixel_length {
+                1 => "I8",
+                4 => "RGBA8888",
+                _ => "RGB888",
+            }.to_string(),
+            size: AtlasSize { w: image_width, h: image_height },
+            scale: "1".to_string(),
+        },
+    }
+}
+
+/// Hashes a raw RGB(A) buffer, for finding frames with identical image content
+fn hash_buffer(buffer: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    buffer.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hashes the pixels of a frame together with its size, but not its offsets, for finding frames
+/// with identical content that may be drawn at different positions on the canvas
+fn hash_frame_content(frame: &GrpFrame) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (frame.actual_width(), frame.height).hash(&mut hasher);
+    frame.image_data.converted_pixels.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Given groups of frames with identical content, returns the groups where not all frames are
+/// drawn at the same position, sorted by the lowest frame index in each group.
+fn repositioned_duplicates(frames: &[GrpFrame], content_hash_map: HashMap<u64, Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = content_hash_map
+        .into_values()
+        .filter(|indices| {
+            let positions: HashSet<(u8, u8)> = indices.iter()
+                .map(|&i| (frames[i].x_offset, frames[i].y_offset))
+                .collect();
+            positions.len() > 1
+        })
+        .collect();
+    groups.sort_by_key(|indices| indices[0]);
+    groups
+}
+
+/// Saves the frames as an animated GIF, which is played as many times as the loop count of the
+/// arguments, or forever if it is 0. The global colour table has only the palette entries used
+/// by the frames, with the transparent index first. Every frame is drawn at its offsets on a
+/// canvas of the given size, and is shown for the frame delay, rounded to the hundredths of a
+/// second that GIFs use, or for its duration in the frame durations file of the arguments.
+pub fn save_frames_as_gif(
+    frames: &[GrpFrame],
+    palette: &[[u8; 3]],
+    canvas_width:  u16,
+    canvas_height: u16,
+    output_path: &str,
+    args: &Args,
+) -> std::io::Result<()> {
+    let (loop_count, transparent_index) = (args.loop_count, args.transparent_index);
+    let reduced = reduced_palette(frames, palette, transparent_index);
+    let file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+    let mut encoder = gif::Encoder::new(file, canvas_width, canvas_height, &reduced.colours.concat())
+        .map_err(std::io::Error::other)?;
+    // GIFs without a repeat count are played once, and the count is of the plays after the first
+    match loop_count {
+        0 => encoder.set_repeat(gif::Repeat::Infinite).map_err(std::io::Error::other)?,
+        1 => {},
+        _ => encoder.set_repeat(gif::Repeat::Finite((loop_count - 1).min(u16::MAX as u32) as u16)).map_err(std::io::Error::other)?,
+    }
+
+    let durations = frame_durations(args, frames.len())?;
+    for (frame, duration) in frames.iter().zip(durations) {
+        let delay = ((duration + 5) / 10).min(u16::MAX as u32) as u16;
+        let canvas = reduced.remap(&draw_on_canvas(frame, canvas_width, canvas_height, transparent_index));
+        let gif_frame = gif::Frame {
+            width:  canvas_width,
+            height: canvas_height,
+            delay,
+            // Clear the canvas between frames, so that the previous frame does not show
+            // through the transparent pixels of the next
+            dispose: gif::DisposalMethod::Background,
+            transparent: Some(0),
+            buffer: std::borrow::Cow::Owned(canvas),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&gif_frame).map_err(std::io::Error::other)?;
+    }
     Ok(())
 }
 
+/// Saves the frames as an animated PNG, drawn like the other output images onto a canvas of the
+/// given size at their offsets, with the frame delay, frame durations and loop count of the
+/// arguments.
+pub fn save_frames_as_apng(
+    frames: &[GrpFrame],
+    palette: &[[u8; 3]],
+    canvas_width:  u32,
+    canvas_height: u32,
+    output_path: &str,
+    args: &Args,
+) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+    let mut encoder = png::Encoder::new(file, canvas_width, canvas_height);
+    encoder.set_color(if args.use_transparency { png::ColorType::Rgba } else { png::ColorType::Rgb });
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, args.loop_count).map_err(std::io::Error::other)?;
+    let durations = frame_durations(args, frames.len())?;
+
+    let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+    for (frame, duration) in frames.iter().zip(durations) {
+        // Delays that don't fit in milliseconds are given in seconds instead
+        let (numerator, denominator) = match u16::try_from(duration) {
+            Ok(delay) => (delay, 1000),
+            Err(_)    => ((duration.div_ceil(1000)).min(u16::MAX as u32) as u16, 1),
+        };
+        writer.set_frame_delay(numerator, denominator).map_err(std::io::Error::other)?;
+        let buffer = image_to_buffer(
+            frame, palette, canvas_width, canvas_height, args.use_transparency, args.opaque_alpha, args.transparent_index, args.background_index,
+        )?;
+        writer.write_image_data(&buffer).map_err(std::io::Error::other)?;
+    }
+    writer.finish().map_err(std::io::Error::other)
+}
+
+/// Returns the number of milliseconds that each of the given number of frames is shown, from the
+/// frame durations file of the arguments if given, and otherwise the frame delay.
+fn frame_durations(args: &Args, frame_count: usize) -> std::io::Result<Vec<u32>> {
+    match &args.frame_durations {
+        Some(path) => read_frame_durations(path, frame_count, args.frame_delay),
+        None => Ok(vec![args.frame_delay; frame_count]),
+    }
+}
+
+/// Reads the durations of individual frames of an animation, in milliseconds, from the given
+/// sidecar file, and returns the durations of all frames. Frames not listed in the file get the
+/// default duration. The file is either JSON, with an object mapping frame numbers to durations,
+/// e.g. `{"0": 500, "7": 250}`, or text, with one frame number and duration per line, e.g.
+/// `0 500`. Empty lines and lines starting with '#' are ignored in text files.
+pub fn read_frame_durations(path: &str, frame_count: usize, default_ms: u32) -> std::io::Result<Vec<u32>> {
+    let content = std::fs::read_to_string(path)?;
+    let is_json = Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let listed: Vec<(usize, u32)> = if is_json {
+        let durations: HashMap<usize, u32> = serde_json::from_str(&content)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        durations.into_iter().collect()
+    } else {
+        content.lines()
+            .enumerate()
+            .map(|(line_number, line)| (line_number, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| {
+                let mut fields = line.split_whitespace();
+                match (fields.next().map(str::parse), fields.next().map(str::parse), fields.next()) {
+                    (Some(Ok(frame)), Some(Ok(duration)), None) => Ok((frame, duration)),
+                    _ => Err(std::io::Error::new(ErrorKind::InvalidData, format!(
+                        "Line {} of {} is not a frame number followed by a duration in milliseconds: '{}'",
+                        line_number + 1, path, line,
+                    ))),
+                }
+            })
+            .collect::<std::io::Result<_>>()?
+    };
+
+    let mut durations = vec![default_ms; frame_count];
+    for (frame, duration) in listed {
+        let slot = durations.get_mut(frame).ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, format!(
+            "{} gives a duration for frame {}, but there are only {} frames", path, frame, frame_count,
+        )))?;
+        *slot = duration;
+    }
+    debug!("Read durations of the frames from {}: {:?}", path, durations);
+    Ok(durations)
+}
+
+/// Serialises the given value as pretty-printed JSON to the given path
+fn write_json_file<T: Serialize>(path: &str, value: &T) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)
+}
+
+/// Renders the frame onto a canvas of the given size, at the offsets of the frame. Pixels with
+/// transparent_index are transparent. If use_transparency is false, transparent pixels are drawn
+/// as opaque black instead.
+pub fn render_frame(
+    frame: &GrpFrame,
+    palette: &[[u8; 3]],
+    canvas_width:  u32,
+    canvas_height: u32,
+    use_transparency: bool,
+    transparent_index: u8,
+) -> std::io::Result<RgbaImage> {
+    let mut buffer = image_to_buffer(frame, palette, canvas_width, canvas_height, true, u8::MAX, transparent_index, None)?;
+    if !use_transparency {
+        buffer.chunks_exact_mut(4)
+            .filter(|pixel| pixel[3] == 0)
+            .for_each(|pixel| pixel.copy_from_slice(&[0, 0, 0, u8::MAX]));
+    }
+    RgbaImage::from_raw(canvas_width, canvas_height, buffer).ok_or_else(|| std::io::Error::new(
+        ErrorKind::InvalidData, format!("Could not render the frame on a canvas of size {}x{}", canvas_width, canvas_height),
+    ))
+}
+
+/// Renders the frame onto a canvas of the given size, at the offsets of the frame, with
+/// transparent pixels, those with transparent_index, drawn as black.
+pub fn render_frame_rgb(
+    frame: &GrpFrame,
+    palette: &[[u8; 3]],
+    canvas_width:  u32,
+    canvas_height: u32,
+    transparent_index: u8,
+) -> std::io::Result<RgbImage> {
+    let buffer = render_frame(frame, palette, canvas_width, canvas_height, false, transparent_index)?
+        .pixels()
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    RgbImage::from_raw(canvas_width, canvas_height, buffer).ok_or_else(|| std::io::Error::new(
+        ErrorKind::InvalidData, format!("Could not render the frame on a canvas of size {}x{}", canvas_width, canvas_height),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn image_to_buffer(
     frame: &GrpFrame,
-    palette: &Vec<[u8; 3]>,
+    palette: &[[u8; 3]],
     max_frame_width:  u32,
     max_frame_height: u32,
     use_transparency: bool,
+    opaque_alpha: u8,
+    transparent_index: u8,
+    background_index: Option<u8>,
 ) -> Result<Vec<u8>, std::io::Error> {
 
-    let width = if frame.image_data.grp_type == GrpType::UncompressedExtended {
-        frame.width as u32 + EXTENDED_IMAGE_WIDTH as u32
-    } else {
-        frame.width as u32
-    };
+    let width = frame.actual_width() as u32;
 
     let image = PalettizedImageWithMetadata {
         x_offset: frame.x_offset as u32,
@@ -162,19 +1166,551 @@ fn image_to_buffer(
         palettized_image: frame.image_data.converted_pixels.clone(),
     };
 
-    let buffer = draw_image_to_pixel_buffer(image, &palette, use_transparency)?;
+    let mut buffer = draw_image_to_pixel_buffer(image, palette, use_transparency, transparent_index, background_index)?;
+    if use_transparency && opaque_alpha != u8::MAX {
+        // All non-transparent pixels are drawn fully opaque; make them translucent instead
+        buffer.chunks_exact_mut(4)
+            .filter(|pixel| pixel[3] == u8::MAX)
+            .for_each(|pixel| pixel[3] = opaque_alpha);
+    }
     Ok(buffer)
 }
 
-pub fn png_to_pixels(png_file_name: &str, palette: &Vec<[u8; 3]>) -> std::io::Result<PalettizedImageWithMetadata<u8, u16>> {
+pub fn png_to_pixels(
+    png_file_name: &str,
+    palette: &[[u8; 3]],
+    trim: TrimEdges,
+    mapping: ColourMapping,
+) -> std::io::Result<PalettizedImageWithMetadata<u8, u16>> {
+    debug!(""); // Give some space in the logs
+    let png: PalettizedImageWithMetadata<u8, u16> = read_png(png_file_name, palette, trim, mapping)?;
+    verify_frame_size(&png)?;
+    Ok(png)
+}
+
+/// Reads a PNG like png_to_pixels, but instead of trimming it, cuts out the frame at the offsets
+/// and dimensions of the given frame record.
+pub fn png_to_frame_pixels(
+    png_file_name: &str,
+    palette: &[[u8; 3]],
+    mapping: ColourMapping,
+    record: &FrameRecord,
+) -> std::io::Result<PalettizedImageWithMetadata<u8, u16>> {
     debug!(""); // Give some space in the logs
-    let png: PalettizedImageWithMetadata<u8, u16> = read_png(png_file_name, palette, true)?;
+    let png: PalettizedImageWithMetadata<u32, u32> = read_png(png_file_name, palette, TrimEdges::NONE, mapping)?;
+    let (x, y) = (record.x_offset as u32, record.y_offset as u32);
+    let (width, height) = (record.width as u32, record.height as u32);
+    if x + width > png.width || y + height > png.height {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!(
+            "Frame {} is {}x{} at offset ({}, {}), which does not fit in {} of size {}x{}",
+            record.index, width, height, x, y, png_file_name, png.width, png.height,
+        )));
+    }
+    let pixels = (y..y + height)
+        .flat_map(|row| {
+            let start = (row * png.width + x) as usize;
+            png.palettized_image[start..start + width as usize].iter().copied()
+        })
+        .collect();
 
-    if png.width as u32 > 2 * (u8::MAX as u32) || png.height as u32 > u8::MAX as u32 {
+    let image = PalettizedImageWithMetadata {
+        x_offset: record.x_offset,
+        y_offset: record.y_offset,
+        width:    record.width,
+        height:   record.height as u16,
+        original_width:  png.width  as u16,
+        original_height: png.height as u16,
+        palettized_image: pixels,
+    };
+    verify_frame_size(&image)?;
+    Ok(image)
+}
+
+/// Turns a buffer of RGBA pixels (4 bytes per pixel, row by row) into a palettized image,
+/// using the same colour mapping and trimming of transparent edges as when reading PNGs.
+pub fn rgba_to_pixels(
+    rgba_pixels: &[u8],
+    width:  u32,
+    height: u32,
+    palette: &[[u8; 3]],
+) -> std::io::Result<PalettizedImageWithMetadata<u8, u16>> {
+    let image: PalettizedImageWithMetadata<u8, u16> = palettize_rgba_pixels(rgba_pixels, width, height, true, palette, TrimEdges::ALL, ColourMapping::default())?;
+    verify_frame_size(&image)?;
+    Ok(image)
+}
+
+/// Returns an error if the image is too big to be a frame in a GRP
+fn verify_frame_size(image: &PalettizedImageWithMetadata<u8, u16>) -> std::io::Result<()> {
+    if image.width as u32 > 2 * (u8::MAX as u32) || image.height as u32 > u8::MAX as u32 {
         return Err(std::io::Error::new(ErrorKind::InvalidInput, format!(
             "Width ({}) is above limit of {}, or height ({}) is above limit of {}",
-            png.width, 2 * (u8::MAX as u32), png.height, u8::MAX,
+            image.width, 2 * (u8::MAX as u32), image.height, u8::MAX,
         )))
     }
-    Ok(png)
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn source_manifest_roundtrips_file_names() -> std::io::Result<()> {
+        let grp_path = "test_source_manifest.grp";
+        let png_files = vec!["pngs/walk_00.png".to_string(), "pngs/walk_01.png".to_string()];
+        assert_eq!(read_source_manifest(grp_path)?, None);
+
+        write_source_manifest(grp_path, &png_files)?;
+        let manifest = read_source_manifest(grp_path)?;
+
+        assert_eq!(manifest, Some(SourceManifest {
+            source_files: vec!["walk_00.png".to_string(), "walk_01.png".to_string()],
+        }));
+        std::fs::remove_file(source_manifest_path(grp_path))?;
+        Ok(())
+    }
+
+    #[test]
+    fn resume_manifest_recognises_changed_inputs() -> std::io::Result<()> {
+        let temp_dir = "test_resume_manifest";
+        std::fs::create_dir_all(temp_dir)?;
+        let input  = format!("{}/input.grp", temp_dir);
+        let output = format!("{}/out", temp_dir);
+        std::fs::write(&input, [1, 2, 3])?;
+        std::fs::create_dir_all(&output)?;
+
+        let manifest_path = resume_manifest_path(Path::new(temp_dir));
+        let mut manifest = ResumeManifest::read(&manifest_path)?;
+        assert_eq!(manifest, ResumeManifest::default());
+        let hash = hash_input_files(std::slice::from_ref(&input), "--compression-type normal")?;
+        assert!(!manifest.is_completed(&input, &output, &hash));
+
+        manifest.mark_completed(&input, &output, &hash);
+        manifest.write(&manifest_path)?;
+        let manifest = ResumeManifest::read(&manifest_path)?;
+        assert!(manifest.is_completed(&input, &output, &hash));
+
+        let changed_options = hash_input_files(std::slice::from_ref(&input), "--compression-type optimised")?;
+        assert!(!manifest.is_completed(&input, &output, &changed_options));
+
+        std::fs::write(&input, [1, 2, 4])?;
+        let changed_hash = hash_input_files(std::slice::from_ref(&input), "--compression-type normal")?;
+        assert!(!manifest.is_completed(&input, &output, &changed_hash));
+
+        std::fs::remove_dir_all(temp_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn mirrors_buffers_horizontally() {
+        let buffer = vec![
+            1, 1, 1,  2, 2, 2,  3, 3, 3,
+            4, 4, 4,  5, 5, 5,  6, 6, 6,
+        ];
+        assert_eq!(mirror_horizontally(&buffer, 3, 3), vec![
+            3, 3, 3,  2, 2, 2,  1, 1, 1,
+            6, 6, 6,  5, 5, 5,  4, 4, 4,
+        ]);
+    }
+
+    #[test]
+    fn reads_frame_durations_from_sidecars() -> std::io::Result<()> {
+        let json_path = "test_durations.json";
+        let text_path = "test_durations.txt";
+        std::fs::write(json_path, r#"{"0": 500, "3": 250}"#)?;
+        std::fs::write(text_path, "# frame duration\n0 500\n\n3   250\n")?;
+
+        assert_eq!(read_frame_durations(json_path, 4, 100)?, vec![500, 100, 100, 250]);
+        assert_eq!(read_frame_durations(text_path, 4, 100)?, vec![500, 100, 100, 250]);
+        assert_eq!(read_frame_durations(text_path, 3, 100).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        std::fs::write(text_path, "0 slow\n")?;
+        assert_eq!(read_frame_durations(text_path, 1, 100).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_file(json_path)?;
+        std::fs::remove_file(text_path)?;
+        Ok(())
+    }
+
+    fn frame(x_offset: u8, y_offset: u8, width: u8, height: u8) -> GrpFrame {
+        GrpFrame {
+            x_offset, y_offset, width, height,
+            image_data_offset: 0,
+            image_data: crate::grp::ImageData {
+                row_offsets: vec![],
+                raw_row_data: vec![],
+                converted_pixels: vec![],
+                grp_type: GrpType::Normal,
+            },
+        }
+    }
+
+    #[test]
+    fn draws_frames_at_their_offsets_on_the_full_canvas() -> std::io::Result<()> {
+        let palette = crate::palpng::greyscale_palette()?;
+        let mut bottom_right = frame(2, 1, 1, 2);
+        bottom_right.image_data.converted_pixels = vec![7, 9];
+
+        let buffer = image_to_buffer(&bottom_right, &palette, 3, 3, false, u8::MAX, 0, None)?;
+        assert_eq!(buffer.len(), 3 * 3 * 3);
+        let pixels: Vec<u8> = buffer.chunks(3).map(|rgb| rgb[0]).collect();
+        assert_eq!(pixels, vec![
+            0, 0, 0,
+            0, 0, 7,
+            0, 0, 9,
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn finds_identical_frames_at_different_positions() {
+        let with_pixels = |x_offset, y_offset, pixels: Vec<u8>| {
+            let mut frame = frame(x_offset, y_offset, 2, 1);
+            frame.image_data.converted_pixels = pixels;
+            frame
+        };
+        let frames = vec![
+            with_pixels(0, 0, vec![1, 2]),
+            with_pixels(3, 0, vec![1, 2]), // Same as frame 0, but moved
+            with_pixels(0, 0, vec![2, 1]),
+            with_pixels(0, 0, vec![2, 1]), // Same as frame 2, at the same position
+        ];
+        let mut content_hash_map: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, frame) in frames.iter().enumerate() {
+            content_hash_map.entry(hash_frame_content(frame)).or_default().push(i);
+        }
+
+        assert_eq!(repositioned_duplicates(&frames, content_hash_map), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn renders_frames_to_image_buffers() -> std::io::Result<()> {
+        let palette = crate::palpng::greyscale_palette()?;
+        let mut single_pixel = frame(1, 0, 1, 1);
+        single_pixel.image_data.converted_pixels = vec![7];
+
+        let image = render_frame(&single_pixel, &palette, 2, 1, true, 0)?;
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [7, 7, 7, 255]);
+
+        let image = render_frame(&single_pixel, &palette, 2, 1, false, 0)?;
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+
+        let image = render_frame_rgb(&single_pixel, &palette, 2, 1, 0)?;
+        assert_eq!(image.as_raw(), &vec![0, 0, 0, 7, 7, 7]);
+
+        // With another transparent index, index 0 is an opaque colour and index 7 is transparent
+        let mut with_index_zero = frame(0, 0, 2, 1);
+        with_index_zero.image_data.converted_pixels = vec![0, 7];
+        let image = render_frame(&with_index_zero, &palette, 2, 1, true, 7)?;
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0[3], 0);
+
+        let image = render_frame_rgb(&with_index_zero, &palette, 2, 1, 7)?;
+        assert_eq!(image.as_raw(), &vec![0, 0, 0, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn exports_unique_frames_across_render_chunks() -> std::io::Result<()> {
+        let output_dir = "test_unique_frames_across_chunks";
+        std::fs::create_dir_all(output_dir)?;
+        let palette = crate::palpng::greyscale_palette()?;
+        // The last frame is rendered in another chunk than the first, but has the same pixels
+        let frames: Vec<GrpFrame> = (0..=RENDER_CHUNK_SIZE).map(|i| {
+            let mut single_pixel = frame(0, 0, 1, 1);
+            single_pixel.image_data_offset = i as u32;
+            single_pixel.image_data.converted_pixels = vec![(i % RENDER_CHUNK_SIZE) as u8 + 1];
+            single_pixel
+        }).collect();
+
+        let args = Args::parse_from(["irongrp", "--export-unique-only", "--output-path", output_dir]);
+        let mut failed_frames = vec![];
+        let result = render_and_save_frames_to_png(&frames, &palette, 1, 1, None, &mut failed_frames, &args);
+        let unique_frames = std::fs::read_to_string(format!("{}/unique_frames.json", output_dir));
+        let saved_last_frame = Path::new(&format!("{}/frame_{:03}.png", output_dir, RENDER_CHUNK_SIZE)).exists();
+        std::fs::remove_dir_all(output_dir)?;
+
+        result?;
+        let unique_frames: serde_json::Value = serde_json::from_str(&unique_frames?).map_err(std::io::Error::other)?;
+        let images = unique_frames["images"].as_array().unwrap();
+        assert_eq!(images.len(), RENDER_CHUNK_SIZE);
+        assert_eq!(images[0]["frames"], serde_json::json!([0, RENDER_CHUNK_SIZE]));
+        assert!(!saved_last_frame);
+        assert!(failed_frames.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn saves_frames_as_animated_gif() -> std::io::Result<()> {
+        let path = "test_animation.gif";
+        let palette = crate::palpng::greyscale_palette()?;
+        let mut first  = frame(0, 0, 1, 1);
+        let mut second = frame(1, 1, 1, 1);
+        first .image_data.converted_pixels = vec![7];
+        second.image_data.converted_pixels = vec![9];
+
+        let args = Args::parse_from(["irongrp", "--frame-delay", "123"]);
+        save_frames_as_gif(&[first, second], &palette, 2, 2, path, &args)?;
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(std::fs::File::open(path)?).map_err(std::io::Error::other)?;
+        assert_eq!((decoder.width(), decoder.height()), (2, 2));
+        // Only the transparent index and the two colours used are in the colour table
+        let colour_table = decoder.global_palette().map(|colours| colours[..9].to_vec());
+        assert_eq!(colour_table, Some(vec![0, 0, 0, 7, 7, 7, 9, 9, 9]));
+        let mut canvases = vec![];
+        while let Some(gif_frame) = decoder.read_next_frame().map_err(std::io::Error::other)? {
+            assert_eq!(gif_frame.delay, 12);
+            assert_eq!(gif_frame.transparent, Some(0));
+            canvases.push(gif_frame.buffer.to_vec());
+        }
+        assert_eq!(canvases, vec![vec![1, 0, 0, 0], vec![0, 0, 0, 2]]);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn saves_gif_frames_with_their_own_durations() -> std::io::Result<()> {
+        let (path, durations_path) = ("test_animation_durations.gif", "test_animation_durations.txt");
+        let palette = crate::palpng::greyscale_palette()?;
+        let frames: Vec<GrpFrame> = [3, 5, 200].into_iter().map(|index| {
+            let mut frame = frame(0, 0, 2, 1);
+            frame.image_data.converted_pixels = vec![index, 5];
+            frame
+        }).collect();
+        std::fs::write(durations_path, "1 500\n2 20\n")?;
+
+        // Index 5 is transparent, so it comes first in the colour table
+        let args = Args::parse_from([
+            "irongrp", "--frame-delay", "100", "--frame-durations", durations_path, "--transparent-index", "5",
+        ]);
+        let result = save_frames_as_gif(&frames, &palette, 2, 1, path, &args);
+        std::fs::remove_file(durations_path)?;
+        result?;
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(std::fs::File::open(path)?).map_err(std::io::Error::other)?;
+        let colour_table = decoder.global_palette().map(|colours| colours[..9].to_vec());
+        assert_eq!(colour_table, Some(vec![5, 5, 5, 3, 3, 3, 200, 200, 200]));
+        let mut delays_and_canvases = vec![];
+        while let Some(gif_frame) = decoder.read_next_frame().map_err(std::io::Error::other)? {
+            assert_eq!(gif_frame.transparent, Some(0));
+            delays_and_canvases.push((gif_frame.delay, gif_frame.buffer.to_vec()));
+        }
+        assert_eq!(delays_and_canvases, vec![(10, vec![1, 0]), (50, vec![0, 0]), (2, vec![2, 0])]);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn saves_frames_as_animated_png() -> std::io::Result<()> {
+        let path = "test_animation.png";
+        let palette = crate::palpng::greyscale_palette()?;
+        let mut first  = frame(0, 0, 1, 1);
+        let mut second = frame(1, 0, 1, 1);
+        first .image_data.converted_pixels = vec![7];
+        second.image_data.converted_pixels = vec![9];
+
+        let args = Args::parse_from(["irongrp", "--use-transparency", "--frame-delay", "250", "--loop-count", "3"]);
+        save_frames_as_apng(&[first, second], &palette, 2, 1, path, &args)?;
+
+        let mut reader = png::Decoder::new(std::fs::File::open(path)?).read_info().map_err(std::io::Error::other)?;
+        let animation = reader.info().animation_control.expect("The PNG should be animated");
+        assert_eq!((animation.num_frames, animation.num_plays), (2, 3));
+        let mut canvases = vec![];
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for _ in 0..2 {
+            reader.next_frame(&mut buffer).map_err(std::io::Error::other)?;
+            let control = reader.info().frame_control.expect("Every frame should have a frame control");
+            assert_eq!((control.delay_num, control.delay_den), (250, 1000));
+            canvases.push(buffer.clone());
+        }
+        assert_eq!(canvases, vec![vec![7, 7, 7, 255, 0, 0, 0, 0], vec![0, 0, 0, 0, 9, 9, 9, 255]]);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn frame_metadata_roundtrips_and_records_shared_data() -> std::io::Result<()> {
+        let path = "test_frame_metadata.json";
+        let mut frames = vec![frame(1, 2, 3, 4), frame(0, 0, 8, 8), frame(5, 6, 3, 4)];
+        frames[0].image_data_offset = 30;
+        frames[1].image_data_offset = 50;
+        frames[2].image_data_offset = 30;
+
+        write_frame_metadata(path, &frames)?;
+        let records = read_frame_metadata(path, 3)?;
+        assert_eq!(records[0], FrameRecord {
+            index: 0, x_offset: 1, y_offset: 2, width: 3, height: 4, image_data_offset: 30, reuses_frame: None,
+        });
+        assert_eq!(records[1].reuses_frame, None);
+        assert_eq!(records[2].reuses_frame, Some(0));
+        assert_eq!(read_frame_metadata(path, 2).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reads_and_writes_offsets_in_file_names() {
+        assert_eq!(file_name_with_offsets("frame_003.png", 12, 8), "frame_003_x12_y8.png");
+        assert_eq!(file_name_with_offsets("frame_003_x1_y2.png", 12, 8), "frame_003_x12_y8.png");
+        assert_eq!(offsets_from_file_name("dir/frame_003_x12_y8.png"), Some((12, 8)));
+        assert_eq!(offsets_from_file_name("frame_003.png"), None);
+        assert_eq!(offsets_from_file_name("frame_x300_y8.png"), None);
+        assert_eq!(offsets_from_file_name("frame_x+1_y8.png"), None);
+    }
+
+    #[test]
+    fn lays_out_tiles_in_the_given_number_of_columns() {
+        let columns = |arguments: &[&str]| {
+            let args = Args::parse_from([&["irongrp", "--tiled"], arguments].concat());
+            tile_columns(25, 10, &args)
+        };
+        assert_eq!(columns(&[]), 5);
+        assert_eq!(columns(&["--columns", "8"]), 8);
+        assert_eq!(columns(&["--columns", "8", "--max-width", "65"]), 6);
+        assert_eq!(columns(&["--columns", "8", "--max-width", "5"]), 1);
+        assert_eq!(columns(&["--columns", "3", "--max-width", "65"]), 3);
+        assert!(Args::try_parse_from(["irongrp", "--columns", "0"]).is_err());
+    }
+
+    #[test]
+    fn refuses_tiled_images_higher_than_max_height() {
+        let rows = |cols: u32, arguments: &[&str]| {
+            let args = Args::parse_from([&["irongrp", "--tiled"], arguments].concat());
+            tile_rows(25, cols, 10, &args).ok()
+        };
+        assert_eq!(rows(5, &[]), Some(5));
+        assert_eq!(rows(8, &[]), Some(4));
+        assert_eq!(rows(5, &["--max-height", "50"]), Some(5));
+        assert_eq!(rows(5, &["--max-height", "49"]), None);
+        assert_eq!(rows(25, &["--max-height", "9"]), None);
+    }
+
+    #[test]
+    fn leaves_gaps_between_tiles() {
+        assert_eq!(tiled_length(5, 10, 0), 50);
+        assert_eq!(tiled_length(5, 10, 2), 58);
+        assert_eq!(tiled_length(1, 10, 2), 10);
+        assert_eq!(tiled_length(0, 10, 2), 0);
+
+        let args = Args::parse_from(["irongrp", "--tiled", "--tile-gap", "2", "--max-width", "57"]);
+        assert_eq!(tile_columns(25, 10, &args), 4);
+        let args = Args::parse_from(["irongrp", "--tiled", "--tile-gap", "2", "--max-width", "58"]);
+        assert_eq!(tile_columns(25, 10, &args), 5);
+        let args = Args::parse_from(["irongrp", "--tiled", "--tile-gap", "2", "--max-height", "57"]);
+        assert!(tile_rows(25, 5, 10, &args).is_err());
+
+        let frames = vec![frame(0, 0, 8, 8), frame(0, 0, 8, 8), frame(0, 0, 8, 8)];
+        let tiles = vec![vec![0], vec![1], vec![2]];
+        let atlas = aseprite_atlas(&frames, &tiles, 2, 8, 8, 3, "out/all_frames.png", 19, 19, 4);
+        assert_eq!((atlas.frames[1].frame.x, atlas.frames[1].frame.y), (11, 0));
+        assert_eq!((atlas.frames[2].frame.x, atlas.frames[2].frame.y), (0, 11));
+    }
+
+    #[test]
+    fn labels_tiles_with_frame_numbers_in_contrasting_colour() {
+        let palette = vec![[0, 0, 0], [200, 200, 200], [255, 255, 255], [10, 10, 10]];
+
+        // A black tile is labelled in white
+        let args = Args::parse_from(["irongrp", "--tiled", "--label-frames"]);
+        let mut buffer = vec![0u8; 10 * 8 * 3];
+        label_tile(&mut buffer, 10, 8, 17, &palette, &args);
+        let pixel = |buffer: &[u8], x: usize, y: usize, len: usize| buffer[(y * 10 + x) * len..(y * 10 + x + 1) * len].to_vec();
+        assert_eq!(pixel(&buffer, 0, 0, 3), vec![0, 0, 0]);
+        assert_eq!(pixel(&buffer, 1, 1, 3), vec![0, 0, 0]); // The left column of the '1' is empty
+        assert_eq!(pixel(&buffer, 2, 1, 3), vec![255, 255, 255]);
+        assert_eq!(pixel(&buffer, 5, 1, 3), vec![255, 255, 255]); // The top row of the '7'
+        assert_eq!(pixel(&buffer, 5, 2, 3), vec![0, 0, 0]);
+
+        // A white tile is labelled in black, skipping the transparent index
+        let args = Args::parse_from(["irongrp", "--tiled", "--label-frames", "--indexed-output"]);
+        let mut buffer = vec![2u8; 10 * 8];
+        label_tile(&mut buffer, 10, 8, 7, &palette, &args);
+        assert_eq!(pixel(&buffer, 1, 1, 1), vec![3]);
+        assert_eq!(pixel(&buffer, 1, 2, 1), vec![2]);
+
+        // Labels are opaque on transparent tiles, and clipped to small tiles
+        let args = Args::parse_from(["irongrp", "--tiled", "--label-frames", "--use-transparency"]);
+        let mut buffer = vec![0u8; 3 * 3 * 4];
+        label_tile(&mut buffer, 3, 3, 0, &palette, &args);
+        assert_eq!(buffer[(3 + 1) * 4..(3 + 2) * 4], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn selects_frames_by_number_or_range() {
+        let args = Args::parse_from(["irongrp", "--frame-range", "17..24"]);
+        assert_eq!(args.frame_range, Some(crate::FrameRange { start: 17, end: 24 }));
+        assert!(!frame_is_selected(16, &args));
+        assert!(frame_is_selected(17, &args));
+        assert!(frame_is_selected(24, &args));
+        assert!(!frame_is_selected(25, &args));
+        assert!(args.frame_range.unwrap().check_in_bounds(25).is_ok());
+        assert!(args.frame_range.unwrap().check_in_bounds(24).is_err());
+
+        assert!(Args::try_parse_from(["irongrp", "--frame-range", "24..17"]).is_err());
+        assert!(Args::try_parse_from(["irongrp", "--frame-range", "17"]).is_err());
+
+        let args = Args::parse_from(["irongrp", "--frame-number", "3"]);
+        assert!(frame_is_selected(3, &args));
+        assert!(!frame_is_selected(2, &args));
+        assert!(frame_is_selected(2, &Args::parse_from(["irongrp"])));
+    }
+
+    #[test]
+    fn keeps_the_full_size_of_pngs_with_no_trim() -> std::io::Result<()> {
+        assert_eq!(TrimEdges::from(&Args::parse_from(["irongrp", "--no-trim"])), TrimEdges::NONE);
+        assert!(!TrimEdges::from(&Args::parse_from(["irongrp", "--no-trim-left"])).left);
+
+        let path = "test_no_trim.png";
+        let palette = crate::palpng::greyscale_palette()?;
+        RgbaImage::new(3, 2).save(path).map_err(std::io::Error::other)?;
+        let image = png_to_pixels(path, &palette, TrimEdges::NONE, ColourMapping::default())?;
+        assert_eq!((image.x_offset, image.y_offset, image.width, image.height), (0, 0, 3, 2));
+
+        // The size limits apply to the untrimmed size
+        let mut tall = RgbaImage::new(3, 300);
+        tall.put_pixel(1, 150, image::Rgba([9, 9, 9, 255]));
+        tall.save(path).map_err(std::io::Error::other)?;
+        assert!(png_to_pixels(path, &palette, TrimEdges::ALL,  ColourMapping::default()).is_ok());
+        assert!(png_to_pixels(path, &palette, TrimEdges::NONE, ColourMapping::default()).is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn aseprite_atlas_places_frames_in_their_tiles() {
+        let frames = vec![frame(2, 3, 4, 5), frame(0, 0, 8, 8), frame(2, 3, 4, 5)];
+        // The third frame is a duplicate of the first, and is drawn in the same tile
+        let tiles = vec![vec![0, 2], vec![1]];
+
+        let atlas = aseprite_atlas(&frames, &tiles, 2, 8, 8, 0, "out/all_frames.png", 16, 8, 4);
+
+        assert_eq!(atlas.frames.len(), 3);
+        assert_eq!(atlas.frames[0].filename, "frame_000");
+        assert_eq!((atlas.frames[0].frame.x, atlas.frames[0].frame.y), (2, 3));
+        assert_eq!((atlas.frames[0].sprite_source_size.w, atlas.frames[0].sprite_source_size.h), (4, 5));
+        assert!(atlas.frames[0].trimmed);
+        assert_eq!((atlas.frames[1].frame.x, atlas.frames[1].frame.y), (8, 0));
+        assert!(!atlas.frames[1].trimmed);
+        assert_eq!((atlas.frames[2].frame.x, atlas.frames[2].frame.y), (2, 3));
+        assert_eq!(atlas.meta.image, "all_frames.png");
+        assert_eq!((atlas.meta.size.w, atlas.meta.size.h), (16, 8));
+
+        let json = serde_json::to_value(&atlas).unwrap();
+        assert_eq!(json["frames"][0]["spriteSourceSize"]["x"], 2);
+        assert_eq!(json["frames"][0]["sourceSize"]["w"], 8);
+        assert_eq!(json["frames"][0]["duration"], 100);
+    }
 }
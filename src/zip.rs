@@ -0,0 +1,124 @@
+use log::debug;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+
+const LOCAL_FILE_HEADER_SIGNATURE:   u32 = 0x0403_4B50;
+const CENTRAL_DIRECTORY_SIGNATURE:   u32 = 0x0201_4B50;
+const END_OF_CENTRAL_DIR_SIGNATURE:  u32 = 0x0605_4B50;
+/// Version 1.0 of the ZIP format, which is all that is needed for stored files
+const ZIP_VERSION: u16 = 10;
+/// The files are stored without compression, since PNGs are already compressed
+const COMPRESSION_STORED: u16 = 0;
+/// 1980-01-01 00:00 in MS-DOS format, the earliest time a ZIP archive can hold
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// Writes a ZIP archive to the given path, with the given files stored in it uncompressed.
+/// Each file is given by its name in the archive and its contents.
+pub fn write_zip(path: &str, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let mut zip = BufWriter::new(File::create(path)?);
+    let mut central_directory = Vec::new();
+    let mut offset: u32 = 0;
+
+    for (name, data) in files {
+        let size = u32::try_from(data.len())
+            .map_err(|_| too_big(path))?;
+        let crc = crc32fast::hash(data);
+        let name = name.as_bytes();
+
+        let mut local_header = Vec::with_capacity(30 + name.len());
+        local_header.extend(LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        local_header.extend(entry_fields(crc, size, name.len() as u16));
+        local_header.extend(0u16.to_le_bytes()); // Extra field length
+        local_header.extend(name);
+
+        central_directory.extend(CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        central_directory.extend(ZIP_VERSION.to_le_bytes()); // Version made by
+        central_directory.extend(entry_fields(crc, size, name.len() as u16));
+        central_directory.extend(0u16.to_le_bytes()); // Extra field length
+        central_directory.extend(0u16.to_le_bytes()); // File comment length
+        central_directory.extend(0u16.to_le_bytes()); // Disk number
+        central_directory.extend(0u16.to_le_bytes()); // Internal file attributes
+        central_directory.extend(0u32.to_le_bytes()); // External file attributes
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name);
+
+        zip.write_all(&local_header)?;
+        zip.write_all(data)?;
+        offset = offset
+            .checked_add(local_header.len() as u32)
+            .and_then(|offset| offset.checked_add(size))
+            .ok_or_else(|| too_big(path))?;
+        debug!("Added {} ({} bytes) to {}", String::from_utf8_lossy(name), size, path);
+    }
+
+    let entries = u16::try_from(files.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Too many files to add to {}", path)))?;
+    zip.write_all(&central_directory)?;
+    zip.write_all(&END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes())?;
+    zip.write_all(&0u16.to_le_bytes())?; // Number of this disk
+    zip.write_all(&0u16.to_le_bytes())?; // Disk where the central directory starts
+    zip.write_all(&entries.to_le_bytes())?; // Entries on this disk
+    zip.write_all(&entries.to_le_bytes())?; // Entries in total
+    zip.write_all(&(central_directory.len() as u32).to_le_bytes())?;
+    zip.write_all(&offset.to_le_bytes())?; // Offset of the central directory
+    zip.write_all(&0u16.to_le_bytes())?; // Comment length
+    zip.flush()
+}
+
+/// The fields that the local file header and the central directory have in common
+fn entry_fields(crc: u32, size: u32, name_len: u16) -> Vec<u8> {
+    let mut fields = Vec::with_capacity(24);
+    fields.extend(ZIP_VERSION.to_le_bytes()); // Version needed to extract
+    fields.extend(0u16.to_le_bytes()); // General purpose flags
+    fields.extend(COMPRESSION_STORED.to_le_bytes());
+    fields.extend(DOS_TIME.to_le_bytes());
+    fields.extend(DOS_DATE.to_le_bytes());
+    fields.extend(crc.to_le_bytes());
+    fields.extend(size.to_le_bytes()); // Compressed size
+    fields.extend(size.to_le_bytes()); // Uncompressed size
+    fields.extend(name_len.to_le_bytes());
+    fields
+}
+
+fn too_big(path: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("The files are too big to add to {}", path))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    }
+
+    #[test]
+    fn writes_stored_files_and_central_directory() -> Result<()> {
+        let path = "test_write_zip.zip";
+        let files = vec![
+            ("frame_000.png".to_string(), vec![1, 2, 3]),
+            ("units.pal".to_string(), vec![4, 5]),
+        ];
+        write_zip(path, &files)?;
+        let data = fs::read(path)?;
+        fs::remove_file(path)?;
+
+        // The first file follows directly after its local header
+        assert_eq!(u32_at(&data, 0), LOCAL_FILE_HEADER_SIGNATURE);
+        assert_eq!(u32_at(&data, 14), crc32fast::hash(&[1, 2, 3]));
+        assert_eq!(&data[30..43], b"frame_000.png");
+        assert_eq!(&data[43..46], &[1, 2, 3]);
+
+        // The end of central directory record lists both files
+        let end = data.len() - 22;
+        assert_eq!(u32_at(&data, end), END_OF_CENTRAL_DIR_SIGNATURE);
+        assert_eq!(u16::from_le_bytes([data[end + 10], data[end + 11]]), 2);
+        let central_directory = u32_at(&data, end + 16) as usize;
+        assert_eq!(u32_at(&data, central_directory), CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(central_directory + u32_at(&data, end + 12) as usize, end);
+        Ok(())
+    }
+}